@@ -0,0 +1,81 @@
+//! Counts the heap allocations made per tiny message sent over a multiplexed stream, comparing `send_serialized`, which
+//! serializes messages under `SMALL_MESSAGE_THRESHOLD` bytes onto the stack, against serializing each message onto the heap.
+//! Both sides of the connection run in this process, so the counts include the transport and the receiving end, which
+//! are the same for both runs. Run with `cargo bench --bench small_messages`
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use async_trait::async_trait;
+use serde::Serialize;
+use netbeam::multiplex::OwnedMultiplexedSubscription;
+use netbeam::reliable_conn::{ReliableOrderedStreamToTarget, ReliableOrderedStreamToTargetExt};
+use netbeam::sync::test_utils::{create_streams, open_pair};
+
+const MESSAGES: u64 = 1_000_000;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// `send_serialized` as it was before small messages were serialized onto the stack
+#[async_trait]
+trait SendOnHeap: ReliableOrderedStreamToTarget {
+    async fn send_serialized_on_heap<T: Serialize + Send + Sync>(&self, t: T) -> std::io::Result<()> {
+        let packet = bincode2::serialize(&t).unwrap();
+        self.send_to_peer(&packet).await
+    }
+}
+
+impl<T: ReliableOrderedStreamToTarget> SendOnHeap for T {}
+
+/// Sends `MESSAGES` 8-byte messages from `sender` to `receiver`, returning the allocations made per message
+async fn allocations_per_message(sender: &OwnedMultiplexedSubscription, receiver: &OwnedMultiplexedSubscription, on_heap: bool) -> f64 {
+    let send = async {
+        for value in 0..MESSAGES {
+            if on_heap {
+                sender.send_serialized_on_heap(value).await.unwrap()
+            } else {
+                sender.send_serialized(value).await.unwrap()
+            }
+        }
+    };
+
+    let recv = async {
+        for _ in 0..MESSAGES {
+            let _ = receiver.recv().await.unwrap();
+        }
+    };
+
+    let (before, start) = (ALLOCATIONS.load(Ordering::Relaxed), Instant::now());
+    tokio::join!(send, recv);
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!("{:>16}: {:.3} allocations per message, {:?} in total", if on_heap { "heap" } else { "send_serialized" }, allocations as f64 / MESSAGES as f64, start.elapsed());
+    allocations as f64 / MESSAGES as f64
+}
+
+fn main() {
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    rt.block_on(async {
+        let (server, client) = create_streams().await;
+        let (sender, receiver) = open_pair(&server, &client).await;
+
+        let heap = allocations_per_message(&sender, &receiver, true).await;
+        let stack = allocations_per_message(&sender, &receiver, false).await;
+        println!("send_serialized saves {:.3} allocations per message", heap - stack);
+    });
+}
@@ -0,0 +1,36 @@
+//! Times a burst of `STREAMS` opens on a fresh multiplexed connection, comparing the default subscriber map, which grows
+//! as streams are opened, against one sized up front with `MultiplexConfig::with_subscriber_capacity`. Both sides of the
+//! connection run in this process. Run with `cargo bench --bench subscriber_capacity`
+use std::time::{Duration, Instant};
+
+use netbeam::config::MultiplexConfig;
+use netbeam::sync::test_utils::create_streams_with_config;
+
+const STREAMS: usize = 10_000;
+const RUNS: usize = 2;
+
+/// Opens `STREAMS` substreams on a fresh connection, returning the time taken
+async fn open_burst(config: MultiplexConfig) -> Duration {
+    let (server, client) = create_streams_with_config(config).await;
+    let start = Instant::now();
+    let (sent, received) = tokio::join!(server.initiate_many(STREAMS), client.initiate_many(STREAMS));
+    let elapsed = start.elapsed();
+    assert_eq!(sent.unwrap().len(), STREAMS);
+    assert_eq!(received.unwrap().len(), STREAMS);
+    elapsed
+}
+
+fn main() {
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    rt.block_on(async {
+        for (label, capacity) in [("default", None), ("with capacity", Some(STREAMS))] {
+            let mut best = Duration::MAX;
+            for _ in 0..RUNS {
+                let config = capacity.map(|capacity| MultiplexConfig::default().with_subscriber_capacity(capacity)).unwrap_or_default();
+                best = std::cmp::min(best, open_burst(config).await);
+            }
+
+            println!("{:>13}: opened {} streams in {:?} ({:.0} opens/s)", label, STREAMS, best, STREAMS as f64 / best.as_secs_f64());
+        }
+    });
+}
@@ -0,0 +1,95 @@
+//! Measures how much concurrent opens and closes slow down routing on a multiplexed connection, comparing a subscriber
+//! map with a single lock against one split into `DEFAULT_SUBSCRIBER_SHARDS` shards. Routing tasks stream messages over
+//! long-lived substreams while churn tasks repeatedly open and close substreams on the same connection, on a
+//! multi-threaded runtime so that the tasks really contend for the map. Run with `cargo bench --bench subscriber_contention`
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use tokio::runtime::Handle;
+
+use netbeam::config::{MultiplexConfig, DEFAULT_SUBSCRIBER_SHARDS};
+use netbeam::reliable_conn::{ReliableOrderedStreamToTarget, ReliableOrderedStreamToTargetExt};
+use netbeam::sync::test_utils::{create_streams_with_config, open_pair};
+
+const ROUTERS: usize = 16;
+const MESSAGES_PER_ROUTER: u64 = 20_000;
+const CHURNERS: usize = 4;
+const RUNS: usize = 3;
+
+/// Routes `ROUTERS * MESSAGES_PER_ROUTER` messages while `CHURNERS` tasks open and close substreams, returning the time
+/// taken to route them and the number of substreams opened and closed meanwhile
+async fn route_under_churn(shards: usize) -> (Duration, usize) {
+    let (server, client) = create_streams_with_config(MultiplexConfig::default().with_subscriber_shards(shards)).await;
+
+    let mut pairs = Vec::with_capacity(ROUTERS);
+    for _ in 0..ROUTERS {
+        pairs.push(open_pair(&server, &client).await);
+    }
+
+    // the open futures cannot be proven Send, so each churn task drives its own on a blocking thread instead
+    let stop = Arc::new(AtomicBool::new(false));
+    let churners = (0..CHURNERS).map(|_| {
+        let (server, client, stop, handle) = (server.clone(), client.clone(), stop.clone(), Handle::current());
+        tokio::task::spawn_blocking(move || handle.block_on(async {
+            let mut churned = 0;
+            while !stop.load(Ordering::Relaxed) {
+                let _ = open_pair(&server, &client).await;
+                churned += 1;
+            }
+
+            churned
+        }))
+    }).collect::<Vec<_>>();
+
+    let start = Instant::now();
+    let routers = pairs.into_iter().map(|(sender, receiver)| {
+        tokio::spawn(async move {
+            let send = async {
+                for value in 0..MESSAGES_PER_ROUTER {
+                    sender.send_serialized(value).await.unwrap();
+                }
+            };
+
+            let recv = async {
+                for _ in 0..MESSAGES_PER_ROUTER {
+                    let _ = receiver.recv().await.unwrap();
+                }
+            };
+
+            tokio::join!(send, recv);
+        })
+    }).collect::<Vec<_>>();
+
+    for router in routers {
+        router.await.unwrap();
+    }
+
+    let elapsed = start.elapsed();
+    stop.store(true, Ordering::Relaxed);
+    let mut churned = 0;
+    for churner in churners {
+        churned += churner.await.unwrap();
+    }
+
+    (elapsed, churned)
+}
+
+fn main() {
+    let rt = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+    rt.block_on(async {
+        for shards in [1, DEFAULT_SUBSCRIBER_SHARDS] {
+            let (mut best, mut churned) = (Duration::MAX, 0);
+            for _ in 0..RUNS {
+                let (elapsed, run_churned) = route_under_churn(shards).await;
+                if elapsed < best {
+                    best = elapsed;
+                    churned = run_churned;
+                }
+            }
+
+            let messages = ROUTERS as u64 * MESSAGES_PER_ROUTER;
+            println!("{:>2} shard(s): routed {} messages in {:?} ({:.0} messages/s) while {} substreams were opened and closed", shards, messages, best, messages as f64 / best.as_secs_f64(), churned);
+        }
+    });
+}
@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use netbeam::multiplex::{decode_packet, MultiplexedPacket};
+use netbeam::sync::SymmetricConvID;
+
+// seeded from corpus/decode_packet, which holds one valid frame of each kind. Run with `cargo fuzz run decode_packet`
+fuzz_target!(|frame: &[u8]| {
+    if let Ok(MultiplexedPacket::Batch { frames }) = decode_packet::<SymmetricConvID>(frame) {
+        for frame in frames {
+            let _ = decode_packet::<SymmetricConvID>(&frame);
+        }
+    }
+});
@@ -0,0 +1,399 @@
+use std::collections::VecDeque;
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::reliable_conn::{ReliableOrderedStreamToTarget, ReliableOrderedStreamToTargetExt};
+
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+const DEFAULT_WINDOW: usize = 1024 * 1024;
+
+#[derive(Serialize, Deserialize)]
+enum BulkFrame {
+    /// Opens every run of the receiver: every byte before `offset` is already written to its sink
+    Resume { offset: u64 },
+    Chunk { offset: u64, payload: Vec<u8> },
+    /// Acknowledges every byte before `offset`
+    Ack { offset: u64 },
+    /// Follows the last chunk of a source `len` bytes long
+    Done { len: u64 }
+}
+
+fn invalid_data(message: &'static str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Sends the contents of an [`AsyncRead`] to a [`BulkReceiver`] in chunks, resuming where the receiver left off after a
+/// reconnect. The receiver acknowledges the offset it has written up to, which serves as its checkpoint, and the sender
+/// retains only the chunks sent since the last acknowledgement. If a stream fails part-way through, calling
+/// [`BulkTransfer::run`] and [`BulkReceiver::run`] again on a re-established stream resumes the transfer: the receiver
+/// reports its checkpoint, and the sender retransmits what lies beyond it, then continues reading the source.
+///
+/// Unlike [`crate::exactly_once::ExactlyOnceStream`], the source need not be seekable nor held in memory, since at most
+/// [`BulkTransfer::with_window`] bytes await acknowledgement at once
+pub struct BulkTransfer<R> {
+    source: R,
+    chunk_size: usize,
+    window: usize,
+    // sent but unacknowledged chunks with their offsets, oldest first
+    unacked: VecDeque<(u64, Bytes)>,
+    // read from the source but not yet sent, beginning at `read`. Kept here so that a failed or cancelled run loses none
+    pending: Vec<u8>,
+    // the number of bytes read from the source and sent as chunks
+    read: u64,
+    acked: u64,
+    eof: bool,
+    sent: u64
+}
+
+impl<R: AsyncRead + Unpin + Send> BulkTransfer<R> {
+    pub fn new(source: R) -> Self {
+        Self { source, chunk_size: DEFAULT_CHUNK_SIZE, window: DEFAULT_WINDOW, unacked: VecDeque::new(), pending: Vec::new(), read: 0, acked: 0, eof: false, sent: 0 }
+    }
+
+    /// The largest number of bytes read from the source and sent as one chunk. Defaults to 64 KiB. The window grows to
+    /// fit one chunk if it is smaller
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = std::cmp::max(chunk_size, 1);
+        self.window = std::cmp::max(self.window, self.chunk_size);
+        self
+    }
+
+    /// The number of sent bytes that may await acknowledgement before the source is read further. These are retained
+    /// for retransmission. Defaults to 1 MiB, and is never less than the chunk size
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.window = std::cmp::max(window, self.chunk_size);
+        self
+    }
+
+    /// Transfers the source over `stream` until the receiver has acknowledged all of it, returning its length. If the
+    /// stream fails, call this again with a stream re-established after the reconnect to resume the transfer. Bytes read
+    /// from the source are kept until sent, so a run that fails or is cancelled part-way, even mid-read, loses none of them
+    pub async fn run<S: ReliableOrderedStreamToTarget>(&mut self, stream: &S) -> std::io::Result<u64> {
+        let checkpoint = match stream.recv_serialized::<BulkFrame>().await? {
+            BulkFrame::Resume { offset } => offset,
+            _ => return Err(invalid_data("Expected the receiver's checkpoint"))
+        };
+
+        if checkpoint < self.acked {
+            return Err(invalid_data("The receiver's checkpoint precedes bytes it acknowledged"))
+        }
+
+        // a receiver resumed from an earlier process may hold bytes this sender never sent
+        if checkpoint > self.read {
+            self.skip_to(checkpoint).await?;
+        }
+
+        self.release(checkpoint);
+        // the acknowledgements lost with the previous stream may have covered some of these, but the checkpoint did not
+        for (offset, payload) in self.unacked.clone() {
+            self.send_chunk(stream, offset, &payload).await?;
+        }
+
+        while !self.eof {
+            if self.read - self.acked >= self.window as u64 {
+                self.recv_ack(stream).await?;
+                continue
+            }
+
+            if !self.fill_pending().await? && self.pending.is_empty() {
+                self.eof = true;
+                break
+            }
+
+            let offset = self.read;
+            let payload = Bytes::from(std::mem::take(&mut self.pending));
+            self.read += payload.len() as u64;
+            // retained before sending, so that a chunk lost with a failed stream is retransmitted
+            self.unacked.push_back((offset, payload.clone()));
+            self.send_chunk(stream, offset, &payload).await?;
+        }
+
+        stream.send_serialized(BulkFrame::Done { len: self.read }).await?;
+        while self.acked < self.read {
+            self.recv_ack(stream).await?;
+        }
+
+        Ok(self.read)
+    }
+
+    /// Reads from the source until a whole chunk is pending, returning false if the source ended first. Each read lands in
+    /// `pending` as it completes, so cancelling this loses nothing
+    async fn fill_pending(&mut self) -> std::io::Result<bool> {
+        while self.pending.len() < self.chunk_size {
+            let want = (self.chunk_size - self.pending.len()) as u64;
+            if (&mut self.source).take(want).read_buf(&mut self.pending).await? == 0 {
+                return Ok(false)
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Discards the source up to `offset`, which the receiver already holds, advancing as each read completes
+    async fn skip_to(&mut self, offset: u64) -> std::io::Result<()> {
+        let from_pending = std::cmp::min(offset - self.read, self.pending.len() as u64) as usize;
+        let _ = self.pending.drain(..from_pending);
+        self.read += from_pending as u64;
+
+        let mut scratch = vec![0u8; std::cmp::min(self.chunk_size as u64, offset - self.read) as usize];
+        while self.read < offset {
+            let want = std::cmp::min(scratch.len() as u64, offset - self.read) as usize;
+            match self.source.read(&mut scratch[..want]).await? {
+                0 => return Err(invalid_data("The receiver's checkpoint lies beyond the end of the source")),
+                skipped => self.read += skipped as u64
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn send_chunk<S: ReliableOrderedStreamToTarget>(&mut self, stream: &S, offset: u64, payload: &[u8]) -> std::io::Result<()> {
+        stream.send_serialized(BulkFrame::Chunk { offset, payload: payload.to_vec() }).await?;
+        self.sent += payload.len() as u64;
+        Ok(())
+    }
+
+    async fn recv_ack<S: ReliableOrderedStreamToTarget>(&mut self, stream: &S) -> std::io::Result<()> {
+        match stream.recv_serialized::<BulkFrame>().await? {
+            BulkFrame::Ack { offset } if offset <= self.read => {
+                self.release(offset);
+                Ok(())
+            }
+
+            _ => Err(invalid_data("Expected an acknowledgement"))
+        }
+    }
+
+    /// Discards the retained bytes before `offset`
+    fn release(&mut self, offset: u64) {
+        while let Some((start, payload)) = self.unacked.front_mut() {
+            let end = *start + payload.len() as u64;
+            if end <= offset {
+                self.unacked.pop_front();
+                continue
+            }
+
+            if *start < offset {
+                *payload = payload.slice((offset - *start) as usize..);
+                *start = offset;
+            }
+
+            break
+        }
+
+        self.acked = std::cmp::max(self.acked, offset);
+    }
+
+    /// The number of bytes the receiver has acknowledged
+    pub fn acked(&self) -> u64 {
+        self.acked
+    }
+
+    /// The number of bytes sent over every stream so far, retransmissions included
+    pub fn bytes_sent(&self) -> u64 {
+        self.sent
+    }
+
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+}
+
+/// Writes a transfer from a [`BulkTransfer`] to an [`AsyncWrite`]. Each chunk is written and flushed before it is
+/// acknowledged, so the sender never discards bytes the sink does not hold
+pub struct BulkReceiver<W> {
+    sink: W,
+    checkpoint: u64
+}
+
+impl<W: AsyncWrite + Unpin + Send> BulkReceiver<W> {
+    pub fn new(sink: W) -> Self {
+        Self { sink, checkpoint: 0 }
+    }
+
+    /// Resumes a transfer whose first `checkpoint` bytes the sink already holds, such as a partially written file kept
+    /// from an earlier process
+    pub fn with_checkpoint(mut self, checkpoint: u64) -> Self {
+        self.checkpoint = checkpoint;
+        self
+    }
+
+    /// Receives the transfer over `stream` until the sender reports its end, returning its length. If the stream fails,
+    /// call this again with a stream re-established after the reconnect to resume the transfer
+    pub async fn run<S: ReliableOrderedStreamToTarget>(&mut self, stream: &S) -> std::io::Result<u64> {
+        stream.send_serialized(BulkFrame::Resume { offset: self.checkpoint }).await?;
+
+        loop {
+            match stream.recv_serialized::<BulkFrame>().await? {
+                BulkFrame::Chunk { offset, payload } if offset == self.checkpoint => {
+                    self.sink.write_all(&payload).await?;
+                    self.sink.flush().await?;
+                    self.checkpoint += payload.len() as u64;
+                    stream.send_serialized(BulkFrame::Ack { offset: self.checkpoint }).await?;
+                }
+
+                BulkFrame::Done { len } if len == self.checkpoint => return Ok(len),
+                BulkFrame::Chunk { .. } | BulkFrame::Done { .. } => return Err(invalid_data("The transfer skipped past the checkpoint")),
+                _ => return Err(invalid_data("Expected a chunk"))
+            }
+        }
+    }
+
+    /// The number of bytes written to the sink
+    pub fn checkpoint(&self) -> u64 {
+        self.checkpoint
+    }
+
+    pub fn into_inner(self) -> W {
+        self.sink
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::test_utils::{create_streams, SeverableConn, open_pair};
+    use crate::bulk::{BulkTransfer, BulkReceiver};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+    use tokio::sync::watch;
+    use std::time::Duration;
+
+    /// Severs the transfer's streams once more than `sever_after` bytes are written
+    struct SeveringSink {
+        written: Vec<u8>,
+        sever: Option<(watch::Sender<bool>, usize)>
+    }
+
+    impl AsyncWrite for SeveringSink {
+        fn poll_write(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+            self.written.extend_from_slice(buf);
+            if matches!(&self.sever, Some((_, after)) if self.written.len() > *after) {
+                let _ = self.sever.take().unwrap().0.send(true);
+            }
+
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Yields `data` a few bytes per read, failing once upon reaching `fail_at`
+    struct FlakySource {
+        data: Vec<u8>,
+        pos: usize,
+        fail_at: Option<usize>
+    }
+
+    impl AsyncRead for FlakySource {
+        fn poll_read(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+            if self.fail_at == Some(self.pos) {
+                self.fail_at = None;
+                return Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Read failed")))
+            }
+
+            let end = std::cmp::min(self.pos + std::cmp::min(buf.remaining(), 1000), self.fail_at.unwrap_or(usize::MAX)).min(self.data.len());
+            buf.put_slice(&self.data[self.pos..end]);
+            self.pos = end;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes_from_checkpoint() {
+        const LEN: usize = 4 * 1024 * 1024;
+        const CHUNK: usize = 64 * 1024;
+        let source = (0..LEN).map(|idx| (idx % 251) as u8).collect::<Vec<u8>>();
+
+        let (server, client) = create_streams().await;
+        // the connection drops once the receiver is midway through
+        let (sever, severed) = watch::channel(false);
+        let mut sender = BulkTransfer::new(&source[..]).with_chunk_size(CHUNK).with_window(4 * CHUNK);
+        let mut receiver = BulkReceiver::new(SeveringSink { written: Vec::new(), sever: Some((sever, LEN / 2)) });
+
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let server_sub = SeverableConn { inner: server_sub, severed: severed.clone() };
+        let client_sub = SeverableConn { inner: client_sub, severed };
+        let (sent, received) = tokio::join!(sender.run(&server_sub), receiver.run(&client_sub));
+        assert!(sent.is_err() && received.is_err());
+
+        let checkpoint = receiver.checkpoint();
+        assert!(checkpoint > (LEN / 2) as u64 && checkpoint <= (LEN / 2 + CHUNK) as u64);
+        assert!(sender.acked() <= checkpoint);
+        let sent_before = sender.bytes_sent();
+
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let (sent, received) = tokio::join!(sender.run(&server_sub), receiver.run(&client_sub));
+        assert_eq!(sent.unwrap(), LEN as u64);
+        assert_eq!(received.unwrap(), LEN as u64);
+        assert_eq!(sender.acked(), LEN as u64);
+
+        // nothing before the checkpoint was sent again
+        assert_eq!(sender.bytes_sent() - sent_before, LEN as u64 - checkpoint);
+        assert!(receiver.into_inner().written == source);
+
+        // a sender started afresh skips the bytes a receiver kept from an earlier process
+        let mut sender = BulkTransfer::new(&source[..]).with_chunk_size(CHUNK);
+        let mut receiver = BulkReceiver::new(source[..CHUNK / 2].to_vec()).with_checkpoint((CHUNK / 2) as u64);
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let (sent, received) = tokio::join!(sender.run(&server_sub), receiver.run(&client_sub));
+        assert_eq!(sent.unwrap(), LEN as u64);
+        assert_eq!(received.unwrap(), LEN as u64);
+        assert_eq!(sender.bytes_sent(), (LEN - CHUNK / 2) as u64);
+        assert!(receiver.into_inner() == source);
+    }
+
+    #[tokio::test]
+    async fn window_fits_a_chunk() {
+        const LEN: usize = 16 * 1024;
+        const CHUNK: usize = 1024;
+        let source = (0..LEN).map(|idx| (idx % 251) as u8).collect::<Vec<u8>>();
+
+        let (server, client) = create_streams().await;
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+
+        // an empty window would await an acknowledgement before sending anything
+        let mut sender = BulkTransfer::new(&source[..]).with_chunk_size(CHUNK).with_window(0);
+        assert_eq!(sender.window, CHUNK);
+        let mut receiver = BulkReceiver::new(Vec::new());
+        let (sent, received) = tokio::time::timeout(Duration::from_secs(5), async { tokio::join!(sender.run(&server_sub), receiver.run(&client_sub)) }).await.unwrap();
+        assert_eq!(sent.unwrap(), LEN as u64);
+        assert_eq!(received.unwrap(), LEN as u64);
+        assert!(receiver.into_inner() == source);
+
+        // a chunk larger than the window set beforehand widens it
+        assert_eq!(BulkTransfer::new(&source[..]).with_window(0).with_chunk_size(128 * CHUNK).window, 128 * CHUNK);
+    }
+
+    #[tokio::test]
+    async fn keeps_partial_reads() {
+        const LEN: usize = 256 * 1024;
+        const CHUNK: usize = 64 * 1024;
+        let source = (0..LEN).map(|idx| (idx % 251) as u8).collect::<Vec<u8>>();
+
+        let (server, client) = create_streams().await;
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+
+        // the source fails part-way through the second chunk, after some of it was read
+        let mut sender = BulkTransfer::new(FlakySource { data: source.clone(), pos: 0, fail_at: Some(CHUNK + CHUNK / 2) }).with_chunk_size(CHUNK);
+        let mut receiver = BulkReceiver::new(Vec::new());
+        tokio::select! {
+            sent = sender.run(&server_sub) => assert_eq!(sent.unwrap_err().kind(), std::io::ErrorKind::BrokenPipe),
+            _ = receiver.run(&client_sub) => panic!("The receiver finished early")
+        }
+
+        drop((server_sub, client_sub));
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let (sent, received) = tokio::join!(sender.run(&server_sub), receiver.run(&client_sub));
+        assert_eq!(sent.unwrap(), LEN as u64);
+        assert_eq!(received.unwrap(), LEN as u64);
+        assert!(receiver.into_inner() == source);
+    }
+}
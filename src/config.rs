@@ -0,0 +1,593 @@
+use std::time::Duration;
+use std::future::Future;
+use std::sync::Arc;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use futures::FutureExt;
+use crate::multiplex::{MultiplexedConnKey, OwnedMultiplexedSubscription};
+use crate::sync::accept::{StreamHandlers, StreamHandler, UnroutedPolicy};
+
+/// Configuration applied to a `MultiplexedConn` at construction time. Each `with_*` method consumes and returns self so calls can be chained
+#[derive(Clone, Debug)]
+pub struct MultiplexConfig {
+    pub(crate) backoff: BackoffConfig,
+    pub(crate) early_data: EarlyDataPolicy,
+    pub(crate) subscriber_shards: usize,
+    pub(crate) subscriber_capacity: usize,
+    pub(crate) backpressure: Option<BackpressureConfig>,
+    pub(crate) max_buffered_messages: Option<usize>,
+    pub(crate) max_buffered_bytes: Option<usize>,
+    pub(crate) keepalive: Option<KeepalivePolicy>,
+    pub(crate) processing_threads: Option<usize>,
+    pub(crate) max_outbound_queue: Option<usize>,
+    pub(crate) eviction: EvictionPolicy,
+    pub(crate) runtime: Option<tokio::runtime::Handle>,
+    pub(crate) coalesce_window: Duration,
+    pub(crate) stream_handlers: StreamHandlers,
+    pub(crate) unrouted: UnroutedPolicy,
+    pub(crate) max_opens_per_sec: Option<u32>,
+    pub(crate) stream_pool: Option<StreamPoolConfig>,
+    pub(crate) demux_lag_threshold: Option<Duration>,
+    pub(crate) max_concurrent_opens: Option<usize>,
+    pub(crate) max_recv_frame: Option<usize>,
+    pub(crate) decode_error: DecodeErrorPolicy,
+    pub(crate) transport_restore_grace: Option<Duration>,
+    pub(crate) close_linger: Option<Duration>,
+    pub(crate) fragment_size: Option<usize>,
+    pub(crate) reassembly: ReassemblyConfig,
+    pub(crate) probe_timeout: Duration,
+    #[cfg(feature = "otel")]
+    pub(crate) tracer: Option<crate::telemetry::TracerHandle>
+}
+
+/// The number of independently-locked shards the subscriber map is split into by default
+pub const DEFAULT_SUBSCRIBER_SHARDS: usize = 8;
+
+impl Default for MultiplexConfig {
+    fn default() -> Self {
+        Self {
+            backoff: BackoffConfig::default(),
+            early_data: EarlyDataPolicy::default(),
+            subscriber_shards: DEFAULT_SUBSCRIBER_SHARDS,
+            subscriber_capacity: 0,
+            backpressure: None,
+            max_buffered_messages: None,
+            max_buffered_bytes: None,
+            keepalive: None,
+            processing_threads: None,
+            max_outbound_queue: None,
+            eviction: EvictionPolicy::default(),
+            runtime: None,
+            coalesce_window: Duration::ZERO,
+            stream_handlers: StreamHandlers::default(),
+            unrouted: UnroutedPolicy::default(),
+            max_opens_per_sec: None,
+            stream_pool: None,
+            demux_lag_threshold: None,
+            max_concurrent_opens: None,
+            max_recv_frame: None,
+            decode_error: DecodeErrorPolicy::default(),
+            transport_restore_grace: None,
+            close_linger: None,
+            fragment_size: None,
+            reassembly: ReassemblyConfig::default(),
+            probe_timeout: Duration::from_secs(10),
+            #[cfg(feature = "otel")]
+            tracer: None
+        }
+    }
+}
+
+impl MultiplexConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The configuration a level multiplexed on top of a stream of this level inherits: the payload bounds and the policies
+    /// for its inbound channels. What is tied to this level, such as its handlers, runtime, keepalive, processing pool and
+    /// tracer, is left at the defaults
+    pub(crate) fn inherited(&self) -> Self {
+        Self {
+            early_data: self.early_data,
+            backpressure: self.backpressure,
+            max_buffered_messages: self.max_buffered_messages,
+            max_buffered_bytes: self.max_buffered_bytes,
+            max_outbound_queue: self.max_outbound_queue,
+            eviction: self.eviction,
+            max_recv_frame: self.max_recv_frame,
+            decode_error: self.decode_error,
+            fragment_size: self.fragment_size,
+            reassembly: self.reassembly,
+            ..Self::default()
+        }
+    }
+
+    /// Sets the backoff between retries of a failed keepalive send (see [`Self::with_keepalive_policy`]), and of the
+    /// handshake replay that follows [`crate::multiplex::MultiplexedConn::restore_transport`]
+    pub fn with_backoff(mut self, backoff: BackoffConfig) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn backoff(&self) -> &BackoffConfig {
+        &self.backoff
+    }
+
+    /// Determines what happens to application data that arrives for a stream that has not yet been opened locally
+    pub fn with_early_data_policy(mut self, early_data: EarlyDataPolicy) -> Self {
+        self.early_data = early_data;
+        self
+    }
+
+    pub fn early_data_policy(&self) -> &EarlyDataPolicy {
+        &self.early_data
+    }
+
+    /// Splits the subscriber map into `shards` independently-locked shards (minimum 1). More shards reduce lock
+    /// contention between opens, closes and routing on different ids at the cost of a hash per lookup
+    pub fn with_subscriber_shards(mut self, shards: usize) -> Self {
+        self.subscriber_shards = std::cmp::max(shards, 1);
+        self
+    }
+
+    pub fn subscriber_shards(&self) -> usize {
+        self.subscriber_shards
+    }
+
+    /// Allocates room for `capacity` subscribers up front, spread evenly across the shards, so that a burst of opens on a
+    /// connection expected to carry many streams does not pause to grow the subscriber map. The map still grows past
+    /// `capacity` if needed. None is allocated up front by default
+    pub fn with_subscriber_capacity(mut self, capacity: usize) -> Self {
+        self.subscriber_capacity = capacity;
+        self
+    }
+
+    pub fn subscriber_capacity(&self) -> usize {
+        self.subscriber_capacity
+    }
+
+    /// Enables `StreamEvent::Backpressure` warnings for subscriber queues. Disabled by default
+    pub fn with_backpressure_events(mut self, backpressure: BackpressureConfig) -> Self {
+        self.backpressure = Some(backpressure);
+        self
+    }
+
+    pub fn backpressure(&self) -> Option<&BackpressureConfig> {
+        self.backpressure.as_ref()
+    }
+
+    /// Caps each subscriber's inbound queue at `max` queued-but-unreceived payloads (minimum 1), however small they are.
+    /// A stream whose queue is full when a payload arrives is evicted on both nodes (see
+    /// [`crate::multiplex::StreamEvent::Evicted`]), so that one slow subscriber cannot stall routing for the rest, and so
+    /// that its receives fail rather than silently skip the payload. Individual subscriptions may override the cap (see
+    /// [`crate::multiplex::OwnedMultiplexedSubscription::set_max_buffered_messages`]). Unlimited by default
+    pub fn with_max_buffered_messages(mut self, max: usize) -> Self {
+        self.max_buffered_messages = Some(std::cmp::max(max, 1));
+        self
+    }
+
+    pub fn max_buffered_messages(&self) -> Option<usize> {
+        self.max_buffered_messages
+    }
+
+    /// Caps each stream's outbound queue at `max` sends (minimum 1) that have begun but not yet been written to the
+    /// transport. Further sends on a stream with a full queue wait for room, so that a producer outrunning the transport
+    /// is held back rather than buffering without bound (see [`crate::multiplex::MultiplexedConn::outbound_queue_len`]).
+    /// Unlimited by default
+    pub fn with_max_outbound_queue(mut self, max: usize) -> Self {
+        self.max_outbound_queue = Some(std::cmp::max(max, 1));
+        self
+    }
+
+    pub fn max_outbound_queue(&self) -> Option<usize> {
+        self.max_outbound_queue
+    }
+
+    /// Caps the bytes queued-but-unreceived across every subscriber's inbound queue at `max`. What happens to a payload
+    /// that would exceed it is set by [`Self::with_eviction_policy`]. Payloads handed to a handler are never queued, and
+    /// those arriving ahead of a stream's open are bounded by [`Self::with_early_data_policy`] instead, so neither evicts
+    /// a stream. Unlimited by default
+    pub fn with_max_buffered_bytes(mut self, max: usize) -> Self {
+        self.max_buffered_bytes = Some(max);
+        self
+    }
+
+    pub fn max_buffered_bytes(&self) -> Option<usize> {
+        self.max_buffered_bytes
+    }
+
+    /// Sets which streams are evicted to make room once the limit set by [`Self::with_max_buffered_bytes`] is reached
+    pub fn with_eviction_policy(mut self, policy: EvictionPolicy) -> Self {
+        self.eviction = policy;
+        self
+    }
+
+    pub fn eviction_policy(&self) -> EvictionPolicy {
+        self.eviction
+    }
+
+    /// Runs the handlers registered through [`crate::multiplex::MultiplexedConn::subscribe_with_handler`] on a pool of
+    /// `threads` dedicated threads (minimum 1) rather than inline on the demultiplexer, so that expensive per-payload work
+    /// neither holds up routing nor serializes every stream behind the slowest. Each stream's payloads are still handled
+    /// one at a time, in order. Disabled by default
+    pub fn with_processing_pool(mut self, threads: usize) -> Self {
+        self.processing_threads = Some(threads);
+        self
+    }
+
+    pub fn processing_pool(&self) -> Option<usize> {
+        self.processing_threads
+    }
+
+    /// Proposes a keepalive to the adjacent node: a keepalive frame is sent every `interval`, and the connection is
+    /// considered dead once nothing at all arrives from the adjacent node for `timeout`, at which point the demultiplexer
+    /// ends with a `TimedOut` error. Both nodes apply the stricter of their two proposals once the adjacent node advertises
+    /// support for keepalives, and neither applies one before then (see [`crate::multiplex::MultiplexedConn::keepalive_policy`]).
+    /// Disabled by default
+    pub fn with_keepalive_policy(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.keepalive = Some(KeepalivePolicy { interval, timeout });
+        self
+    }
+
+    pub fn keepalive_policy(&self) -> Option<KeepalivePolicy> {
+        self.keepalive
+    }
+
+    /// Pins the connection's background work (the demultiplexer and the close sequence of dropped subscriptions) to
+    /// `handle`. By default, whichever runtime is ambient at the time the work is spawned is used
+    pub fn with_runtime(mut self, handle: tokio::runtime::Handle) -> Self {
+        self.runtime = Some(handle);
+        self
+    }
+
+    /// Returns the designated runtime if one was set, otherwise the ambient runtime, if any
+    pub fn runtime(&self) -> Option<tokio::runtime::Handle> {
+        self.runtime.clone().or_else(|| tokio::runtime::Handle::try_current().ok())
+    }
+
+    /// Outbound packets, across all substreams, accumulate for up to `window` and are then written to the transport as a
+    /// single frame. This trades up to `window` of added latency for fewer transport writes on chatty connections.
+    /// A zero window (the default) writes every packet immediately
+    pub fn with_coalesce_window(mut self, window: Duration) -> Self {
+        self.coalesce_window = window;
+        self
+    }
+
+    pub fn coalesce_window(&self) -> Duration {
+        self.coalesce_window
+    }
+
+    /// Routes inbound streams that the adjacent node opens via `open_named(label)` to `handler`, which is spawned with the
+    /// accepted subscription. Registering here, rather than on the connection, ensures no inbound open can arrive first.
+    /// `K` must match the id type of the connection this config is used with, otherwise the handler is never invoked
+    pub fn on_stream<K, F, Fut>(mut self, label: impl Into<String>, handler: F) -> Self
+        where
+            K: MultiplexedConnKey + 'static,
+            F: Fn(OwnedMultiplexedSubscription<K>) -> Fut + Send + Sync + 'static,
+            Fut: Future<Output=()> + Send + 'static {
+        let handler: StreamHandler<K> = Arc::new(move |subscription| (handler)(subscription).boxed());
+        self.stream_handlers.insert(label.into(), handler);
+        self
+    }
+
+    /// Determines what happens to inbound named streams without a handler registered via `on_stream`
+    pub fn with_unrouted_policy(mut self, policy: UnroutedPolicy) -> Self {
+        self.unrouted = policy;
+        self
+    }
+
+    pub fn unrouted_policy(&self) -> &UnroutedPolicy {
+        &self.unrouted
+    }
+
+    /// Limits how quickly the adjacent node may open streams, via `open_named` or, on the Initiator, by sending open
+    /// signals, allowing bursts of up to one second's worth. Opens beyond the limit are rejected and counted (see
+    /// `MultiplexedConn::opens_rejected`), failing the open on both nodes. Open signals beyond the limit from an adjacent
+    /// node that does not advertise "open-reject" are counted and dropped unanswered. Unlimited by default
+    pub fn with_max_opens_per_sec(mut self, max_opens_per_sec: u32) -> Self {
+        self.max_opens_per_sec = Some(std::cmp::max(max_opens_per_sec, 1));
+        self
+    }
+
+    pub fn max_opens_per_sec(&self) -> Option<u32> {
+        self.max_opens_per_sec
+    }
+
+    /// Keeps up to `size` recently-closed streams warm for `ttl` after both nodes finish closing them. While warm, the next
+    /// `initiate_subscription` reuses the stream's id without the open handshake, amortizing its cost for bursty
+    /// request/response traffic. Reopens draw from the pool only once the pre-reserved streams are used up.
+    /// Both nodes must enable the pool, and the Receiver's `size` and `ttl` are the ones that apply. Streams that close
+    /// before the adjacent node advertises support for the pool are not kept warm. Disabled by default
+    pub fn with_stream_pool(mut self, size: usize, ttl: Duration) -> Self {
+        self.stream_pool = Some(StreamPoolConfig { size: std::cmp::max(size, 1), ttl });
+        self
+    }
+
+    pub fn stream_pool(&self) -> Option<&StreamPoolConfig> {
+        self.stream_pool.as_ref()
+    }
+
+    /// Emits `StreamEvent::DemuxLag` (and logs a warning) when routing a single inbound frame takes at least `threshold`,
+    /// which indicates that slow handlers are holding up every stream on the connection. Disabled by default
+    pub fn with_demux_lag_threshold(mut self, threshold: Duration) -> Self {
+        self.demux_lag_threshold = Some(threshold);
+        self
+    }
+
+    pub fn demux_lag_threshold(&self) -> Option<Duration> {
+        self.demux_lag_threshold
+    }
+
+    /// Limits the number of `initiate_subscription` calls (including those made by `initiate_many`) in progress at once
+    /// to `max` (minimum 1). Further calls wait for an earlier one to finish, which paces a burst of opens rather than
+    /// queueing all of them against the adjacent node. Unlimited by default
+    pub fn with_max_concurrent_opens(mut self, max: usize) -> Self {
+        self.max_concurrent_opens = Some(std::cmp::max(max, 1));
+        self
+    }
+
+    pub fn max_concurrent_opens(&self) -> Option<usize> {
+        self.max_concurrent_opens
+    }
+
+    /// The largest frame, in bytes, this node accepts from the adjacent node. Larger frames are discarded on arrival.
+    /// The limit is advertised in the capabilities exchange, after which the adjacent node refuses to send frames
+    /// exceeding it, and keeps the batches it coalesces within it. Unlimited by default
+    pub fn with_max_recv_frame(mut self, size: usize) -> Self {
+        self.max_recv_frame = Some(size);
+        self
+    }
+
+    pub fn max_recv_frame(&self) -> Option<usize> {
+        self.max_recv_frame
+    }
+
+    /// Determines what the demultiplexer does with an inbound frame it cannot decode, such as one carrying a frame type
+    /// introduced by a newer protocol version
+    pub fn on_decode_error(mut self, policy: DecodeErrorPolicy) -> Self {
+        self.decode_error = policy;
+        self
+    }
+
+    pub fn decode_error_policy(&self) -> DecodeErrorPolicy {
+        self.decode_error
+    }
+
+    /// When the transport fails or reaches EOF, the demultiplexer waits up to `grace` for
+    /// [`crate::multiplex::MultiplexedConn::restore_transport`] before ending. Disabled by default, in which case the
+    /// demultiplexer ends as soon as the transport does
+    pub fn with_transport_restore_grace(mut self, grace: Duration) -> Self {
+        self.transport_restore_grace = Some(grace);
+        self
+    }
+
+    pub fn transport_restore_grace(&self) -> Option<Duration> {
+        self.transport_restore_grace
+    }
+
+    /// Defers the close sequence of a dropped [`crate::multiplex::OwnedMultiplexedSubscription`] by `linger`. Within that
+    /// window, [`crate::multiplex::MultiplexedConn::reacquire`] returns the stream without either node noticing the drop;
+    /// otherwise, the close sequence runs once the window ends. Disabled by default, in which case dropping a subscription
+    /// closes it right away. Scoped subscriptions always close right away
+    pub fn with_close_linger(mut self, linger: Duration) -> Self {
+        self.close_linger = Some(linger);
+        self
+    }
+
+    pub fn close_linger(&self) -> Option<Duration> {
+        self.close_linger
+    }
+
+    /// Splits application messages larger than `size` bytes into fragments of at most `size` bytes, which the adjacent
+    /// node reassembles before delivery (within the limits of [`Self::with_max_reassembly_bytes`]). Each fragment carries
+    /// a few dozen bytes of framing on top of `size`. Messages are sent whole until the adjacent node advertises support for
+    /// reassembly. Disabled by default
+    pub fn with_fragmentation(mut self, size: usize) -> Self {
+        self.fragment_size = Some(std::cmp::max(size, 1));
+        self
+    }
+
+    pub fn fragment_size(&self) -> Option<usize> {
+        self.fragment_size
+    }
+
+    /// Bounds the memory held by partially-received fragmented messages, both for any single stream and across the
+    /// connection. A fragment that would exceed either limit is discarded along with the rest of its message
+    pub fn with_max_reassembly_bytes(mut self, per_id: usize, total: usize) -> Self {
+        self.reassembly.max_bytes_per_id = per_id;
+        self.reassembly.max_total_bytes = total;
+        self
+    }
+
+    /// Discards a partially-received fragmented message once this long has passed since its first fragment arrived
+    pub fn with_reassembly_timeout(mut self, timeout: Duration) -> Self {
+        self.reassembly.timeout = timeout;
+        self
+    }
+
+    pub fn reassembly(&self) -> &ReassemblyConfig {
+        &self.reassembly
+    }
+
+    /// How long [`crate::multiplex::MultiplexedConn::probe_stream`] waits for the adjacent node's answer before failing
+    /// with `TimedOut`. Defaults to 10 seconds
+    pub fn with_probe_timeout(mut self, timeout: Duration) -> Self {
+        self.probe_timeout = timeout;
+        self
+    }
+
+    pub fn probe_timeout(&self) -> Duration {
+        self.probe_timeout
+    }
+
+    /// Records a span for each stream's open, sends, receives and close, and propagates trace context to the adjacent
+    /// node with each open signal, provided the adjacent node advertises support for it
+    #[cfg(feature = "otel")]
+    pub fn with_tracer(mut self, tracer: Arc<dyn crate::telemetry::StreamTracer>) -> Self {
+        self.tracer = Some(crate::telemetry::TracerHandle(tracer));
+        self
+    }
+}
+
+/// Application data can arrive for an id before the local node has finished opening it (e.g., a fast peer).
+/// By default, such frames are rejected. Buffering holds them until the stream opens, then flushes them in order
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum EarlyDataPolicy {
+    #[default]
+    Reject,
+    Buffer {
+        /// The maximum number of frames held for any single pending id. Frames beyond this are rejected
+        max_frames_per_id: usize,
+        /// The maximum number of distinct pending ids that may hold frames at once
+        max_pending_ids: usize,
+        /// Frames for an id that does not open within this duration are discarded
+        timeout: Duration
+    }
+}
+
+/// See [`MultiplexConfig::on_decode_error`]. Frames are delimited by the transport, so skipping one never desynchronizes
+/// the frames that follow it
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum DecodeErrorPolicy {
+    /// The frame is logged and discarded, and every stream carries on
+    #[default]
+    Skip,
+    /// The demultiplexer ends with an `InvalidData` error (see [`crate::multiplex::MultiplexedConn::demux_result`]),
+    /// ending every stream
+    Abort
+}
+
+/// How often a node sends keepalive frames, and how long it waits to hear anything from the adjacent node before
+/// considering the connection dead (see [`MultiplexConfig::with_keepalive_policy`])
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeepalivePolicy {
+    pub interval: Duration,
+    pub timeout: Duration
+}
+
+impl KeepalivePolicy {
+    /// The stricter of two proposals, taking each setting from whichever proposes the shorter. A node that proposes
+    /// nothing accepts the other's proposal
+    pub fn stricter(a: Option<Self>, b: Option<Self>) -> Option<Self> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(Self { interval: std::cmp::min(a.interval, b.interval), timeout: std::cmp::min(a.timeout, b.timeout) }),
+            (a, b) => a.or(b)
+        }
+    }
+}
+
+/// Selects the streams evicted once the connection's buffered bytes limit is reached (see
+/// [`MultiplexConfig::with_max_buffered_bytes`]). Only streams with payloads queued are evicted, and on both nodes: the
+/// evicted stream's receives fail, and anything sent on it afterwards is discarded
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// No other stream is evicted to make room. The stream a payload that would exceed the limit is bound for is evicted
+    /// instead, so that its receives fail rather than silently skip the payload
+    #[default]
+    None,
+    /// The stream the application least recently sent or received on is evicted first
+    Lru,
+    /// The oldest stream is evicted first
+    Fifo
+}
+
+/// Determines when a subscriber's inbound queue is considered close to full. A warning is emitted once the number of
+/// queued-but-unreceived payloads reaches `threshold * capacity`, and is not repeated until the queue drains back below it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BackpressureConfig {
+    /// The queue depth that corresponds to a fill ratio of 1.0
+    pub capacity: usize,
+    /// The fill ratio (0.0..=1.0) at which the warning is emitted
+    pub threshold: f32
+}
+
+impl BackpressureConfig {
+    pub fn new(capacity: usize, threshold: f32) -> Self {
+        Self { capacity: std::cmp::max(capacity, 1), threshold: threshold.clamp(0.0, 1.0) }
+    }
+}
+
+/// See [`MultiplexConfig::with_stream_pool`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StreamPoolConfig {
+    /// The maximum number of warm streams. Once exceeded, the longest-closed stream is released
+    pub size: usize,
+    /// How long after closing a stream may be reopened without a handshake
+    pub ttl: Duration
+}
+
+/// See [`MultiplexConfig::with_max_reassembly_bytes`] and [`MultiplexConfig::with_reassembly_timeout`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ReassemblyConfig {
+    pub max_bytes_per_id: usize,
+    pub max_total_bytes: usize,
+    pub timeout: Duration
+}
+
+impl Default for ReassemblyConfig {
+    fn default() -> Self {
+        Self { max_bytes_per_id: 16 * 1024 * 1024, max_total_bytes: 64 * 1024 * 1024, timeout: Duration::from_secs(30) }
+    }
+}
+
+/// Exponential backoff with jitter. Delays begin at `base`, double on each attempt, and never exceed `max`.
+/// `jitter` is the fraction (0.0..=1.0) of each delay that gets randomized, which prevents many connections
+/// that failed simultaneously from all retrying at the same instant
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BackoffConfig {
+    pub base: Duration,
+    pub max: Duration,
+    pub jitter: f64
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self { base: Duration::from_millis(100), max: Duration::from_secs(10), jitter: 0.2 }
+    }
+}
+
+impl BackoffConfig {
+    /// Returns the delay to wait before the given (zero-indexed) attempt. The output is always within [base, max]
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let max = std::cmp::max(self.base, self.max);
+        let exp = self.base.checked_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX)).unwrap_or(max);
+        let exp = std::cmp::min(exp, max);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+
+        if jitter == 0.0 {
+            return exp
+        }
+
+        let reduction = exp.mul_f64(rand::thread_rng().gen_range(0.0..=jitter));
+        std::cmp::max(exp.saturating_sub(reduction), self.base)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::BackoffConfig;
+    use std::time::Duration;
+    use std::collections::HashSet;
+
+    #[test]
+    fn backoff_within_bounds() {
+        let backoff = BackoffConfig { base: Duration::from_millis(50), max: Duration::from_secs(2), jitter: 0.5 };
+        let mut seen = HashSet::new();
+
+        for attempt in 0..64 {
+            for _ in 0..20 {
+                let delay = backoff.delay(attempt);
+                assert!(delay >= backoff.base && delay <= backoff.max, "{:?} out of bounds", delay);
+                seen.insert(delay);
+            }
+        }
+
+        // jitter must cause the delays to vary
+        assert!(seen.len() > 64);
+
+        let no_jitter = BackoffConfig { jitter: 0.0, ..backoff };
+        assert_eq!(no_jitter.delay(0), Duration::from_millis(50));
+        assert_eq!(no_jitter.delay(3), Duration::from_millis(400));
+        assert_eq!(no_jitter.delay(40), Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+use tokio::sync::{Mutex, Notify};
+
+use crate::reliable_conn::{ReliableOrderedStreamToTarget, ReliableOrderedStreamToTargetExt};
+
+#[derive(Serialize, Deserialize)]
+enum SequencedFrame {
+    Data { seq: u64, payload: Vec<u8> },
+    /// Acknowledges every message up to and including `seq`
+    Ack { seq: u64 }
+}
+
+/// Sequence state carried across reconnects
+#[derive(Default)]
+struct SequenceState {
+    next_seq: AtomicU64,
+    // the sequence number of the last message read from the stream. Messages are only ever read in sequence
+    received: AtomicU64,
+    // the highest sequence number delivered to the application, and so acknowledged
+    delivered: AtomicU64,
+    // messages read from the stream, but not yet delivered to the application
+    inbound: parking_lot::Mutex<VecDeque<(u64, Vec<u8>)>>,
+    inbound_arrived: Notify,
+    unacked: parking_lot::Mutex<VecDeque<(u64, Vec<u8>)>>,
+    // the highest sequence number acknowledged by the adjacent node
+    acked: AtomicU64,
+    ack_received: Notify
+}
+
+/// A stream with exactly-once delivery across reconnects. Every message carries a sequence number and is retained by
+/// the sender until the adjacent node acknowledges it. After a reconnect, [`ExactlyOnceStream::resume`] retransmits
+/// whatever is unacknowledged, and the receiving side suppresses the messages it already delivered.
+///
+/// Both nodes must wrap their end of the stream. This is heavier than sending on the stream directly: each message is
+/// copied into the retransmit buffer and answered by an acknowledgement once the adjacent node's application receives
+/// it. Acknowledgements are consumed while receiving, so a node that only sends retains its messages until it next
+/// calls `recv` or [`ExactlyOnceStream::send_acked`]
+pub struct ExactlyOnceStream<S> {
+    inner: S,
+    state: SequenceState,
+    // held from assigning a sequence number until the message is written, so that sequence numbers reach the wire in order
+    send_lock: Mutex<()>,
+    // held while delivering a message, so that each is acknowledged before the next is delivered
+    recv_lock: Mutex<()>
+}
+
+impl<S: ReliableOrderedStreamToTarget> ExactlyOnceStream<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner, state: SequenceState::default(), send_lock: Mutex::new(()), recv_lock: Mutex::new(()) }
+    }
+
+    /// Continues this stream on `inner`, typically a stream re-established after a reconnect, then retransmits every
+    /// unacknowledged message on it
+    pub async fn resume<T: ReliableOrderedStreamToTarget>(self, inner: T) -> std::io::Result<ExactlyOnceStream<T>> {
+        let this = ExactlyOnceStream { inner, state: self.state, send_lock: Mutex::new(()), recv_lock: Mutex::new(()) };
+        this.resend_unacked().await?;
+        Ok(this)
+    }
+
+    /// Sends `input`, then waits until the adjacent node's application has received it. Messages that arrive in the
+    /// meantime are held for `recv`. Dropping the returned future stops the wait, not the delivery
+    pub async fn send_acked(&self, input: &[u8]) -> std::io::Result<()> {
+        let seq = self.send_sequenced(input).await?;
+
+        loop {
+            // registered before checking, so that an acknowledgement read concurrently by `recv` is not missed
+            let ack_received = self.state.ack_received.notified();
+            if self.state.acked.load(Ordering::Relaxed) >= seq {
+                return Ok(())
+            }
+
+            tokio::select! {
+                _ = ack_received => {}
+                res = self.read_frame() => res?
+            }
+        }
+    }
+
+    async fn send_sequenced(&self, input: &[u8]) -> std::io::Result<u64> {
+        let _guard = self.send_lock.lock().await;
+        let seq = 1 + self.state.next_seq.fetch_add(1, Ordering::Relaxed);
+        self.state.unacked.lock().push_back((seq, input.to_vec()));
+        self.inner.send_serialized(SequencedFrame::Data { seq, payload: input.to_vec() }).await?;
+        Ok(seq)
+    }
+
+    /// Reads one frame from the stream. New messages are queued for delivery rather than returned, so that a read
+    /// cancelled part-way through never loses one. Cancelling is otherwise harmless, since any acknowledgement it was
+    /// sending is sent again
+    async fn read_frame(&self) -> std::io::Result<()> {
+        match self.inner.recv_serialized::<SequencedFrame>().await? {
+            SequencedFrame::Data { seq, payload } => {
+                let received = match self.state.received.compare_exchange(seq.wrapping_sub(1), seq, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        self.state.inbound.lock().push_back((seq, payload));
+                        self.state.inbound_arrived.notify_waiters();
+                        return Ok(())
+                    }
+
+                    Err(received) => received
+                };
+
+                // a message ahead of one that was lost is dropped too. The sender retains both until acknowledged, and
+                // retransmits them in order
+                if seq > received {
+                    log::trace!("Dropped sequence {}, which arrived ahead of sequence {}", seq, received + 1);
+                    return Ok(())
+                }
+
+                log::trace!("Suppressed duplicate delivery of sequence {}", seq);
+                // acknowledged again, since the original acknowledgement may be what was lost
+                let delivered = self.state.delivered.load(Ordering::Relaxed);
+                self.inner.send_serialized(SequencedFrame::Ack { seq: delivered }).await
+            }
+
+            SequencedFrame::Ack { seq } => {
+                self.state.unacked.lock().retain(|(unacked_seq, _)| *unacked_seq > seq);
+                self.state.acked.fetch_max(seq, Ordering::Relaxed);
+                self.state.ack_received.notify_waiters();
+                Ok(())
+            }
+        }
+    }
+
+    /// Retransmits every unacknowledged message, oldest first. Useful when the underlying stream survived a disruption,
+    /// such as a transport replacement, that may have lost messages in flight
+    pub async fn resend_unacked(&self) -> std::io::Result<()> {
+        let _guard = self.send_lock.lock().await;
+        let unacked = self.state.unacked.lock().clone();
+
+        for (seq, payload) in unacked {
+            self.inner.send_serialized(SequencedFrame::Data { seq, payload }).await?;
+        }
+
+        Ok(())
+    }
+
+    /// The number of sent messages not yet acknowledged by the adjacent node
+    pub fn unacked(&self) -> usize {
+        self.state.unacked.lock().len()
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<S: ReliableOrderedStreamToTarget> ReliableOrderedStreamToTarget for ExactlyOnceStream<S> {
+    async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+        self.send_sequenced(input).await.map(|_| ())
+    }
+
+    async fn recv(&self) -> std::io::Result<Bytes> {
+        let _guard = self.recv_lock.lock().await;
+        loop {
+            // registered before checking, so that a message read concurrently by `send_acked` is not missed
+            let inbound_arrived = self.state.inbound_arrived.notified();
+            let next = self.state.inbound.lock().front().map(|(seq, _)| *seq);
+            if let Some(seq) = next {
+                // the message stays queued until acknowledged, so a cancelled acknowledgement does not lose it
+                self.inner.send_serialized(SequencedFrame::Ack { seq }).await?;
+                self.state.delivered.fetch_max(seq, Ordering::Relaxed);
+                // the guard excludes any other delivery, so the front is still this message
+                let (_, payload) = self.state.inbound.lock().pop_front().unwrap();
+                return Ok(Bytes::from(payload))
+            }
+
+            tokio::select! {
+                _ = inbound_arrived => {}
+                res = self.read_frame() => res?
+            }
+        }
+    }
+
+    fn peer_alive(&self) -> Option<bool> {
+        self.inner.peer_alive()
+    }
+
+    async fn shutdown(&self) -> std::io::Result<()> {
+        self.inner.shutdown().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::test_utils::{create_channel_streams_with_config, open_pair};
+    use crate::config::MultiplexConfig;
+    use crate::exactly_once::ExactlyOnceStream;
+    use crate::reliable_conn::{ReliableOrderedStreamToTarget, ReliableOrderedStreamToTargetExt};
+    use std::time::Duration;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[tokio::test(start_paused = true)]
+    async fn no_duplicates_across_reconnect() {
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default()).await;
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let (sender, receiver) = (ExactlyOnceStream::new(server_sub), ExactlyOnceStream::new(client_sub));
+
+        sender.send_serialized(0u64).await.unwrap();
+        sender.send_serialized(1u64).await.unwrap();
+        assert_eq!(receiver.recv_serialized::<u64>().await.unwrap(), 0);
+        assert_eq!(receiver.recv_serialized::<u64>().await.unwrap(), 1);
+
+        // the connection drops before the sender learns that anything was delivered
+        assert_eq!(sender.unacked(), 2);
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let sender = sender.resume(server_sub).await.unwrap();
+        let receiver = receiver.resume(client_sub).await.unwrap();
+
+        sender.send_serialized(2u64).await.unwrap();
+        assert_eq!(receiver.recv_serialized::<u64>().await.unwrap(), 2);
+        assert!(tokio::time::timeout(Duration::from_millis(100), receiver.recv_serialized::<u64>()).await.is_err());
+
+        // the acknowledgements for the retransmitted and new messages release them all
+        assert!(tokio::time::timeout(Duration::from_millis(100), sender.recv()).await.is_err());
+        assert_eq!(sender.unacked(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gap_before_retransmission() {
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default()).await;
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let (sender, receiver) = (ExactlyOnceStream::new(server_sub), ExactlyOnceStream::new(client_sub));
+
+        // the first message is lost in flight, and the second is sent before the first is retransmitted
+        sender.state.unacked.lock().push_back((1, b"lost".to_vec()));
+        sender.state.next_seq.store(1, Ordering::Relaxed);
+        sender.send_to_peer(b"later").await.unwrap();
+        sender.resend_unacked().await.unwrap();
+
+        // the second is dropped on arrival ahead of the first, then delivered in order once retransmitted
+        assert_eq!(&receiver.recv().await.unwrap()[..], b"lost");
+        assert_eq!(&receiver.recv().await.unwrap()[..], b"later");
+        assert!(tokio::time::timeout(Duration::from_millis(100), receiver.recv()).await.is_err());
+
+        assert!(tokio::time::timeout(Duration::from_millis(100), sender.recv()).await.is_err());
+        assert_eq!(sender.unacked(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn send_acked() {
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default()).await;
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let (sender, receiver) = (ExactlyOnceStream::new(server_sub), ExactlyOnceStream::new(client_sub));
+
+        // the adjacent node has not received it, so the send is never confirmed
+        assert!(tokio::time::timeout(Duration::from_millis(100), sender.send_acked(b"first")).await.is_err());
+
+        let acked = AtomicBool::new(false);
+        let send = async {
+            sender.send_acked(b"second").await.unwrap();
+            acked.store(true, Ordering::SeqCst);
+        };
+
+        let consume = async {
+            // receiving the first message does not confirm the second
+            assert_eq!(&receiver.recv().await.unwrap()[..], b"first");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert!(!acked.load(Ordering::SeqCst));
+            assert_eq!(&receiver.recv().await.unwrap()[..], b"second");
+        };
+
+        tokio::join!(send, consume);
+        assert!(acked.load(Ordering::SeqCst));
+        assert_eq!(sender.unacked(), 0);
+
+        // messages that arrive while awaiting confirmation are held for recv
+        let (res, _) = tokio::join!(sender.send_acked(b"third"), async {
+            receiver.send_to_peer(b"reply").await.unwrap();
+            receiver.recv().await.unwrap()
+        });
+        res.unwrap();
+        assert_eq!(&sender.recv().await.unwrap()[..], b"reply");
+    }
+}
@@ -4,5 +4,14 @@
 pub mod sync;
 pub mod reliable_conn;
 pub mod time_tracker;
+pub mod config;
+pub mod codec;
+pub mod exactly_once;
+pub mod bulk;
+pub mod negotiation;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+#[cfg(feature = "ws")]
+pub mod ws;
 
 pub mod multiplex;
\ No newline at end of file
@@ -1,21 +1,37 @@
 
-use crate::reliable_conn::{ReliableOrderedStreamToTarget, ReliableOrderedStreamToTargetExt};
-use std::sync::Arc;
+use crate::reliable_conn::{ReliableOrderedStreamToTarget, ReliableOrderedStreamToTargetExt, Direction, serialize_to_buffer};
+use std::sync::{Arc, Weak};
 use tokio::sync::Mutex;
 use parking_lot::RwLock;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use crate::sync::{SymmetricConvID, RelativeNodeType};
 use tokio::sync::mpsc::{UnboundedSender, unbounded_channel, UnboundedReceiver};
-use std::hash::Hash;
-use crate::sync::subscription::{SubscriptionBiStream, close_sequence_for_multiplexed_bistream, Subscribable};
+use std::hash::{Hash, BuildHasher};
+use std::collections::hash_map::RandomState;
+use crate::sync::subscription::{SubscriptionBiStream, close_sequence_for_multiplexed_bistream, Subscribable, NestedLevelAttacher};
 use serde::{Serialize, Deserialize};
 use serde::de::DeserializeOwned;
 use std::fmt::Debug;
 use anyhow::Error;
 use crate::sync::network_application::{PostActionChannel, PreActionChannel, INITIAL_CAPACITY};
 use std::ops::Deref;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::net::SocketAddr;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, AtomicUsize, AtomicBool, Ordering};
 use async_trait::async_trait;
+use bytes::Bytes;
+use crate::config::{MultiplexConfig, EarlyDataPolicy, BackpressureConfig, EvictionPolicy};
+use crate::sync::accept::{OpenRegistry, Origin};
+use crate::sync::pool::StreamPool;
+use crate::sync::processing::ProcessingPool;
+use crate::sync::reassembly::Reassembly;
+use crate::sync::priority::{Priority, ScheduledConn};
+use crate::sync::swap::SwappableConn;
+use crate::sync::coalesce::CoalescingConn;
+use crate::sync::frame_limit::FrameLimitedConn;
+use crate::sync::partition::Partitions;
+use crate::negotiation::{PeerState, Capabilities};
+use tokio::time::Instant;
 
 pub trait MultiplexedConnKey: Debug + Eq + Hash + Copy + Send + Sync + Serialize + DeserializeOwned + IDGen<Self> {}
 impl<T: Debug + Eq + Hash + Copy + Send + Sync + Serialize + DeserializeOwned + IDGen<Self>> MultiplexedConnKey for T {}
@@ -25,6 +41,26 @@ pub trait IDGen<Key: MultiplexedConnKey> {
     fn generate_container() -> Self::Container;
     fn generate_next(container: &Self::Container) -> Self;
     fn get_proposed_next(container: &Self::Container) -> Key;
+
+    /// Ids set aside for control use. They are skipped when generating ids for streams, and application data scoped to
+    /// them is never delivered
+    fn reserved_ids() -> Vec<Key> {
+        Vec::new()
+    }
+
+    fn is_reserved(_id: &Key) -> bool {
+        false
+    }
+}
+
+/// Generates the next id from `container`, skipping reserved ids
+pub(crate) fn next_unreserved<K: MultiplexedConnKey>(container: &K::Container) -> K {
+    loop {
+        let id = K::generate_next(container);
+        if !K::is_reserved(&id) {
+            return id
+        }
+    }
 }
 
 impl IDGen<SymmetricConvID> for SymmetricConvID {
@@ -35,251 +71,3725 @@ impl IDGen<SymmetricConvID> for SymmetricConvID {
     }
 
     fn generate_next(container: &Self::Container) -> SymmetricConvID {
-        (1 + container.fetch_add(1, Ordering::Relaxed)).into()
+        container.fetch_add(1, Ordering::Relaxed).wrapping_add(1).into()
     }
 
     fn get_proposed_next(container: &Self::Container) -> SymmetricConvID {
-        (1 + container.load(Ordering::Relaxed)).into()
+        container.load(Ordering::Relaxed).wrapping_add(1).into()
+    }
+
+    /// Id 0 is never generated, except on wrapping around
+    fn reserved_ids() -> Vec<SymmetricConvID> {
+        vec![SymmetricConvID::from(0)]
+    }
+
+    fn is_reserved(id: &SymmetricConvID) -> bool {
+        u64::from(*id) == 0
     }
 }
 
+/// A cheaply-cloneable handle to a multiplexed connection. Every subscription holds its own handle, so the connection,
+/// along with the demultiplexer started by `register`, stays alive for as long as any handle or subscription does, even
+/// once the handle originally returned is dropped. Once the last of them drops (and any close handshakes they started
+/// have finished), the demultiplexer tells the adjacent node's level goodbye and stops, and `demux_result` resolves with
+/// Ok on both nodes.
+///
+/// Nested levels therefore tear down innermost first, whatever order their handles are dropped in: a level multiplexed
+/// over a substream holds that substream, which holds the level beneath. Only once the inner level has stopped is its
+/// substream dropped and closed, which in turn lets the level beneath stop. On the adjacent node, each level observes the
+/// goodbye as a clean close, and its substream closes once the local handles to that level drop too
 pub struct MultiplexedConn<K: MultiplexedConnKey = SymmetricConvID> {
-    inner: Arc<MultiplexedConnInner<K>>
+    pub(crate) inner: Arc<MultiplexedConnInner<K>>
 }
 
 pub struct MultiplexedConnInner<K: MultiplexedConnKey> {
     pub(crate) conn: Arc<dyn ReliableOrderedStreamToTarget>,
-    subscribers: RwLock<HashMap<K, MemorySender>>,
+    pub(crate) transport: Arc<SwappableConn>,
+    subscribers: SubscriberMap<K>,
     pre_open_container: PreActionChannel<K>,
     post_close_container: PostActionChannel<K>,
-    id_gen: K::Container,
+    pub(crate) id_gen: K::Container,
     current_latest_subscribed: K::Container,
-    node_type: RelativeNodeType
+    pub(crate) node_type: RelativeNodeType,
+    config: MultiplexConfig,
+    nested_levels: parking_lot::Mutex<Vec<(K, Weak<dyn TopologySource>)>>,
+    // the id of the substream this level runs on, keyed by the parent level's key type
+    parent_id: parking_lot::Mutex<Option<Box<dyn std::any::Any + Send + Sync>>>,
+    // the local and peer addrs of the connection beneath this level, once known
+    addrs: parking_lot::Mutex<Option<(SocketAddr, SocketAddr)>>,
+    early_data: parking_lot::Mutex<HashMap<K, EarlyFrames>>,
+    pub(crate) pending_probes: parking_lot::Mutex<HashMap<u64, tokio::sync::oneshot::Sender<bool>>>,
+    pub(crate) probe_nonce: AtomicU64,
+    pub(crate) demux_status: Arc<tokio::sync::watch::Sender<Option<DemuxOutcome>>>,
+    pub(crate) demux_status_rx: tokio::sync::watch::Receiver<Option<DemuxOutcome>>,
+    // never written to. Its receivers observe the drop of the last handle as the channel closing
+    handles_alive: tokio::sync::watch::Sender<()>,
+    // a partition keeps its parent, and with it the parent's demultiplexer, alive
+    _parent: Option<MultiplexedConn<K>>,
+    event_listeners: parking_lot::Mutex<Vec<UnboundedSender<StreamEvent<K>>>>,
+    pub(crate) opens: OpenRegistry,
+    pub(crate) peer: PeerState,
+    // usize::MAX until the adjacent node advertises a limit
+    pub(crate) peer_max_frame: Arc<AtomicUsize>,
+    demux_lag: DemuxLag,
+    pub(crate) pool: StreamPool<K>,
+    processing: Option<ProcessingPool>,
+    pub(crate) partitions: Partitions<K>,
+    pub(crate) handshakes: HandshakeLog<K>,
+    pub(crate) reassembly: Reassembly<K>,
+    #[cfg(feature = "otel")]
+    pub(crate) traces: crate::telemetry::StreamTraces<K>,
+    // set once the adjacent node advertises that it decodes compact frames
+    pub(crate) compact_frames: AtomicBool,
+    // the streams registered through `subscribe_polled`
+    polled: parking_lot::Mutex<PolledStreams<K>>,
+    // resolved as each dropped scoped subscription finishes closing
+    scoped_closes: parking_lot::Mutex<Vec<tokio::sync::oneshot::Receiver<()>>>,
+    // ids ever opened through `scoped_subscription`, which the generators skip from then on
+    scoped_ids: parking_lot::Mutex<HashSet<K>>,
+    // dropped subscriptions whose close is deferred by the configured linger
+    lingering: parking_lot::Mutex<HashMap<K, Lingering>>,
+    // streams whose adjacent end closed while the local end remained open
+    peer_closed: parking_lot::Mutex<HashSet<K>>,
+    deadline: parking_lot::Mutex<Option<Instant>>,
+    // the origin of streams opened through `open_named`, which may differ from the default for the node's role
+    pub(crate) origins: parking_lot::Mutex<HashMap<K, Origin>>,
+    // the bytes delivered to the inbound queues of all streams, but not yet received
+    buffered_bytes: Arc<AtomicUsize>
 }
 
-pub struct MemorySender {
-    tx: UnboundedSender<Vec<u8>>,
-    pre_reserved_rx: Option<UnboundedReceiver<Vec<u8>>>
+/// The most times [`MultiplexedConn::replay_handshakes`] is attempted before its error is returned
+const MAX_REPLAY_ATTEMPTS: u32 = 5;
+
+/// A dropped subscription awaiting its deferred close. Removing the entry cancels the close
+struct Lingering {
+    receiver: InboundReceiver,
+    priority: Priority,
+    // dropping the sender wakes the close timer, which then ends
+    _cancel: tokio::sync::oneshot::Sender<()>
 }
 
-impl Deref for MemorySender {
-    type Target = UnboundedSender<Vec<u8>>;
+/// Handshake signals awaiting the adjacent node, which [`MultiplexedConn::restore_transport`] replays. Only the Receiver
+/// sends signals that await a reply; the Initiator answers replayed signals idempotently
+pub(crate) struct HandshakeLog<K: MultiplexedConnKey> {
+    // Receiver: open signals awaiting the Initiator's echo
+    pub(crate) unechoed_opens: parking_lot::Mutex<HashSet<K>>,
+    // Receiver: close signals awaiting the Initiator's answer
+    pub(crate) unanswered_closes: parking_lot::Mutex<HashSet<K>>,
+    // Initiator: open signals received but not yet acted upon
+    pub(crate) queued_opens: parking_lot::Mutex<HashSet<K>>,
+    // open signals rejected for exceeding the Initiator's open rate, with the reason, until the local open consumes them
+    pub(crate) rejected_opens: parking_lot::Mutex<HashMap<K, String>>
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.tx
-    }
+/// The handshakes a node is waiting on the adjacent node to complete (see [`MultiplexedConn::pending_handshakes`])
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(bound="")]
+pub struct PendingHandshakes<K: MultiplexedConnKey> {
+    /// Streams this node has announced but the adjacent node has not yet confirmed opening
+    pub opens: Vec<K>,
+    /// Streams this node has closed but the adjacent node has not yet confirmed closing
+    pub closes: Vec<K>
 }
 
-impl<K: MultiplexedConnKey> Deref for MultiplexedConn<K> {
-    type Target = MultiplexedConnInner<K>;
+/// The streams registered through [`MultiplexedConn::subscribe_polled`], along with the order their payloads arrived in
+struct PolledStreams<K: MultiplexedConnKey> {
+    receivers: HashMap<K, InboundReceiver>,
+    // one entry per payload delivered, naming the stream it waits in
+    arrivals: std::collections::VecDeque<K>
+}
 
-    fn deref(&self) -> &Self::Target {
-        self.inner.deref()
+/// What [`MultiplexedConn::assemble`] builds a connection from, besides its node type and configuration
+pub(crate) struct AssembleParts<K: MultiplexedConnKey> {
+    /// The wrapped transport that packets are sent on
+    pub(crate) conn: Arc<dyn ReliableOrderedStreamToTarget>,
+    pub(crate) peer_max_frame: Arc<AtomicUsize>,
+    /// The replaceable transport beneath `conn`
+    pub(crate) transport: Arc<SwappableConn>,
+    pub(crate) id_gen: K::Container,
+    pub(crate) current_latest_subscribed: K::Container,
+    pub(crate) demux_status: Arc<tokio::sync::watch::Sender<Option<DemuxOutcome>>>,
+    pub(crate) demux_status_rx: tokio::sync::watch::Receiver<Option<DemuxOutcome>>,
+    /// A connection kept alive for as long as this one is
+    pub(crate) parent: Option<MultiplexedConn<K>>
+}
+
+/// Notable occurrences on a connection, delivered to every receiver obtained via [`MultiplexedConn::events`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum StreamEvent<K: MultiplexedConnKey> {
+    /// The inbound queue of stream `id` crossed the configured fill threshold (see [`BackpressureConfig`]).
+    /// Emitted once per crossing; the stream must drain back below the threshold before it can be emitted again
+    Backpressure { id: K, fill_ratio: f32 },
+    /// Routing an inbound frame took at least the configured threshold (see [`MultiplexConfig::with_demux_lag_threshold`]).
+    /// Emitted once per crossing; frames must be routed below the threshold again before it can be emitted again
+    DemuxLag { lag: std::time::Duration },
+    /// Stream `id` was evicted by either node to stay within its inbound queue limits (see
+    /// [`MultiplexConfig::with_eviction_policy`] and [`MultiplexConfig::with_max_buffered_messages`]). Its receives fail from then on, and anything sent on it is discarded
+    Evicted { id: K },
+    /// A stream completed the close handshake. Emitted once per close
+    Closed { summary: StreamSummary<K> },
+    /// The adjacent node went away without a clean goodbye, leaving behind the last will it registered through
+    /// [`MultiplexedConn::set_last_will`]
+    LastWill { name: String, payload: Vec<u8> }
+}
+
+/// The lifetime of a closed stream and the traffic it carried (see [`StreamEvent::Closed`])
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StreamSummary<K: MultiplexedConnKey> {
+    pub id: K,
+    /// When the open handshake completed. Pre-reserved and pooled streams, which need no handshake, open as they are
+    /// handed out
+    pub opened: Instant,
+    pub closed: Instant,
+    /// The traffic counted over the stream's lifetime, whether or not its counters were taken in the meantime (see
+    /// [`MultiplexedConn::take_stats`])
+    pub stats: SubStats
+}
+
+impl<K: MultiplexedConnKey> StreamSummary<K> {
+    pub fn duration(&self) -> std::time::Duration {
+        self.closed.saturating_duration_since(self.opened)
     }
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(bound="")]
-pub(crate) enum MultiplexedPacket<K: MultiplexedConnKey> {
-    ApplicationLayer { id: K, payload: Vec<u8> },
-    PostDrop { id: K },
-    PreCreate { id: K },
-    Greeter
+/// How the demultiplexer task terminated. io::Error is not Clone, so the kind and message are kept for re-creation
+pub(crate) type DemuxOutcome = Result<(), (std::io::ErrorKind, String)>;
+
+/// Routes inbound payloads by id. The map is split into independently-locked shards keyed by a hash of the id, so that
+/// opens, closes and routing on different ids do not all contend for a single lock
+pub struct SubscriberMap<K: MultiplexedConnKey> {
+    shards: Vec<RwLock<HashMap<K, MemorySender>>>,
+    hasher: RandomState
 }
 
-impl<K: MultiplexedConnKey> MultiplexedConn<K> {
-    pub fn new<T: ReliableOrderedStreamToTarget + 'static>(node_type: RelativeNodeType, conn: T) -> Self {
-        let id_gen = K::generate_container();
-        let ids: Vec<K> = (0..INITIAL_CAPACITY).into_iter().map(|_| <K as IDGen<K>>::generate_next(&id_gen)).collect();
-        // the next two lines will generate a list of pre-established bistreams
-        let post_close_container = PostActionChannel::new(&ids);
-        let mut subscribers = HashMap::new();
+impl<K: MultiplexedConnKey> SubscriberMap<K> {
+    /// Allocates room for `capacity` entries in all, divided evenly between the shards
+    pub(crate) fn with_capacity(shard_count: usize, capacity: usize) -> Self {
+        let shard_count = std::cmp::max(shard_count, 1);
+        let per_shard = capacity.div_ceil(shard_count);
+        let shards = (0..shard_count).map(|_| RwLock::new(HashMap::with_capacity(per_shard))).collect();
+        Self { shards, hasher: RandomState::new() }
+    }
 
-        for id in ids {
-            let (tx, pre_reserved_rx) = tokio::sync::mpsc::unbounded_channel();
-            subscribers.insert(id, MemorySender { tx, pre_reserved_rx: Some(pre_reserved_rx) });
+    /// Returns the shard responsible for `id`. Holding this shard's lock excludes any concurrent open, close or routing for `id`
+    pub fn shard(&self, id: &K) -> &RwLock<HashMap<K, MemorySender>> {
+        if self.shards.len() == 1 {
+            return &self.shards[0]
         }
 
-        let current_latest_subscribed = K::generate_container();
+        &self.shards[(self.hasher.hash_one(id) % self.shards.len() as u64) as usize]
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// The total number of entries across all shards. Each shard is locked in turn, so this is not an atomic snapshot
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the ids of streams that have been opened locally (that is, excluding pre-reserved ids not yet handed out)
+    pub fn open_ids(&self) -> Vec<K> {
+        let mut open_ids = Vec::new();
+        self.for_each(|id, sender| {
+            if sender.pre_reserved_rx.is_none() {
+                open_ids.push(*id)
+            }
+        });
+
+        open_ids
+    }
+
+    /// Visits every entry, locking one shard at a time
+    pub fn for_each<F: FnMut(&K, &MemorySender)>(&self, mut f: F) {
+        for shard in &self.shards {
+            for (id, sender) in shard.read().iter() {
+                (f)(id, sender)
+            }
+        }
+    }
 
-        Self { inner: Arc::new(MultiplexedConnInner { conn: Arc::new(conn), subscribers: RwLock::new(subscribers), pre_open_container: PreActionChannel::new(), post_close_container, current_latest_subscribed, id_gen, node_type })}
+    /// Like [`Self::for_each`], but holds every shard's read lock for the whole visit, so that it observes a single
+    /// point in time. Opens, closes and routing wait for the visit to finish, so `f` should be brief
+    pub fn for_each_consistent<F: FnMut(&K, &MemorySender)>(&self, mut f: F) {
+        let shards = self.shards.iter().map(|shard| shard.read()).collect::<Vec<_>>();
+        for (id, sender) in shards.iter().flat_map(|shard| shard.iter()) {
+            (f)(id, sender)
+        }
     }
 }
 
-impl<K: MultiplexedConnKey> Clone for MultiplexedConn<K> {
-    fn clone(&self) -> Self {
-        Self { inner: self.inner.clone() }
+/// Frames received for a not-yet-opened id, along with when the first of them arrived
+type EarlyFrames = (Instant, Vec<Vec<u8>>);
+
+/// The time the demultiplexer spent routing the most recent inbound frame
+#[derive(Default)]
+struct DemuxLag {
+    last_nanos: AtomicU64,
+    above_threshold: AtomicBool
+}
+
+/// A callback invoked inline by the demultiplexer for each inbound payload on a stream
+pub type PayloadHandler = Arc<dyn Fn(Bytes) + Send + Sync>;
+
+pub struct MemorySender {
+    tx: UnboundedSender<Vec<u8>>,
+    pre_reserved_rx: Option<InboundReceiver>,
+    handler: Option<PayloadHandler>,
+    depth: Arc<QueueDepth>,
+    pub(crate) priority: Priority,
+    stats: StreamCounters,
+    outbound: Arc<OutboundQueue>,
+    taps: parking_lot::Mutex<Vec<UnboundedSender<(Direction, Bytes)>>>
+}
+
+/// The sends on one stream that have begun but not yet been written to the transport
+#[derive(Default)]
+struct OutboundQueue {
+    len: AtomicUsize,
+    slot_freed: tokio::sync::Notify
+}
+
+/// Admits sends into a stream's outbound queue (see [`MultiplexConfig::with_max_outbound_queue`])
+pub struct OutboundGate {
+    queue: Arc<OutboundQueue>,
+    max: Option<usize>
+}
+
+impl OutboundGate {
+    /// Waits for room in the queue, then holds a place in it until the returned slot drops
+    pub(crate) async fn enter(self) -> OutboundSlot {
+        loop {
+            let len = self.queue.len.load(Ordering::Relaxed);
+            if matches!(self.max, Some(max) if len >= max) {
+                self.queue.slot_freed.notified().await;
+                continue
+            }
+
+            if self.queue.len.compare_exchange(len, len + 1, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+                return OutboundSlot { queue: self.queue }
+            }
+        }
     }
 }
 
-pub struct MultiplexedSubscription<'a, K: MultiplexedConnKey = SymmetricConvID> {
-    ptr: &'a MultiplexedConn<K>,
-    receiver: Option<Mutex<UnboundedReceiver<Vec<u8>>>>,
-    id: K
+/// A send's place in its stream's outbound queue
+pub(crate) struct OutboundSlot {
+    queue: Arc<OutboundQueue>
 }
 
-impl<K: MultiplexedConnKey> SubscriptionBiStream for MultiplexedSubscription<'_, K> {
-    type Conn = Arc<dyn ReliableOrderedStreamToTarget + 'static>;
-    type ID = K;
+impl Drop for OutboundSlot {
+    fn drop(&mut self) {
+        self.queue.len.fetch_sub(1, Ordering::Relaxed);
+        // a permit is stored if no send is waiting yet, so the wakeup cannot be lost
+        self.queue.slot_freed.notify_one()
+    }
+}
 
-    fn conn(&self) -> &Self::Conn {
-        &self.ptr.conn
+/// The traffic on one stream, as counted since it opened or since the counters were last taken (see [`MultiplexedConn::stats`])
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SubStats {
+    pub messages_sent: u64,
+    /// Counted before any framing
+    pub bytes_sent: u64,
+    /// Counted as each message is routed to the stream, whether or not it has been received yet
+    pub messages_received: u64,
+    pub bytes_received: u64
+}
+
+/// A stream's traffic, counted both over its lifetime and over the current interval (see [`MultiplexedConn::take_stats`])
+#[derive(Default)]
+struct StreamCounters {
+    interval: TrafficCounters,
+    lifetime: TrafficCounters
+}
+
+impl StreamCounters {
+    fn record_sent(&self, len: usize) {
+        self.interval.record_sent(len);
+        self.lifetime.record_sent(len);
     }
 
-    fn receiver(&self) -> &Mutex<UnboundedReceiver<Vec<u8>>> {
-        self.receiver.as_ref().unwrap()
+    fn record_received(&self, len: usize) {
+        self.interval.record_received(len);
+        self.lifetime.record_received(len);
     }
 
-    fn id(&self) -> Self::ID {
-        self.id
+    fn snapshot(&self) -> SubStats {
+        self.interval.snapshot()
     }
 
-    fn node_type(&self) -> RelativeNodeType {
-        self.ptr.node_type
+    fn take(&self) -> SubStats {
+        self.interval.take()
+    }
+
+    fn lifetime(&self) -> SubStats {
+        self.lifetime.snapshot()
     }
 }
 
-impl<K: MultiplexedConnKey> From<MultiplexedSubscription<'_, K>> for OwnedMultiplexedSubscription<K> {
-    fn from(mut this: MultiplexedSubscription<'_, K>) -> Self {
-        let ret = Self {
-            ptr: this.ptr.clone(),
-            receiver: this.receiver.take().unwrap(),
-            id: this.id
-        };
+#[derive(Default)]
+struct TrafficCounters {
+    messages_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    messages_received: AtomicU64,
+    bytes_received: AtomicU64
+}
 
-        // prevent destructor from running
-        std::mem::forget(this);
-        ret
+impl TrafficCounters {
+    fn record_sent(&self, len: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(len as u64, Ordering::Relaxed);
     }
-}
 
-pub struct OwnedMultiplexedSubscription<K: MultiplexedConnKey + 'static = SymmetricConvID> {
-    ptr: MultiplexedConn<K>,
-    receiver: Mutex<UnboundedReceiver<Vec<u8>>>,
-    id: K
+    fn record_received(&self, len: usize) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> SubStats {
+        SubStats {
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed)
+        }
+    }
+
+    fn take(&self) -> SubStats {
+        SubStats {
+            messages_sent: self.messages_sent.swap(0, Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.swap(0, Ordering::Relaxed),
+            messages_received: self.messages_received.swap(0, Ordering::Relaxed),
+            bytes_received: self.bytes_received.swap(0, Ordering::Relaxed)
+        }
+    }
 }
 
-impl<K: MultiplexedConnKey> SubscriptionBiStream for OwnedMultiplexedSubscription<K> {
-    type Conn = Arc<dyn ReliableOrderedStreamToTarget + 'static>;
-    type ID = K;
+impl MemorySender {
+    /// Pushes the payload into the registered handler if one exists, otherwise into the per-id channel
+    pub(crate) fn deliver(&self, payload: Vec<u8>) -> Result<(), anyhow::Error> {
+        self.stats.record_received(payload.len());
+        self.mirror(Direction::Received, &payload);
+        if let Some(handler) = self.handler.as_ref() {
+            (handler)(Bytes::from(payload));
+            Ok(())
+        } else {
+            if self.depth.evicted.load(Ordering::Relaxed) {
+                return Err(anyhow::Error::msg("The stream was evicted. Discarding payload"))
+            }
 
-    fn conn(&self) -> &Self::Conn {
-        &self.ptr.conn
+            // counted before the send, so that a receiver woken by it never takes the payload off the counts first
+            let len = payload.len();
+            self.depth.queued.fetch_add(1, Ordering::Relaxed);
+            self.depth.queued_bytes.fetch_add(len, Ordering::Relaxed);
+            self.depth.total_bytes.fetch_add(len, Ordering::Relaxed);
+
+            if let Err(err) = self.tx.send(payload) {
+                self.depth.queued.fetch_sub(1, Ordering::Relaxed);
+                self.depth.queued_bytes.fetch_sub(len, Ordering::Relaxed);
+                self.depth.total_bytes.fetch_sub(len, Ordering::Relaxed);
+                return Err(err.into())
+            }
+
+            Ok(())
+        }
     }
 
-    fn receiver(&self) -> &Mutex<UnboundedReceiver<Vec<u8>>> {
-        &self.receiver
+    /// Copies `message` to every tap (see [`MultiplexedConn::tap`]), removing those whose receiver has dropped
+    fn mirror(&self, direction: Direction, message: &[u8]) {
+        let mut taps = self.taps.lock();
+        if !taps.is_empty() {
+            let message = Bytes::copy_from_slice(message);
+            taps.retain(|tap| tap.send((direction, message.clone())).is_ok())
+        }
     }
 
-    fn id(&self) -> Self::ID {
-        self.id
+    /// Discards everything queued for the subscription, which fails its receives from then on, and refuses any further
+    /// payloads. Returns false if the stream was already evicted
+    fn evict(&self) -> bool {
+        if self.depth.evicted.swap(true, Ordering::Relaxed) {
+            return false
+        }
+
+        let bytes = self.depth.queued_bytes.swap(0, Ordering::Relaxed);
+        self.depth.total_bytes.fetch_sub(bytes, Ordering::Relaxed);
+        true
     }
 
-    fn node_type(&self) -> RelativeNodeType {
-        self.ptr.node_type
+    /// Delivers into a new per-id channel from now on, returning its receiving half. The subscription's receiver observes
+    /// its channel closing once it has received the payloads already queued. Both count against the same queue depth
+    fn redirect(&mut self) -> InboundReceiver {
+        let (tx, rx) = unbounded_channel();
+        self.tx = tx;
+        InboundReceiver { rx, depth: self.depth.clone() }
+    }
+
+    /// Closes the per-id channel, which the subscription observes once it has received the payloads already queued
+    fn end(&mut self) {
+        self.tx = unbounded_channel().0;
+    }
+
+    /// The number of payloads delivered into the per-id channel that the subscription has not yet received
+    pub fn queued(&self) -> usize {
+        self.depth.queued.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if a payload delivered now would be queued in the per-id channel, rather than handed to a handler or
+    /// refused because the stream was evicted
+    pub(crate) fn queues(&self) -> bool {
+        self.handler.is_none() && !self.depth.evicted.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if the per-id channel holds as many payloads as the subscription allows, falling back to the
+    /// connection's `default` cap
+    pub(crate) fn is_full(&self, default: Option<usize>) -> bool {
+        let max = match self.depth.max_queued.load(Ordering::Relaxed) {
+            0 => default,
+            max => Some(max)
+        };
+
+        self.handler.is_none() && matches!(max, Some(max) if self.queued() >= max)
+    }
+
+    /// Returns the fill ratio if the queue has just crossed the configured threshold. Falling back below the threshold re-arms the warning
+    pub(crate) fn crossed_high_water(&self, config: &BackpressureConfig) -> Option<f32> {
+        let fill_ratio = self.queued() as f32 / config.capacity as f32;
+        if fill_ratio < config.threshold {
+            self.depth.above_high_water.store(false, Ordering::Relaxed);
+            None
+        } else if !self.depth.above_high_water.swap(true, Ordering::Relaxed) {
+            Some(fill_ratio)
+        } else {
+            None
+        }
     }
 }
 
-#[async_trait]
-impl<K: MultiplexedConnKey + 'static> Subscribable for MultiplexedConn<K> {
-    type ID = K;
-    type UnderlyingConn = Arc<dyn ReliableOrderedStreamToTarget + 'static>;
-    type SubscriptionType = OwnedMultiplexedSubscription<K>;
-    //type BorrowedSubscriptionType<'a> = MultiplexedSubscription<'a, K>;
-    type BorrowedSubscriptionType = OwnedMultiplexedSubscription<K>;
+struct QueueDepth {
+    queued: AtomicUsize,
+    queued_bytes: AtomicUsize,
+    // shared by every stream of the connection
+    total_bytes: Arc<AtomicUsize>,
+    above_high_water: AtomicBool,
+    // 0 until the subscription overrides the connection's cap
+    max_queued: AtomicUsize,
+    created: Instant,
+    // when the open handshake completed, or when a stream that needs none was handed out
+    opened: parking_lot::Mutex<Option<Instant>>,
+    // the last time the application sent or received on the stream
+    last_activity: parking_lot::Mutex<Instant>,
+    evicted: AtomicBool
+}
 
-    fn underlying_conn(&self) -> &Self::UnderlyingConn {
-        &self.conn
+impl QueueDepth {
+    fn new(total_bytes: &Arc<AtomicUsize>) -> Self {
+        let now = Instant::now();
+        Self { queued: AtomicUsize::new(0), queued_bytes: AtomicUsize::new(0), total_bytes: total_bytes.clone(), above_high_water: AtomicBool::new(false), max_queued: AtomicUsize::new(0), created: now, opened: parking_lot::Mutex::new(None), last_activity: parking_lot::Mutex::new(now), evicted: AtomicBool::new(false) }
     }
 
-    fn subscriptions(&self) -> &RwLock<HashMap<Self::ID, MemorySender>> {
-        &self.subscribers
+    fn touch(&self) {
+        *self.last_activity.lock() = Instant::now()
     }
 
-    fn post_close_container(&self) -> &PostActionChannel<Self::ID> {
-        &self.post_close_container
+    fn mark_opened(&self) {
+        *self.opened.lock() = Some(Instant::now())
     }
 
-    fn pre_action_container(&self) -> &PreActionChannel<Self::ID> {
-        &self.pre_open_container
+    // streams subscribed to directly never complete a handshake, so they count as opened once created
+    fn opened_at(&self) -> Instant {
+        self.opened.lock().unwrap_or(self.created)
     }
+}
 
-    async fn recv_post_close_signal_from_stream(&self, id: Self::ID) -> Result<(), Error> {
-        self.post_close_container.recv(id).await
+/// The receiving half of a subscriber's inbound queue, which keeps the queue depth seen by the demultiplexer up to date
+pub struct InboundReceiver {
+    rx: UnboundedReceiver<Vec<u8>>,
+    depth: Arc<QueueDepth>
+}
+
+impl InboundReceiver {
+    /// Returns None once the queue has closed, or right away if the stream was evicted (see [`MultiplexConfig::with_eviction_policy`])
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        if self.depth.evicted.load(Ordering::Relaxed) {
+            // frees what the eviction discarded
+            self.rx.close();
+            while self.rx.try_recv().is_ok() {}
+            return None
+        }
+
+        let payload = self.rx.recv().await?;
+        Some(self.received(payload))
     }
 
-    async fn send_post_close_signal(&self, id: Self::ID) -> Result<(), Error> {
-        Ok(self.conn.send_serialized(MultiplexedPacket::PostDrop { id }).await?)
+    /// Like [`Self::recv`], but returns None right away if nothing is queued
+    pub fn try_recv(&mut self) -> Option<Vec<u8>> {
+        if self.depth.evicted.load(Ordering::Relaxed) {
+            self.rx.close();
+            while self.rx.try_recv().is_ok() {}
+            return None
+        }
+
+        let payload = self.rx.try_recv().ok()?;
+        Some(self.received(payload))
     }
 
-    async fn send_pre_open_signal(&self, id: Self::ID) -> Result<(), Error> {
-        Ok(self.conn.send_serialized(MultiplexedPacket::PreCreate { id }).await?)
+    /// Takes a received payload off the queue depth
+    fn received(&self, payload: Vec<u8>) -> Vec<u8> {
+        self.depth.queued.fetch_sub(1, Ordering::Relaxed);
+        // an eviction already took the bytes of anything queued off the total
+        if !self.depth.evicted.load(Ordering::Relaxed) {
+            self.depth.queued_bytes.fetch_sub(payload.len(), Ordering::Relaxed);
+            self.depth.total_bytes.fetch_sub(payload.len(), Ordering::Relaxed);
+        }
+
+        self.depth.touch();
+        payload
     }
+}
 
-    fn node_type(&self) -> RelativeNodeType {
-        self.node_type
+fn already_subscribed<K: MultiplexedConnKey>(id: K) -> anyhow::Error {
+    anyhow::Error::msg(format!("Unable to subscribe to {:?}, which is already subscribed to", id))
+}
+
+fn inbound_channel(total_bytes: &Arc<AtomicUsize>) -> (MemorySender, InboundReceiver) {
+    let (tx, rx) = unbounded_channel();
+    let depth = Arc::new(QueueDepth::new(total_bytes));
+    (MemorySender { tx, pre_reserved_rx: None, handler: None, depth: depth.clone(), priority: Priority::Normal, stats: StreamCounters::default(), outbound: Arc::default(), taps: parking_lot::Mutex::new(Vec::new()) }, InboundReceiver { rx, depth })
+}
+
+impl Deref for MemorySender {
+    type Target = UnboundedSender<Vec<u8>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tx
     }
+}
 
-    fn get_next_prereserved(&self) -> Option<Self::BorrowedSubscriptionType> {
-        let mut lock = self.subscribers.write();
-        let next_key = K::get_proposed_next(&self.current_latest_subscribed);
-        let pre_reserved_stream = lock.get_mut(&next_key)?;
-        let sub = MultiplexedSubscription { ptr: self, receiver: Some(Mutex::new(pre_reserved_stream.pre_reserved_rx.take()?)), id: next_key };
-        assert_eq!(K::generate_next(&self.current_latest_subscribed), next_key);
-        Some(sub.into())
+impl<K: MultiplexedConnKey> Deref for MultiplexedConn<K> {
+    type Target = MultiplexedConnInner<K>;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.deref()
     }
+}
 
-    fn subscribe(&self, id: Self::ID) -> Self::BorrowedSubscriptionType {
-        let mut lock = self.subscribers.write();
-        let (tx, receiver) = unbounded_channel();
-        let sub = MultiplexedSubscription { ptr: self, receiver: Some(Mutex::new(receiver)), id };
-        assert!(lock.insert(id, MemorySender { tx, pre_reserved_rx: None }).is_none());
-        assert_eq!(K::generate_next(&self.current_latest_subscribed), id);
-        // TODO: on GAT stabalization, remove into
-        sub.into()
+/// A comparable snapshot of a connection's state. Two endpoints that agree on the symmetric state produce equal `open_ids`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugState<K> {
+    pub node_type: RelativeNodeType,
+    /// Sorted ascending
+    pub open_ids: Vec<K>,
+    /// The total number of payloads delivered to open streams but not yet received
+    pub queued_payloads: usize,
+    /// The number of not-yet-opened ids currently holding early data
+    pub early_data_ids: usize,
+    /// The number of closed streams kept warm by the stream pool
+    pub pooled_ids: usize,
+    pub opens_rejected: u64,
+    /// The negotiated protocol version (see [`crate::negotiation::PeerInfo`])
+    pub protocol_version: Option<u32>
+}
+
+/// Any multiplexed level that can describe itself within a nesting tree
+pub trait TopologySource: Send + Sync {
+    fn topology(&self) -> TopologyNode;
+}
+
+/// A point-in-time description of one multiplexed level and the levels nested on top of its substreams
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TopologyNode {
+    pub key_type: String,
+    pub node_type: RelativeNodeType,
+    pub open_ids: Vec<String>,
+    pub children: Vec<TopologyChild>
+}
+
+/// A nested level, along with the id of the substream (in the parent's id space) it was multiplexed from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TopologyChild {
+    pub substream_id: String,
+    pub node: TopologyNode
+}
+
+impl<K: MultiplexedConnKey> TopologySource for MultiplexedConnInner<K> {
+    fn topology(&self) -> TopologyNode {
+        let mut open_ids = self.subscribers.open_ids().iter().map(|id| format!("{:?}", id)).collect::<Vec<String>>();
+        open_ids.sort();
+
+        let mut nested_levels = self.nested_levels.lock();
+        // levels that have since been dropped are pruned here
+        nested_levels.retain(|(_, level)| level.strong_count() != 0);
+        let children = nested_levels.iter()
+            .filter_map(|(id, level)| Some(TopologyChild { substream_id: format!("{:?}", id), node: level.upgrade()?.topology() }))
+            .collect();
+
+        TopologyNode { key_type: std::any::type_name::<K>().to_string(), node_type: self.node_type, open_ids, children }
     }
+}
 
-    fn owned_subscription(&self, id: Self::ID) -> Self::SubscriptionType {
-        self.subscribe(id)
+impl TopologyNode {
+    fn fmt_depth(&self, f: &mut std::fmt::Formatter<'_>, depth: usize) -> std::fmt::Result {
+        writeln!(f, "{:?} [{}] open: {:?}", self.node_type, self.key_type, self.open_ids)?;
+        for child in &self.children {
+            write!(f, "{:indent$}└ {} -> ", "", child.substream_id, indent = depth * 2)?;
+            child.node.fmt_depth(f, depth + 1)?;
+        }
+
+        Ok(())
     }
+}
 
-    fn get_next_id(&self) -> Self::ID {
-        <K as IDGen<K>>::generate_next(&self.id_gen)
+impl std::fmt::Display for TopologyNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_depth(f, 0)
     }
 }
 
-impl<K: MultiplexedConnKey + 'static> Drop for OwnedMultiplexedSubscription<K> {
-    fn drop(&mut self) {
-        close_sequence_for_multiplexed_bistream(self.id, self.ptr.clone())
+/// A frame of the multiplexing protocol, as written to the transport. See [`decode_packet`]
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(bound="")]
+pub enum MultiplexedPacket<K: MultiplexedConnKey> {
+    ApplicationLayer { id: K, payload: Vec<u8> },
+    PostDrop { id: K },
+    PreCreate { id: K },
+    Greeter,
+    StreamProbe { id: K, nonce: u64 },
+    StreamProbeAck { id: K, nonce: u64, routable: bool },
+    TransportSwap,
+    OpenRequest { nonce: u64, label: Option<String> },
+    OpenAccepted { nonce: u64 },
+    Rejected { nonce: u64, reason: String },
+    /// Several serialized packets written as one transport frame (see [`MultiplexConfig::with_coalesce_window`])
+    Batch { frames: Vec<Vec<u8>> },
+    Hello { capabilities: Capabilities },
+    /// Sent by the Receiver when a warm stream leaves the stream pool without being reopened
+    PoolEvict { id: K },
+    /// The last packet a level sends, once every local handle to it has dropped
+    Goodbye,
+    /// A piece of an application message too large to send whole (see [`MultiplexConfig::with_fragmentation`])
+    Fragment { id: K, message: u64, last: bool, chunk: Vec<u8> },
+    /// A `PreCreate` carrying the sender's trace context, sent to nodes that advertise "trace-context"
+    PreCreateTraced { id: K, trace_context: Vec<u8> },
+    /// Sent when a stream is evicted, so that the adjacent node evicts its end too
+    Reset { id: K },
+    /// Sent every keepalive interval, so that the adjacent node hears from this node even while it is otherwise idle
+    Keepalive,
+    /// Registers the sender's last will (see [`MultiplexedConn::set_last_will`])
+    LastWill { name: String, payload: Vec<u8> },
+    /// Sent by the Initiator as it begins closing a stream, to nodes that advertise "close-notice", so that the
+    /// Receiver's end stops waiting for payloads that will not come
+    CloseNotice { id: K },
+    /// Sent by the Initiator in place of echoing an open signal that exceeds its open rate (see
+    /// [`MultiplexConfig::with_max_opens_per_sec`]), to nodes that advertise "open-reject". Such signals from other
+    /// nodes are dropped unanswered
+    PreCreateRejected { id: K, reason: String }
+}
+
+/// Encodes exactly as `MultiplexedPacket::ApplicationLayer`, the first variant, but borrows the payload, so that a send
+/// need not copy it into an owned packet
+#[derive(Serialize)]
+#[serde(bound = "")]
+pub(crate) enum ApplicationLayerRef<'a, K: MultiplexedConnKey> {
+    ApplicationLayer { id: K, payload: &'a [u8] }
+}
+
+/// Leads a compact `ApplicationLayer` frame (see [`encode_compact_frame`]). No bincode-encoded packet begins with this
+/// byte, since it holds the low byte of the variant index
+const COMPACT_FRAME: u8 = 0xFF;
+
+/// Encodes an `ApplicationLayer` frame as the marker, the id's bincode encoding without its trailing zero bytes (preceded
+/// by the remaining length and the number of bytes trimmed), then the payload, which the transport frame delimits. For
+/// small integer ids this is 4 bytes of overhead rather than 20. Returns None for ids too long to encode this way
+pub(crate) fn encode_compact_frame<K: MultiplexedConnKey>(id: K, payload: &[u8]) -> std::io::Result<Option<Vec<u8>>> {
+    let id = bincode2::serialize(&id).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+    let len = id.iter().rposition(|byte| *byte != 0).map(|idx| idx + 1).unwrap_or(0);
+    let trimmed = id.len() - len;
+
+    if len > u8::MAX as usize || trimmed > u8::MAX as usize {
+        return Ok(None)
     }
+
+    let mut frame = Vec::with_capacity(3 + len + payload.len());
+    frame.extend_from_slice(&[COMPACT_FRAME, len as u8, trimmed as u8]);
+    frame.extend_from_slice(&id[..len]);
+    frame.extend_from_slice(payload);
+    Ok(Some(frame))
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::sync::test_utils::create_streams;
-    use crate::reliable_conn::ReliableOrderedStreamToTargetExt;
-    use crate::sync::network_application::NetworkApplication;
-    use crate::sync::subscription::{Subscribable, SubscriptionBiStreamExt};
-    use serde::{Serialize, Deserialize};
-    use crate::multiplex::OwnedMultiplexedSubscription;
-    use crate::sync::SymmetricConvID;
-    use async_recursion::async_recursion;
+/// Why a frame could not be decoded (see [`decode_packet`])
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The frame ended before the packet did
+    Truncated,
+    /// The frame is not a packet of this protocol
+    Malformed(String)
+}
 
-    #[derive(Serialize, Deserialize)]
-    struct Packet(usize);
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "Truncated frame"),
+            Self::Malformed(reason) => write!(f, "Malformed frame: {}", reason)
+        }
+    }
+}
 
-    #[tokio::test]
-    async fn nested_multiplexed_stream() {
+impl std::error::Error for DecodeError {}
 
-        let (outer_stream_server, outer_stream_client) = create_streams().await;
-        // 50 recursions deep ....
-        nested(0, 50, outer_stream_server, outer_stream_client).await;
+impl From<bincode2::Error> for DecodeError {
+    fn from(err: bincode2::Error) -> Self {
+        match &*err {
+            bincode2::ErrorKind::Io(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => Self::Truncated,
+            _ => Self::Malformed(err.to_string())
+        }
+    }
+}
+
+/// Decodes one frame as read from the transport, in either the bincode or the compact encoding. Never panics, whatever
+/// `frame` holds, which makes this the entry point for fuzzing the parsing of untrusted input. The frames inside a
+/// `Batch` are left encoded, and are decoded the same way
+pub fn decode_packet<K: MultiplexedConnKey>(frame: &[u8]) -> Result<MultiplexedPacket<K>, DecodeError> {
+    MultiplexedPacket::decode(frame)
+}
+
+impl<K: MultiplexedConnKey> MultiplexedPacket<K> {
+    /// Decodes a packet in either the bincode or the compact encoding
+    pub(crate) fn decode(frame: &[u8]) -> Result<Self, DecodeError> {
+        if frame.first() != Some(&COMPACT_FRAME) {
+            return Ok(bincode2::deserialize(frame)?)
+        }
+
+        let (len, trimmed) = (*frame.get(1).ok_or(DecodeError::Truncated)? as usize, *frame.get(2).ok_or(DecodeError::Truncated)? as usize);
+        let mut id = frame.get(3..3 + len).ok_or(DecodeError::Truncated)?.to_vec();
+        id.resize(len + trimmed, 0);
+
+        Ok(Self::ApplicationLayer { id: bincode2::deserialize_from(&id[..])?, payload: frame[3 + len..].to_vec() })
+    }
+
+    /// Returns true if `frame` is the swap marker, the last frame sent on a transport being replaced. Compared byte for
+    /// byte rather than decoded, since every inbound frame is checked
+    pub(crate) fn is_transport_swap(frame: &[u8]) -> bool {
+        serialize_to_buffer(&Self::TransportSwap).is_ok_and(|marker| marker[..] == frame[..])
+    }
+
+    /// The stream this packet is scoped to, if any
+    pub(crate) fn stream_id(&self) -> Option<&K> {
+        match self {
+            Self::ApplicationLayer { id, .. } | Self::PostDrop { id } | Self::PreCreate { id } | Self::PreCreateTraced { id, .. } | Self::StreamProbe { id, .. } | Self::StreamProbeAck { id, .. } | Self::PoolEvict { id } | Self::Fragment { id, .. } | Self::Reset { id } => Some(id),
+            _ => None
+        }
+    }
+}
+
+/// Encodes `ApplicationLayer` frames that share one payload across many ids. bincode lays out the fields back-to-back,
+/// so each frame is the id-specific header followed by the payload, which is serialized only once
+pub(crate) struct SharedPayloadEncoder {
+    payload: Vec<u8>,
+    frame: Vec<u8>
+}
+
+impl SharedPayloadEncoder {
+    pub(crate) fn new(payload: &[u8]) -> std::io::Result<Self> {
+        let payload = bincode2::serialize(payload).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+        Ok(Self { frame: Vec::with_capacity(payload.len() + 32), payload })
+    }
+
+    /// Returns the complete frame for `id`. The internal buffer is reused between calls
+    pub(crate) fn frame_for<K: MultiplexedConnKey>(&mut self, id: K) -> std::io::Result<&[u8]> {
+        let map_err = |err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err);
+        // the header is the packet with an empty payload, minus that empty payload's length prefix
+        let empty_len = bincode2::serialized_size(&Vec::<u8>::new()).map_err(map_err)? as usize;
+        self.frame.clear();
+        bincode2::serialize_into(&mut self.frame, &MultiplexedPacket::ApplicationLayer { id, payload: Vec::new() }).map_err(map_err)?;
+        self.frame.truncate(self.frame.len() - empty_len);
+        self.frame.extend_from_slice(&self.payload);
+        Ok(&self.frame)
+    }
+}
+
+impl<K: MultiplexedConnKey> MultiplexedConn<K> {
+    pub fn new<T: ReliableOrderedStreamToTarget + 'static>(node_type: RelativeNodeType, conn: T) -> Self {
+        Self::new_with_config(node_type, conn, MultiplexConfig::default())
+    }
+
+    pub fn new_with_config<T: ReliableOrderedStreamToTarget + 'static>(node_type: RelativeNodeType, conn: T, config: MultiplexConfig) -> Self {
+        let transport = Arc::new(SwappableConn::new(Arc::new(conn), config.transport_restore_grace));
+        let peer_max_frame = Arc::new(AtomicUsize::new(usize::MAX));
+        let conn: Arc<dyn ReliableOrderedStreamToTarget> = if config.coalesce_window.is_zero() {
+            transport.clone()
+        } else {
+            Arc::new(CoalescingConn::new::<K>(transport.clone(), config.coalesce_window, config.runtime.clone(), peer_max_frame.clone()))
+        };
+
+        let conn: Arc<dyn ReliableOrderedStreamToTarget> = Arc::new(FrameLimitedConn::new(conn, peer_max_frame.clone()));
+        let conn: Arc<dyn ReliableOrderedStreamToTarget> = Arc::new(ScheduledConn::new(conn, !config.coalesce_window.is_zero()));
+
+        let (demux_status, demux_status_rx) = tokio::sync::watch::channel(None);
+        let parts = AssembleParts { conn, peer_max_frame, transport, id_gen: K::generate_container(), current_latest_subscribed: K::generate_container(), demux_status: Arc::new(demux_status), demux_status_rx, parent: None };
+        Self::assemble(node_type, config, parts)
+    }
+
+    /// Builds a connection over an already-wrapped transport. The first id generated from `id_gen` and every id after it
+    /// up to `INITIAL_CAPACITY` are pre-reserved
+    pub(crate) fn assemble(node_type: RelativeNodeType, config: MultiplexConfig, parts: AssembleParts<K>) -> Self {
+        let AssembleParts { conn, peer_max_frame, transport, id_gen, current_latest_subscribed, demux_status, demux_status_rx, parent } = parts;
+        let ids: Vec<K> = (0..INITIAL_CAPACITY).into_iter().map(|_| next_unreserved(&id_gen)).collect();
+        // the next two lines will generate a list of pre-established bistreams
+        let post_close_container = PostActionChannel::new(&ids);
+        let subscribers = SubscriberMap::with_capacity(config.subscriber_shards, config.subscriber_capacity);
+        let buffered_bytes = Arc::new(AtomicUsize::new(0));
+
+        for id in ids {
+            let (mut sender, pre_reserved_rx) = inbound_channel(&buffered_bytes);
+            sender.pre_reserved_rx = Some(pre_reserved_rx);
+            subscribers.shard(&id).write().insert(id, sender);
+        }
+
+        let opens = OpenRegistry::new(config.max_opens_per_sec);
+        let peer = PeerState::new(config.keepalive_policy());
+        let pool = StreamPool::new(config.stream_pool);
+        // without its threads, handlers run inline as they would without a pool
+        let processing = config.processing_pool().and_then(|threads| ProcessingPool::new(threads).map_err(|err| log::error!("Unable to start the processing pool: {:?}", err)).ok());
+        let reassembly = Reassembly::new(config.reassembly);
+        #[cfg(feature = "otel")]
+        let traces = crate::telemetry::StreamTraces::new(config.tracer.clone());
+
+        Self { inner: Arc::new(MultiplexedConnInner {
+            conn,
+            transport,
+            peer_max_frame,
+            subscribers,
+            pre_open_container: PreActionChannel::new(config.max_concurrent_opens),
+            post_close_container,
+            current_latest_subscribed,
+            id_gen,
+            node_type,
+            config,
+            nested_levels: parking_lot::Mutex::new(Vec::new()),
+            parent_id: parking_lot::Mutex::new(None),
+            addrs: parking_lot::Mutex::new(None),
+            early_data: parking_lot::Mutex::new(HashMap::new()),
+            pending_probes: parking_lot::Mutex::new(HashMap::new()),
+            probe_nonce: AtomicU64::new(0),
+            demux_status,
+            demux_status_rx,
+            handles_alive: tokio::sync::watch::channel(()).0,
+            _parent: parent,
+            event_listeners: parking_lot::Mutex::new(Vec::new()),
+            opens,
+            peer,
+            demux_lag: DemuxLag::default(),
+            pool,
+            processing,
+            partitions: Partitions::new(),
+            handshakes: HandshakeLog { unechoed_opens: parking_lot::Mutex::new(HashSet::new()), unanswered_closes: parking_lot::Mutex::new(HashSet::new()), queued_opens: parking_lot::Mutex::new(HashSet::new()), rejected_opens: parking_lot::Mutex::new(HashMap::new()) },
+            polled: parking_lot::Mutex::new(PolledStreams { receivers: HashMap::new(), arrivals: std::collections::VecDeque::new() }),
+            scoped_closes: parking_lot::Mutex::new(Vec::new()),
+            scoped_ids: parking_lot::Mutex::new(HashSet::new()),
+            lingering: parking_lot::Mutex::new(HashMap::new()),
+            peer_closed: parking_lot::Mutex::new(HashSet::new()),
+            deadline: parking_lot::Mutex::new(None),
+            origins: parking_lot::Mutex::new(HashMap::new()),
+            buffered_bytes,
+            compact_frames: AtomicBool::new(false),
+            reassembly,
+            #[cfg(feature = "otel")]
+            traces
+        })}
+    }
+
+    /// Generates the next id from `container`, skipping reserved ids, scoped ids and the ranges of live partitions
+    fn next_unpartitioned(&self, container: &K::Container) -> K {
+        loop {
+            let id = next_unreserved::<K>(container);
+            if self.scoped_ids.lock().contains(&id) {
+                continue
+            }
+
+            if !self.partitions.skip_past(&id, container) {
+                return id
+            }
+        }
+    }
+
+    pub(crate) fn mark_opened(&self, id: K) {
+        if let Some(sender) = self.subscribers.shard(&id).read().get(&id) {
+            sender.depth.mark_opened()
+        }
+    }
+
+    pub fn config(&self) -> &MultiplexConfig {
+        &self.config
+    }
+
+    /// Resolves once the background demultiplexer exits: Ok on a clean EOF from the underlying connection, or the
+    /// error that the underlying connection returned. Never resolves for connections that were not started via `register`
+    pub fn demux_result(&self) -> impl Future<Output=std::io::Result<()>> + Send + 'static {
+        let mut rx = self.demux_status_rx.clone();
+
+        async move {
+            loop {
+                if let Some(outcome) = rx.borrow().clone() {
+                    return outcome.map_err(|(kind, message)| std::io::Error::new(kind, message))
+                }
+
+                if rx.changed().await.is_err() {
+                    return Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "Demultiplexer dropped"))
+                }
+            }
+        }
+    }
+
+    /// Returns Some(false) once the demultiplexer has ended, otherwise forwards the underlying transport's liveness signal
+    /// (see [`ReliableOrderedStreamToTarget::peer_alive`]). Nothing is sent to the peer
+    pub fn peer_alive(&self) -> Option<bool> {
+        if self.demux_status_rx.borrow().is_some() {
+            return Some(false)
+        }
+
+        self.conn.peer_alive()
+    }
+
+    /// Returns a receiver of the [`StreamEvent`]s that occur on this connection from this point on. Each call returns an independent receiver
+    pub fn events(&self) -> UnboundedReceiver<StreamEvent<K>> {
+        let (tx, rx) = unbounded_channel();
+        self.event_listeners.lock().push(tx);
+        rx
+    }
+
+    pub(crate) fn emit_event(&self, event: StreamEvent<K>) {
+        // receivers that have since been dropped are pruned here
+        self.event_listeners.lock().retain(|tx| tx.send(event.clone()).is_ok())
+    }
+
+    /// The time between the transport returning the most recent inbound frame and the demultiplexer finishing routing it.
+    /// This climbs when slow payload handlers or a full pre-open queue hold up the demultiplexer, and with it every stream
+    pub fn demux_lag(&self) -> std::time::Duration {
+        std::time::Duration::from_nanos(self.demux_lag.last_nanos.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn record_demux_lag(&self, lag: std::time::Duration) {
+        self.demux_lag.last_nanos.store(lag.as_nanos().min(u64::MAX as u128) as u64, Ordering::Relaxed);
+
+        if let Some(threshold) = self.config.demux_lag_threshold {
+            if lag < threshold {
+                self.demux_lag.above_threshold.store(false, Ordering::Relaxed);
+            } else if !self.demux_lag.above_threshold.swap(true, Ordering::Relaxed) {
+                log::warn!("Demultiplexer took {:?} to route a frame, exceeding the threshold of {:?}", lag, threshold);
+                self.emit_event(StreamEvent::DemuxLag { lag })
+            }
+        }
+    }
+
+    /// Ids set aside for control use, which are never assigned to a stream (see [`IDGen::reserved_ids`])
+    pub fn reserved_ids(&self) -> Vec<K> {
+        K::reserved_ids()
+    }
+
+    /// The bytes held by fragmented messages that have partially arrived (see [`MultiplexConfig::with_max_reassembly_bytes`])
+    pub fn reassembly_bytes(&self) -> usize {
+        self.reassembly.buffered_bytes()
+    }
+
+    /// Returns the traffic counted on `id` since it opened, or since [`Self::take_stats`] last reset it. None if `id`
+    /// is not open on this connection
+    pub fn stats(&self, id: K) -> Option<SubStats> {
+        self.subscribers.shard(&id).read().get(&id).map(|sender| sender.stats.snapshot())
+    }
+
+    /// The id and traffic of every stream opened locally, as of a single point in time: no open or close lands part-way
+    /// through, unlike a series of calls to [`Self::stats`]
+    pub fn snapshot_subscribers(&self) -> Vec<(K, SubStats)> {
+        let mut snapshot = Vec::new();
+        self.subscribers.for_each_consistent(|id, sender| {
+            if sender.pre_reserved_rx.is_none() {
+                snapshot.push((*id, sender.stats.snapshot()))
+            }
+        });
+
+        snapshot
+    }
+
+    /// Like [`Self::stats`], but resets the counters as they are read, so that repeated calls return the traffic of each
+    /// interval between them. Each counter is swapped individually, so no count is ever lost, though a message counted
+    /// concurrently may have its bytes reported in the next interval
+    pub fn take_stats(&self, id: K) -> Option<SubStats> {
+        self.subscribers.shard(&id).read().get(&id).map(|sender| sender.stats.take())
+    }
+
+    /// The sends on stream `id` that have begun but not yet been written to the transport, including any being written.
+    /// None if `id` is not open. Bounded by [`MultiplexConfig::with_max_outbound_queue`], if set
+    pub fn outbound_queue_len(&self, id: K) -> Option<usize> {
+        self.subscribers.shard(&id).read().get(&id).map(|sender| sender.outbound.len.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn outbound_gate(&self, id: K) -> Option<OutboundGate> {
+        let queue = self.subscribers.shard(&id).read().get(&id)?.outbound.clone();
+        Some(OutboundGate { queue, max: self.config.max_outbound_queue() })
+    }
+
+    pub(crate) fn record_sent(&self, id: K, message: &[u8]) {
+        if let Some(sender) = self.subscribers.shard(&id).read().get(&id) {
+            sender.stats.record_sent(message.len());
+            sender.depth.touch();
+            sender.mirror(Direction::Sent, message)
+        }
+    }
+
+    /// Mirrors every message sent or received on stream `id` to the returned receiver, in the order they were sent or
+    /// routed, without affecting the stream itself. Each tap receives its own copies. Dropping the receiver removes the
+    /// tap. If `id` is not open, the returned receiver yields nothing
+    pub fn tap(&self, id: K) -> UnboundedReceiver<(Direction, Bytes)> {
+        let (tx, rx) = unbounded_channel();
+        if let Some(sender) = self.subscribers.shard(&id).read().get(&id) {
+            sender.taps.lock().push(tx)
+        }
+
+        rx
+    }
+
+    fn emit_closed(&self, id: K, sender: &MemorySender) {
+        let summary = StreamSummary { id, opened: sender.depth.opened_at(), closed: Instant::now(), stats: sender.stats.lifetime() };
+        self.emit_event(StreamEvent::Closed { summary })
+    }
+
+    /// The bytes delivered to the inbound queues of this connection's streams, but not yet received
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Evicts streams, as the eviction policy selects them, until `len` more bytes fit within the connection's budget
+    /// (see [`MultiplexConfig::with_max_buffered_bytes`]). Fails if `id`, which they are bound for, is evicted to make
+    /// room. If nothing else can be evicted, `id` itself is, so that its receives fail rather than silently skip the payload
+    pub(crate) async fn make_room(&self, id: K, len: usize) -> Result<(), anyhow::Error> {
+        let max = match self.config.max_buffered_bytes() {
+            Some(max) => max,
+            None => return Ok(())
+        };
+
+        while self.buffered_bytes() + len > max {
+            let mut victim: Option<(K, Instant)> = None;
+            // streams with nothing queued would free nothing
+            self.subscribers.for_each(|candidate, sender| {
+                if sender.depth.evicted.load(Ordering::Relaxed) || sender.depth.queued_bytes.load(Ordering::Relaxed) == 0 {
+                    return
+                }
+
+                let age = match self.config.eviction_policy() {
+                    EvictionPolicy::Lru => *sender.depth.last_activity.lock(),
+                    EvictionPolicy::Fifo => sender.depth.opened_at(),
+                    EvictionPolicy::None => return
+                };
+
+                if victim.map(|(_, oldest)| age < oldest).unwrap_or(true) {
+                    victim = Some((*candidate, age))
+                }
+            });
+
+            let victim = match victim {
+                Some((victim, _)) => victim,
+                None => {
+                    self.evict(id).await;
+                    return Err(anyhow::Error::msg(format!("Buffering {} more bytes for {:?} would exceed the limit of {} bytes. Evicted it and discarded the payload", len, id, max)))
+                }
+            };
+
+            self.evict(victim).await;
+            if victim == id {
+                return Err(anyhow::Error::msg(format!("{:?} was evicted to stay within the limit of {} buffered bytes. Discarding payload", id, max)))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Evicts `id` on both nodes: the adjacent node is told to evict its end too, if it advertises support for resets
+    pub(crate) async fn evict(&self, id: K) {
+        if self.on_reset(id) {
+            log::warn!("Evicted {:?} to stay within the inbound queue limits", id);
+            if !self.peer_supports("reset") {
+                log::warn!("The adjacent node does not support resets, so its end of {:?} stays open", id);
+                return
+            }
+
+            if let Err(err) = self.conn.send_serialized(MultiplexedPacket::Reset { id }).await {
+                log::warn!("Unable to notify the adjacent node of the eviction of {:?}: {:?}", id, err)
+            }
+        }
+    }
+
+    /// Evicts the local end of `id`. Returns false if it was not open, or already evicted
+    pub(crate) fn on_reset(&self, id: K) -> bool {
+        let evicted = self.subscribers.shard(&id).read().get(&id).map(MemorySender::evict).unwrap_or(false);
+        if evicted {
+            self.emit_event(StreamEvent::Evicted { id })
+        }
+
+        evicted
+    }
+
+    /// Returns a snapshot of this connection's state, intended for asserting agreement between two endpoints in tests.
+    /// Shards are locked one at a time, so the snapshot is only exact while the connection is quiescent
+    pub fn debug_state(&self) -> DebugState<K> where K: Ord {
+        let mut open_ids = self.subscribers.open_ids();
+        open_ids.sort();
+
+        let mut queued_payloads = 0;
+        self.subscribers.for_each(|_, sender| queued_payloads += sender.queued());
+
+        DebugState {
+            node_type: self.node_type,
+            open_ids,
+            queued_payloads,
+            early_data_ids: self.early_data.lock().len(),
+            pooled_ids: self.pool.len(),
+            opens_rejected: self.opens.rejected(),
+            protocol_version: self.peer_info().protocol_version
+        }
+    }
+
+    /// Returns the handshakes awaiting the adjacent node, excluding those of partitions. Only the Receiver awaits replies,
+    /// so this is always empty on the Initiator
+    pub fn pending_handshakes(&self) -> PendingHandshakes<K> {
+        PendingHandshakes {
+            opens: self.handshakes.unechoed_opens.lock().iter().copied().collect(),
+            closes: self.handshakes.unanswered_closes.lock().iter().copied().collect()
+        }
+    }
+
+    /// Re-sends every handshake signal still awaiting the adjacent node, on this connection and its partitions. A failed
+    /// replay is retried in full, after the configured backoff (see [`MultiplexConfig::with_backoff`]), since the adjacent
+    /// node answers replayed signals idempotently
+    pub(crate) async fn replay_handshakes(&self) -> std::io::Result<()> {
+        let mut attempt = 0;
+        loop {
+            match self.replay_handshakes_once().await {
+                Err(err) if attempt + 1 < MAX_REPLAY_ATTEMPTS => {
+                    let delay = self.config.backoff().delay(attempt);
+                    log::warn!("Unable to replay handshakes (attempt {}). Retrying in {:?}: {:?}", attempt + 1, delay, err);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+
+                res => return res
+            }
+        }
+    }
+
+    async fn replay_handshakes_once(&self) -> std::io::Result<()> {
+        let partitions = self.partitions.live();
+
+        for conn in std::iter::once(self).chain(partitions.iter()) {
+            let pending = conn.pending_handshakes();
+            for id in pending.opens {
+                conn.conn.send_serialized(MultiplexedPacket::PreCreate { id }).await?;
+            }
+
+            for id in pending.closes {
+                conn.conn.send_serialized(MultiplexedPacket::PostDrop { id }).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if `id` has been opened locally, and not yet finished closing
+    pub(crate) fn is_open(&self, id: K) -> bool {
+        self.subscribers.shard(&id).read().get(&id).map(|sender| sender.pre_reserved_rx.is_none()).unwrap_or(false)
+    }
+
+    /// Returns a tree describing this connection and every multiplexed level nested on top of its substreams
+    pub fn topology(&self) -> TopologyNode {
+        self.inner.topology()
+    }
+
+    /// Returns a receiver whose `changed` errors once every handle to this connection has been dropped
+    pub(crate) fn handles_alive(&self) -> tokio::sync::watch::Receiver<()> {
+        self.handles_alive.subscribe()
+    }
+
+    pub(crate) fn downgrade(&self) -> Weak<MultiplexedConnInner<K>> {
+        Arc::downgrade(&self.inner)
+    }
+
+    pub(crate) fn upgrade(inner: &Weak<MultiplexedConnInner<K>>) -> Option<Self> {
+        inner.upgrade().map(|inner| Self { inner })
+    }
+
+    pub(crate) fn as_topology_source(&self) -> Weak<dyn TopologySource> where K: 'static {
+        Arc::downgrade(&self.inner) as Weak<dyn TopologySource>
+    }
+
+    pub(crate) fn attach_nested_level(&self, id: K, level: Weak<dyn TopologySource>) {
+        let mut nested_levels = self.nested_levels.lock();
+        // levels that have since been dropped are pruned here too, so that the list stays bounded without topology calls
+        nested_levels.retain(|(_, level)| level.strong_count() != 0);
+        nested_levels.push((id, level))
+    }
+
+    /// For a level created by [`SubscriptionBiStreamExt::multiplex`], the id of the substream it runs on within the
+    /// parent level, whose key type is `P`. None for a top-level connection, or if `P` is not the parent's key type
+    pub fn parent_id<P: Copy + 'static>(&self) -> Option<P> {
+        self.parent_id.lock().as_ref()?.downcast_ref::<P>().copied()
+    }
+
+    pub(crate) fn set_parent_id<P: MultiplexedConnKey + 'static>(&self, id: P) {
+        *self.parent_id.lock() = Some(Box::new(id))
+    }
+
+    pub(crate) fn set_addrs(&self, local_addr: SocketAddr, peer_addr: SocketAddr) {
+        *self.addrs.lock() = Some((local_addr, peer_addr))
+    }
+
+    /// Returns true if inbound payloads for `id` currently have somewhere to go
+    pub(crate) fn is_routable(&self, id: K) -> bool {
+        self.subscribers.shard(&id).read().get(&id).map(|sender| sender.handler.is_some() || !sender.tx.is_closed()).unwrap_or(false)
+    }
+
+    /// Holds a frame for an id that has not yet been opened locally, as permitted by the configured [`EarlyDataPolicy`].
+    /// Must be called while holding the subscriber read lock so that it cannot race with `subscribe` flushing the buffer
+    pub(crate) fn buffer_early_frame(&self, id: K, payload: Vec<u8>) -> Result<(), anyhow::Error> {
+        let (max_frames_per_id, max_pending_ids, timeout) = match self.config.early_data {
+            EarlyDataPolicy::Reject => return Err(anyhow::Error::msg("Channel ID does not exist")),
+            EarlyDataPolicy::Buffer { max_frames_per_id, max_pending_ids, timeout } => (max_frames_per_id, max_pending_ids, timeout)
+        };
+
+        let mut early_data = self.early_data.lock();
+        // discard the frames of any id that never opened within the timeout
+        early_data.retain(|_, (first_seen, _)| first_seen.elapsed() < timeout);
+
+        if !early_data.contains_key(&id) && early_data.len() >= max_pending_ids {
+            return Err(anyhow::Error::msg("Too many pending ids with early data"))
+        }
+
+        let (_, frames) = early_data.entry(id).or_insert_with(|| (Instant::now(), Vec::new()));
+        if frames.len() >= max_frames_per_id {
+            return Err(anyhow::Error::msg("Early data buffer for ID is full"))
+        }
+
+        frames.push(payload);
+        Ok(())
+    }
+
+    /// The adjacent node closed its end of `id`, so no further payloads will arrive. Ends the local subscription's
+    /// inbound channel once it has received the payloads already queued
+    pub(crate) fn on_peer_closed(&self, id: K) {
+        if let Some(sender) = self.subscribers.shard(&id).write().get_mut(&id) {
+            sender.end();
+            self.peer_closed.lock().insert(id);
+        }
+    }
+
+    /// Initiator: if `id` is warm in the stream pool, re-registers it as pre-reserved so that `take_reopened` can hand it out
+    pub(crate) fn reclaim_pooled(&self, id: K) -> bool {
+        if self.node_type.is_initiator() && self.pool.reclaim(id) {
+            let (mut sender, pre_reserved_rx) = inbound_channel(&self.buffered_bytes);
+            sender.pre_reserved_rx = Some(pre_reserved_rx);
+            self.subscribers.shard(&id).write().insert(id, sender);
+            return true
+        }
+
+        false
+    }
+
+    /// Returns true if the stream pool is enabled locally and the adjacent node has advertised support for it
+    fn pool_active(&self) -> bool {
+        self.pool.is_enabled() && self.peer_supports("stream-pool")
+    }
+
+    async fn send_pool_evictions(&self, evicted: Vec<K>) -> Result<(), Error> {
+        for id in evicted {
+            self.conn.send_serialized(MultiplexedPacket::PoolEvict { id }).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Receiver: evicts the pooled streams that have outlived the TTL, returning when the next of those left expires
+    pub(crate) async fn expire_pooled(&self) -> Option<Instant> {
+        let (expired, next_expiry) = self.pool.expire();
+        if let Err(err) = self.send_pool_evictions(expired).await {
+            log::warn!("Unable to evict pooled streams: {:?}", err)
+        }
+
+        next_expiry
+    }
+
+    /// Redirects inbound payloads for an already-subscribed `id` to be pulled by [`Self::drain_received`] instead of
+    /// awaited. The subscription's `recv` yields only the payloads queued beforehand. Payloads wait in the stream's own
+    /// inbound queue, so the connection's queue limits and eviction apply to them as usual (see
+    /// [`MultiplexConfig::with_max_buffered_messages`]). Those still queued when the stream closes are discarded
+    pub fn subscribe_polled(&self, id: K) -> Result<(), anyhow::Error> {
+        let mut lock = self.subscribers.shard(&id).write();
+        let sender = lock.get_mut(&id).ok_or_else(|| anyhow::Error::msg("Channel ID does not exist"))?;
+        let receiver = sender.redirect();
+        let _ = self.polled.lock().receivers.insert(id, receiver);
+        Ok(())
+    }
+
+    /// Takes every payload received so far on the streams registered through [`Self::subscribe_polled`], in the order
+    /// they arrived, without waiting. Intended for synchronous loops that poll the network once per tick. The payloads
+    /// are received by the demultiplexer task started by `register`, or by [`Self::poll_once`] on a connection
+    /// registered through [`Self::register_polled`]
+    pub fn drain_received(&self) -> Vec<(K, Bytes)> {
+        let mut polled = self.polled.lock();
+        let PolledStreams { receivers, arrivals } = &mut *polled;
+        // an evicted or closed stream yields nothing for its remaining arrivals
+        arrivals.drain(..).filter_map(|id| Some((id, Bytes::from(receivers.get_mut(&id)?.try_recv()?)))).collect()
+    }
+
+    /// Records that a payload was delivered to `id`, if it is polled. Must be called while holding the subscriber lock
+    /// the payload was delivered under, so that the stream cannot be redirected in between
+    pub(crate) fn on_delivered(&self, id: K) {
+        let mut polled = self.polled.lock();
+        if polled.receivers.contains_key(&id) {
+            polled.arrivals.push_back(id)
+        }
+    }
+
+    /// Opens substream `id`, whose close can be awaited whether it is closed explicitly or its scope ends early (see
+    /// [`ScopedSubscription`]). The adjacent node must open its end with `scoped_subscription(id)` too, and this returns
+    /// once it has. Fails if `id` is reserved or already open, or if the
+    /// connection's deadline passes first. From then on, the ids this connection generates for its other streams skip
+    /// over `id`, as they do on the adjacent node once it opens its end
+    pub async fn scoped_subscription(&self, id: K) -> Result<ScopedSubscription<K>, anyhow::Error> where K: 'static {
+        if K::is_reserved(&id) {
+            return Err(anyhow::Error::msg(format!("{:?} is reserved for control use", id)))
+        }
+
+        let receiver = self.register_subscriber(id)?;
+        let _ = self.scoped_ids.lock().insert(id);
+        // unwinds the local end if the open fails or is dropped before the adjacent node opens its end
+        let mut open = ScopedOpen { conn: self, id, armed: true };
+        self.post_close_container.setup_channel(id);
+        self.await_adjacent_open(id).await?;
+
+        self.mark_opened(id);
+        open.armed = false;
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let subscription = OwnedMultiplexedSubscription { ptr: self.clone(), receiver: Mutex::new(receiver), id, priority: Priority::Normal, on_closed: Some(tx), deadline: parking_lot::Mutex::new(None) };
+        Ok(ScopedSubscription { inner: Some(subscription), closed: Some(rx) })
+    }
+
+    /// Probes `id` until the adjacent node has opened its end, waiting out the configured backoff between probes
+    async fn await_adjacent_open(&self, id: K) -> Result<(), anyhow::Error> where K: 'static {
+        let mut attempt = 0;
+        loop {
+            match self.probe_stream(id).await {
+                Ok(_) => return Ok(()),
+                Err(err) if err.kind() == std::io::ErrorKind::NotConnected => {
+                    let delay = self.config.backoff().delay(attempt);
+                    match self.deadline() {
+                        Some(deadline) if Instant::now() + delay >= deadline => return Err(anyhow::Error::msg(format!("The deadline passed before the adjacent node opened {:?}", id))),
+                        _ => tokio::time::sleep(delay).await
+                    }
+
+                    attempt = attempt.saturating_add(1);
+                }
+
+                Err(err) => return Err(err.into())
+            }
+        }
+    }
+
+    /// Creates the local end of `id`, flushing any frames that arrived for it beforehand. Fails if `id` is already subscribed to
+    fn register_subscriber(&self, id: K) -> Result<InboundReceiver, anyhow::Error> {
+        let mut lock = self.subscribers.shard(&id).write();
+        // replacing the sender would leave the existing subscription waiting on a receiver that nothing delivers to
+        if lock.contains_key(&id) {
+            return Err(already_subscribed(id))
+        }
+
+        self.handshakes.queued_opens.lock().remove(&id);
+        let (sender, receiver) = inbound_channel(&self.buffered_bytes);
+        // flush any frames that arrived before the stream was opened locally
+        if let Some((_, frames)) = self.early_data.lock().remove(&id) {
+            for frame in frames {
+                let _ = sender.deliver(frame);
+            }
+        }
+
+        let _ = lock.insert(id, sender);
+        Ok(receiver)
+    }
+
+    /// Waits for every scoped subscription dropped so far to finish closing
+    pub async fn join_scoped_closes(&self) {
+        let closes = std::mem::take(&mut *self.scoped_closes.lock());
+        let _ = futures::future::join_all(closes).await;
+    }
+
+    /// Returns the stream for `id` if it was dropped within the configured close linger (see
+    /// [`MultiplexConfig::with_close_linger`]), cancelling its deferred close. Messages that arrived in the meantime are
+    /// still delivered. Returns None if the stream is not lingering
+    pub fn reacquire(&self, id: K) -> Option<OwnedMultiplexedSubscription<K>> where K: 'static {
+        let Lingering { receiver, priority, .. } = self.lingering.lock().remove(&id)?;
+        log::trace!("Reacquired lingering stream {:?}", id);
+        Some(OwnedMultiplexedSubscription { ptr: self.clone(), receiver: Mutex::new(receiver), id, priority, on_closed: None, deadline: parking_lot::Mutex::new(None) })
+    }
+
+    /// Bounds every subsequent open, and every send and receive on this connection's streams, by `deadline`, until
+    /// [`Self::clear_deadline`] is called. Opens and receives still pending at the deadline fail, as do sends that have
+    /// not yet begun, all with `TimedOut`. A send already writing to the transport is not interrupted. Where a stream has
+    /// a deadline of its own (see [`OwnedMultiplexedSubscription::set_deadline`]), the earlier of the two applies, as does
+    /// any timeout placed around an individual call
+    pub fn set_deadline(&self, deadline: Instant) {
+        *self.deadline.lock() = Some(deadline)
+    }
+
+    pub fn clear_deadline(&self) {
+        *self.deadline.lock() = None
+    }
+
+    /// Defers the close of `id` by `linger`, unless the stream is reacquired first
+    fn linger(&self, id: K, receiver: InboundReceiver, priority: Priority, linger: std::time::Duration, rt: tokio::runtime::Handle) where K: 'static {
+        let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel();
+        self.lingering.lock().insert(id, Lingering { receiver, priority, _cancel: cancel_tx });
+        let conn = self.clone();
+
+        rt.spawn(async move {
+            tokio::select! {
+                _ = tokio::time::sleep(linger) => {}
+                // the entry was removed by `reacquire`
+                _ = cancel_rx => return
+            }
+
+            if conn.lingering.lock().remove(&id).is_some() {
+                #[cfg(feature = "otel")]
+                conn.traces.on_close_started(id);
+                close_sequence_for_multiplexed_bistream(id, conn, None)
+            }
+        });
+    }
+
+    /// Opens a stream whose frames are scheduled with `priority`: its queued sends are written, and its frames read ahead by
+    /// the demultiplexer are routed, before those of lower-priority streams. The adjacent node should open its end with the
+    /// same priority for the stream to be favored in both directions
+    pub async fn subscribe_with_priority(&self, priority: Priority) -> Result<OwnedMultiplexedSubscription<K>, anyhow::Error> where K: 'static {
+        let mut subscription: OwnedMultiplexedSubscription<K> = self.initiate_subscription().await?;
+        subscription.priority = priority;
+
+        if let Some(sender) = self.subscribers.shard(&subscription.id).write().get_mut(&subscription.id) {
+            sender.priority = priority;
+        }
+
+        Ok(subscription)
+    }
+
+    /// Delivers inbound payloads for an already-subscribed `id` by calling `handler` directly from the demultiplexer task,
+    /// bypassing the per-id channel. The subscription's `recv` will no longer yield any new payloads.
+    ///
+    /// The handler runs inline inside the demux loop: a slow or blocking handler stalls delivery for every other
+    /// stream on this connection (head-of-line blocking). Keep it short and hand heavy work off to another task, or
+    /// configure a processing pool to run it on (see [`MultiplexConfig::with_processing_pool`])
+    pub fn subscribe_with_handler<F: Fn(Bytes) + Send + Sync + 'static>(&self, id: K, handler: F) -> Result<(), anyhow::Error> {
+        let handler: PayloadHandler = Arc::new(handler);
+        match self.processing.as_ref() {
+            Some(processing) => self.set_handler(id, processing.bind(handler)),
+            None => self.set_handler(id, handler)
+        }
+    }
+
+    fn set_handler(&self, id: K, handler: PayloadHandler) -> Result<(), anyhow::Error> {
+        let mut lock = self.subscribers.shard(&id).write();
+        let sender = lock.get_mut(&id).ok_or_else(|| anyhow::Error::msg("Channel ID does not exist"))?;
+        sender.handler = Some(handler);
+        Ok(())
+    }
+}
+
+impl<K: MultiplexedConnKey> Clone for MultiplexedConn<K> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+pub struct MultiplexedSubscription<'a, K: MultiplexedConnKey = SymmetricConvID> {
+    ptr: &'a MultiplexedConn<K>,
+    receiver: Option<Mutex<InboundReceiver>>,
+    id: K
+}
+
+impl<K: MultiplexedConnKey> SubscriptionBiStream for MultiplexedSubscription<'_, K> {
+    type Conn = Arc<dyn ReliableOrderedStreamToTarget + 'static>;
+    type ID = K;
+
+    fn conn(&self) -> &Self::Conn {
+        &self.ptr.conn
+    }
+
+    fn receiver(&self) -> &Mutex<InboundReceiver> {
+        self.receiver.as_ref().unwrap()
+    }
+
+    fn id(&self) -> Self::ID {
+        self.id
+    }
+
+    fn node_type(&self) -> RelativeNodeType {
+        self.ptr.node_type
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        *self.ptr.deadline.lock()
+    }
+
+    fn record_sent(&self, message: &[u8]) {
+        self.ptr.record_sent(self.id, message)
+    }
+
+    fn outbound_gate(&self) -> Option<OutboundGate> {
+        self.ptr.outbound_gate(self.id)
+    }
+
+    fn inherited_config(&self) -> Option<MultiplexConfig> {
+        Some(self.ptr.config.inherited())
+    }
+
+    fn addrs(&self) -> Option<(SocketAddr, SocketAddr)> {
+        *self.ptr.addrs.lock()
+    }
+}
+
+impl<K: MultiplexedConnKey> From<MultiplexedSubscription<'_, K>> for OwnedMultiplexedSubscription<K> {
+    fn from(mut this: MultiplexedSubscription<'_, K>) -> Self {
+        let ret = Self {
+            ptr: this.ptr.clone(),
+            receiver: this.receiver.take().unwrap(),
+            id: this.id,
+            priority: Priority::Normal,
+            on_closed: None,
+            deadline: parking_lot::Mutex::new(None)
+        };
+
+        // prevent destructor from running
+        std::mem::forget(this);
+        ret
+    }
+}
+
+pub struct OwnedMultiplexedSubscription<K: MultiplexedConnKey + 'static = SymmetricConvID> {
+    ptr: MultiplexedConn<K>,
+    receiver: Mutex<InboundReceiver>,
+    id: K,
+    priority: Priority,
+    on_closed: Option<tokio::sync::oneshot::Sender<()>>,
+    deadline: parking_lot::Mutex<Option<Instant>>
+}
+
+impl<K: MultiplexedConnKey> OwnedMultiplexedSubscription<K> {
+    /// Receives the next message, waiting up to `max_total` in all. The stream outlives any number of transport
+    /// restores within that time (see [`MultiplexConfig::with_transport_restore_grace`]), so a message sent after a
+    /// restore is delivered to the same call. Fails with `ConnectionReset` once the transport is lost for good, or with
+    /// `TimedOut` once `max_total` elapses
+    pub async fn recv_resilient(&self, max_total: std::time::Duration) -> std::io::Result<Bytes> {
+        let demux_ended = self.ptr.demux_result();
+        let recv = async {
+            tokio::select! {
+                // messages that arrived before the transport was lost are still delivered
+                biased;
+                res = self.recv() => res,
+                res = demux_ended => Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, format!("The transport was lost and not restored ({:?})", res)))
+            }
+        };
+
+        tokio::time::timeout(max_total, recv).await
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, format!("No message arrived within {:?}", max_total)))?
+    }
+
+    /// Bounds every subsequent send and receive on this stream by `deadline`, until [`Self::clear_deadline`] is called
+    /// (see [`MultiplexedConn::set_deadline`], which this composes with)
+    pub fn set_deadline(&self, deadline: Instant) {
+        *self.deadline.lock() = Some(deadline)
+    }
+
+    pub fn clear_deadline(&self) {
+        *self.deadline.lock() = None
+    }
+
+    /// Caps this stream's inbound queue at `max` queued-but-unreceived payloads (minimum 1), overriding the connection's
+    /// cap (see [`MultiplexConfig::with_max_buffered_messages`]). Pass `usize::MAX` to lift the connection's cap for this stream
+    pub fn set_max_buffered_messages(&self, max: usize) {
+        if let Some(sender) = self.ptr.subscribers.shard(&self.id).read().get(&self.id) {
+            sender.depth.max_queued.store(std::cmp::max(max, 1), Ordering::Relaxed)
+        }
+    }
+
+    /// Receives the next message, or None once the adjacent node has closed its end and every message it sent before
+    /// closing has been received. Fails if the stream ended any other way, such as by losing the transport, or if the
+    /// stream's deadline passes first.
+    ///
+    /// The Initiator announces its close only to nodes that advertise "close-notice". Against older nodes, this waits
+    /// on the Receiver like [`ReliableOrderedStreamToTarget::recv`] until the local end closes too
+    pub async fn recv_opt(&self) -> std::io::Result<Option<Bytes>> {
+        match self.recv().await {
+            Ok(payload) => Ok(Some(payload)),
+            Err(err) if err.kind() == std::io::ErrorKind::ConnectionReset && self.ptr.peer_closed.lock().contains(&self.id) => Ok(None),
+            Err(err) => Err(err)
+        }
+    }
+
+    /// Waits for the exclusive right to receive on this stream, which passes to the next waiting caller once the returned
+    /// guard drops. Callers are served in the order they began waiting. Messages that arrive between two holders stay
+    /// queued for the next, so consumers sharing the stream, such as workers of a pool, each receive a message exactly once
+    pub async fn handoff(&self) -> ReceiverGuard<'_, K> {
+        ReceiverGuard { receiver: self.receiver.lock().await, subscription: self }
+    }
+}
+
+/// The exclusive right to receive on a stream (see [`OwnedMultiplexedSubscription::handoff`]). While held, receives
+/// through the subscription itself wait for it to drop
+pub struct ReceiverGuard<'a, K: MultiplexedConnKey + 'static> {
+    receiver: tokio::sync::MutexGuard<'a, InboundReceiver>,
+    subscription: &'a OwnedMultiplexedSubscription<K>
+}
+
+impl<K: MultiplexedConnKey> ReceiverGuard<'_, K> {
+    /// Receives the next message, bounded by the stream's deadline, if any
+    pub async fn recv(&mut self) -> std::io::Result<Bytes> {
+        let deadline = self.subscription.deadline();
+        let recv = async {
+            self.receiver.recv().await.map(Bytes::from).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::ConnectionReset, "Receiver died"))
+        };
+
+        match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, recv).await
+                .unwrap_or_else(|_| Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "The stream's deadline has passed"))),
+            None => recv.await
+        }
+    }
+
+    /// The id of the stream received on
+    pub fn id(&self) -> K {
+        self.subscription.id
+    }
+}
+
+impl<K: MultiplexedConnKey> SubscriptionBiStream for OwnedMultiplexedSubscription<K> {
+    type Conn = Arc<dyn ReliableOrderedStreamToTarget + 'static>;
+    type ID = K;
+
+    fn conn(&self) -> &Self::Conn {
+        &self.ptr.conn
+    }
+
+    fn receiver(&self) -> &Mutex<InboundReceiver> {
+        &self.receiver
+    }
+
+    fn id(&self) -> Self::ID {
+        self.id
+    }
+
+    fn node_type(&self) -> RelativeNodeType {
+        self.ptr.node_type
+    }
+
+    fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    fn compact_frames(&self) -> bool {
+        self.ptr.compact_frames.load(Ordering::Relaxed)
+    }
+
+    fn fragment_size(&self) -> Option<usize> {
+        // nodes that do not advertise reassembly are sent whole messages
+        self.ptr.config.fragment_size.filter(|_| self.ptr.peer_supports("fragment"))
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        match (*self.deadline.lock(), self.ptr.deadline()) {
+            (Some(own), Some(conn)) => Some(std::cmp::min(own, conn)),
+            (own, conn) => own.or(conn)
+        }
+    }
+
+    fn record_sent(&self, message: &[u8]) {
+        self.ptr.record_sent(self.id, message)
+    }
+
+    fn outbound_gate(&self) -> Option<OutboundGate> {
+        self.ptr.outbound_gate(self.id)
+    }
+
+    #[cfg(feature = "otel")]
+    fn traces(&self) -> Option<&crate::telemetry::StreamTraces<K>> {
+        Some(&self.ptr.traces)
+    }
+
+    fn inherited_config(&self) -> Option<MultiplexConfig> {
+        Some(self.ptr.config.inherited())
+    }
+
+    fn addrs(&self) -> Option<(SocketAddr, SocketAddr)> {
+        *self.ptr.addrs.lock()
+    }
+
+    fn nested_level_attacher(&self) -> Option<NestedLevelAttacher> {
+        let (ptr, id) = (self.ptr.clone(), self.id);
+        Some(Box::new(move |level| ptr.attach_nested_level(id, level)))
+    }
+}
+
+#[async_trait]
+impl<K: MultiplexedConnKey + 'static> Subscribable for MultiplexedConn<K> {
+    type ID = K;
+    type UnderlyingConn = Arc<dyn ReliableOrderedStreamToTarget + 'static>;
+    type SubscriptionType = OwnedMultiplexedSubscription<K>;
+    //type BorrowedSubscriptionType<'a> = MultiplexedSubscription<'a, K>;
+    type BorrowedSubscriptionType = OwnedMultiplexedSubscription<K>;
+
+    fn underlying_conn(&self) -> &Self::UnderlyingConn {
+        &self.conn
+    }
+
+    fn subscriptions(&self) -> &SubscriberMap<Self::ID> {
+        &self.subscribers
+    }
+
+    fn post_close_container(&self) -> &PostActionChannel<Self::ID> {
+        &self.post_close_container
+    }
+
+    fn pre_action_container(&self) -> &PreActionChannel<Self::ID> {
+        &self.pre_open_container
+    }
+
+    async fn recv_post_close_signal_from_stream(&self, id: Self::ID) -> Result<(), Error> {
+        self.post_close_container.recv(id).await
+    }
+
+    async fn send_post_close_signal(&self, id: Self::ID) -> Result<(), Error> {
+        if self.node_type.is_initiator() && self.pool_active() {
+            // this is the last step of the close handshake. The slot is warmed before the Receiver can learn of the close,
+            // so that the Receiver's reopen always finds it warm here
+            if let Some(sender) = self.subscribers.shard(&id).write().remove(&id) {
+                self.emit_closed(id, &sender)
+            }
+
+            self.pool.keep_warm(id);
+        }
+
+        if !self.node_type.is_initiator() {
+            // kept even if the send fails, so that a restored transport lets the Initiator finish its half
+            self.handshakes.unanswered_closes.lock().insert(id);
+        }
+
+        Ok(self.conn.send_serialized(MultiplexedPacket::PostDrop { id }).await?)
+    }
+
+    async fn send_pre_open_signal(&self, id: Self::ID) -> Result<(), Error> {
+        let awaits_echo = !self.node_type.is_initiator();
+        if awaits_echo {
+            self.handshakes.unechoed_opens.lock().insert(id);
+        }
+
+        #[cfg(feature = "otel")]
+        let packet = match self.traces.on_open_signal(id).filter(|_| self.peer_supports("trace-context")) {
+            Some(trace_context) => MultiplexedPacket::PreCreateTraced { id, trace_context },
+            None => MultiplexedPacket::PreCreate { id }
+        };
+        #[cfg(not(feature = "otel"))]
+        let packet = MultiplexedPacket::PreCreate { id };
+
+        let res = self.conn.send_serialized(packet).await;
+        if res.is_err() && awaits_echo {
+            // the open fails, so there is nothing to resume
+            self.handshakes.unechoed_opens.lock().remove(&id);
+        }
+
+        Ok(res?)
+    }
+
+    fn node_type(&self) -> RelativeNodeType {
+        self.node_type
+    }
+
+    fn runtime(&self) -> Option<tokio::runtime::Handle> {
+        self.config.runtime()
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        *self.deadline.lock()
+    }
+
+    fn get_next_prereserved(&self) -> Option<Self::BorrowedSubscriptionType> {
+        let mut next_key = K::get_proposed_next(&self.current_latest_subscribed);
+        // the pre-reserved ids skipped any reserved ids, so the sequence tracked here skips them too
+        while K::is_reserved(&next_key) {
+            let _ = K::generate_next(&self.current_latest_subscribed);
+            next_key = K::get_proposed_next(&self.current_latest_subscribed);
+        }
+
+        let mut lock = self.subscribers.shard(&next_key).write();
+        let pre_reserved_stream = lock.get_mut(&next_key)?;
+        let sub = MultiplexedSubscription { ptr: self, receiver: Some(Mutex::new(pre_reserved_stream.pre_reserved_rx.take()?)), id: next_key };
+        pre_reserved_stream.depth.mark_opened();
+        assert_eq!(self.next_unpartitioned(&self.current_latest_subscribed), next_key);
+        Some(sub.into())
+    }
+
+    async fn reopen_pooled(&self) -> Result<Option<Self::BorrowedSubscriptionType>, Error> {
+        if !self.pool_active() {
+            return Ok(None)
+        }
+
+        let (id, expired) = self.pool.take();
+        self.send_pool_evictions(expired).await?;
+
+        let id = match id {
+            Some(id) => id,
+            None => return Ok(None)
+        };
+
+        let receiver = {
+            let mut lock = self.subscribers.shard(&id).write();
+            if lock.contains_key(&id) {
+                return Err(already_subscribed(id))
+            }
+
+            let (sender, receiver) = inbound_channel(&self.buffered_bytes);
+            sender.depth.mark_opened();
+            let _ = lock.insert(id, sender);
+            receiver
+        };
+
+        self.post_close_container.setup_channel(id);
+        // unlike a regular open signal, this one is not echoed
+        self.conn.send_serialized(MultiplexedPacket::PreCreate { id }).await?;
+        Ok(Some(MultiplexedSubscription { ptr: self, receiver: Some(Mutex::new(receiver)), id }.into()))
+    }
+
+    fn take_rejected_open(&self, id: Self::ID) -> Option<String> {
+        let reason = self.handshakes.rejected_opens.lock().remove(&id)?;
+        if self.node_type.is_initiator() {
+            self.handshakes.queued_opens.lock().remove(&id);
+            // the Receiver subscribed to `id` before its open was rejected, so the sequence tracked here moves past it too
+            assert_eq!(self.next_unpartitioned(&self.current_latest_subscribed), id);
+        }
+
+        Some(reason)
+    }
+
+    fn take_reopened(&self, id: Self::ID) -> Option<Self::BorrowedSubscriptionType> {
+        self.handshakes.queued_opens.lock().remove(&id);
+        let mut lock = self.subscribers.shard(&id).write();
+        let sender = lock.get_mut(&id)?;
+        let receiver = sender.pre_reserved_rx.take()?;
+        sender.depth.mark_opened();
+        Some(MultiplexedSubscription { ptr: self, receiver: Some(Mutex::new(receiver)), id }.into())
+    }
+
+    fn on_open_complete(&self, id: Self::ID) {
+        self.mark_opened(id);
+        #[cfg(feature = "otel")]
+        self.traces.on_opened(id)
+    }
+
+    async fn on_close_begin(&self, id: Self::ID) {
+        // the Receiver announces its close through the handshake itself
+        if self.node_type.is_initiator() && self.peer_supports("close-notice") {
+            if let Err(err) = self.conn.send_serialized(MultiplexedPacket::CloseNotice { id }).await {
+                log::warn!("Unable to announce the close of {:?}: {:?}", id, err);
+            }
+        }
+    }
+
+    async fn on_close_complete(&self, id: Self::ID) {
+        #[cfg(feature = "otel")]
+        self.traces.on_closed(id);
+        self.peer_closed.lock().remove(&id);
+        self.origins.lock().remove(&id);
+        let _ = self.polled.lock().receivers.remove(&id);
+
+        // the Initiator warmed the slot while sending its half of the close handshake, and it may have been reopened since
+        if self.node_type.is_initiator() && self.pool.finish_close(id) {
+            return
+        }
+
+        if let Some(sender) = self.subscribers.shard(&id).write().remove(&id) {
+            self.emit_closed(id, &sender)
+        }
+
+        if !self.node_type.is_initiator() && self.pool_active() {
+            if let Err(err) = self.send_pool_evictions(self.pool.release(id)).await {
+                log::warn!("Unable to evict pooled streams: {:?}", err)
+            }
+        }
+    }
+
+    fn subscribe(&self, id: Self::ID) -> Result<Self::BorrowedSubscriptionType, Error> {
+        let receiver = self.register_subscriber(id)?;
+        let sub = MultiplexedSubscription { ptr: self, receiver: Some(Mutex::new(receiver)), id };
+        assert_eq!(self.next_unpartitioned(&self.current_latest_subscribed), id);
+        // TODO: on GAT stabalization, remove into
+        Ok(sub.into())
+    }
+
+    fn owned_subscription(&self, id: Self::ID) -> Result<Self::SubscriptionType, Error> {
+        self.subscribe(id)
+    }
+
+    fn get_next_id(&self) -> Self::ID {
+        self.next_unpartitioned(&self.id_gen)
+    }
+}
+
+impl<K: MultiplexedConnKey + 'static> Drop for OwnedMultiplexedSubscription<K> {
+    fn drop(&mut self) {
+        // scoped subscriptions promise a prompt close
+        if let (None, Some(linger), Some(rt)) = (self.on_closed.as_ref(), self.ptr.config.close_linger, self.ptr.runtime()) {
+            let receiver = std::mem::replace(self.receiver.get_mut(), inbound_channel(&self.ptr.buffered_bytes).1);
+            return self.ptr.linger(self.id, receiver, self.priority, linger, rt)
+        }
+
+        #[cfg(feature = "otel")]
+        self.ptr.traces.on_close_started(self.id);
+        close_sequence_for_multiplexed_bistream(self.id, self.ptr.clone(), self.on_closed.take())
+    }
+}
+
+/// Removes the local end of a scoped open unless disarmed once the open completes
+struct ScopedOpen<'a, K: MultiplexedConnKey + 'static> {
+    conn: &'a MultiplexedConn<K>,
+    id: K,
+    armed: bool
+}
+
+impl<K: MultiplexedConnKey + 'static> Drop for ScopedOpen<'_, K> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return
+        }
+
+        let id = self.id;
+        let _ = self.conn.subscribers.shard(&id).write().remove(&id);
+        self.conn.post_close_container.discard_channel(id);
+    }
+}
+
+/// A substream bound to the scope that owns it (see [`MultiplexedConn::scoped_subscription`]). Ending the scope in any
+/// way, including by panicking, starts the close sequence, and [`MultiplexedConn::join_scoped_closes`] waits for it to
+/// finish. [`Self::close`] closes the stream in place
+pub struct ScopedSubscription<K: MultiplexedConnKey + 'static = SymmetricConvID> {
+    inner: Option<OwnedMultiplexedSubscription<K>>,
+    closed: Option<tokio::sync::oneshot::Receiver<()>>
+}
+
+impl<K: MultiplexedConnKey + 'static> ScopedSubscription<K> {
+    /// Closes the stream, returning once both nodes have completed the close handshake
+    pub async fn close(mut self) -> Result<(), anyhow::Error> {
+        let closed = self.closed.take();
+        drop(self.inner.take());
+        match closed {
+            Some(closed) => closed.await.map_err(|_| anyhow::Error::msg("The close sequence was abandoned")),
+            None => Ok(())
+        }
+    }
+}
+
+impl<K: MultiplexedConnKey + 'static> std::ops::Deref for ScopedSubscription<K> {
+    type Target = OwnedMultiplexedSubscription<K>;
+
+    fn deref(&self) -> &Self::Target {
+        // only taken by `close` and `drop`
+        self.inner.as_ref().unwrap()
+    }
+}
+
+impl<K: MultiplexedConnKey + 'static> Drop for ScopedSubscription<K> {
+    fn drop(&mut self) {
+        if let (Some(inner), Some(closed)) = (self.inner.take(), self.closed.take()) {
+            let conn = inner.ptr.clone();
+            drop(inner);
+            let mut scoped_closes = conn.scoped_closes.lock();
+            // those that already finished closing are pruned here, so that the list stays bounded without joins
+            scoped_closes.retain_mut(|closed| matches!(closed.try_recv(), Err(tokio::sync::oneshot::error::TryRecvError::Empty)));
+            scoped_closes.push(closed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::test_utils::{create_streams, create_streams_with_config, create_channel_streams_with_config, create_framed_pair, create_streams_with_addrs, channel_pair, open_pair, drain_prereserved};
+    use crate::sync::network_application::INITIAL_CAPACITY;
+    use crate::sync::network_endpoint::NetworkEndpoint;
+    use crate::reliable_conn::ConnAddr;
+    use crate::reliable_conn::ReliableOrderedStreamToTargetExt;
+    use crate::sync::network_application::NetworkApplication;
+    use crate::sync::subscription::{Subscribable, SubscriptionBiStreamExt, SubscriptionBiStream};
+    use serde::{Serialize, Deserialize};
+    use crate::multiplex::{OwnedMultiplexedSubscription, StreamCounters, SubStats, TopologyNode, SubscriberMap, inbound_channel, SharedPayloadEncoder, MultiplexedPacket, ApplicationLayerRef, StreamEvent, StreamSummary, decode_packet, DecodeError, encode_compact_frame};
+    use crate::negotiation::Capabilities;
+    use crate::sync::{SymmetricConvID, RelativeNodeType};
+    use crate::config::{MultiplexConfig, EarlyDataPolicy, DecodeErrorPolicy, EvictionPolicy};
+    use crate::reliable_conn::{StreamWrapper, ReliableOrderedStreamToTarget, Direction};
+    use bytes::Bytes;
+    use async_recursion::async_recursion;
+    use std::time::Duration;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::collections::HashMap;
+    use crate::sync::priority::Priority;
+    use tokio::time::Instant;
+    use futures::FutureExt;
+
+    #[derive(Serialize, Deserialize)]
+    struct Packet(usize);
+
+    #[tokio::test]
+    async fn nested_multiplexed_stream() {
+
+        let (outer_stream_server, outer_stream_client) = create_streams().await;
+        // 50 recursions deep ....
+        nested(0, 50, outer_stream_server, outer_stream_client).await;
+    }
+
+    #[tokio::test]
+    async fn handler_subscription() {
+        let (server_stream, client_stream) = create_streams().await;
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let server = tokio::spawn(async move {
+            let next_stream: OwnedMultiplexedSubscription = server_stream.initiate_subscription().await.unwrap();
+            server_stream.subscribe_with_handler(next_stream.id(), move |payload| { let _ = tx.send(payload); }).unwrap();
+            // let the client know the handler is in place
+            next_stream.send_serialized(Packet(0)).await.unwrap();
+
+            for idx in 0..10 {
+                let payload = rx.recv().await.unwrap();
+                assert_eq!(bincode2::deserialize::<Packet>(&payload).unwrap().0, idx);
+            }
+        });
+
+        let client = tokio::spawn(async move {
+            let next_stream: OwnedMultiplexedSubscription = client_stream.initiate_subscription().await.unwrap();
+            let _ = next_stream.recv_serialized::<Packet>().await.unwrap();
+
+            for idx in 0..10 {
+                next_stream.send_serialized(Packet(idx)).await.unwrap();
+            }
+        });
+
+        let (r0, r1) = tokio::join!(server, client);
+        r0.unwrap();
+        r1.unwrap();
+    }
+
+    #[tokio::test]
+    async fn processing_pool() {
+        // opens four streams whose payloads are handled on a pool of `threads`, each by `on_payload` before it is
+        // reported, then sends five payloads on each and returns the order they were reported in, after checking that
+        // every stream's are handled in order
+        async fn handle_on_pool(threads: usize, on_payload: Arc<dyn Fn(u64) + Send + Sync>) -> Vec<(SymmetricConvID, u64)> {
+            let (server, client) = create_channel_streams_with_config(MultiplexConfig::new().with_processing_pool(threads)).await;
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+            let mut streams = Vec::new();
+            for _ in 0..4 {
+                let (server_sub, client_sub) = open_pair(&server, &client).await;
+                let (tx, id, on_payload) = (tx.clone(), client_sub.id(), on_payload.clone());
+                client.subscribe_with_handler(id, move |payload| {
+                    let seq = bincode2::deserialize::<u64>(&payload).unwrap();
+                    (on_payload)(seq);
+                    let _ = tx.send((id, seq));
+                }).unwrap();
+                streams.push((server_sub, client_sub));
+            }
+
+            for seq in 0..5u64 {
+                for (server_sub, _) in &streams {
+                    server_sub.send_serialized(seq).await.unwrap();
+                }
+            }
+
+            let mut next_seq = HashMap::new();
+            let mut reported = Vec::new();
+            for _ in 0..20 {
+                let (id, seq) = rx.recv().await.unwrap();
+                let expected = next_seq.entry(id).or_insert(0u64);
+                assert_eq!(seq, *expected);
+                *expected += 1;
+                reported.push((id, seq));
+            }
+
+            reported
+        }
+
+        // a single thread handles one payload at a time, so every payload is handled in the order it arrived
+        let (running, most) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let reported = handle_on_pool(1, Arc::new({
+            let (running, most) = (running.clone(), most.clone());
+            move |_| {
+                most.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                running.fetch_sub(1, Ordering::SeqCst);
+            }
+        })).await;
+        assert_eq!(most.load(Ordering::SeqCst), 1);
+        assert!(reported.iter().enumerate().all(|(idx, (_, seq))| *seq == (idx / 4) as u64));
+        assert!(reported.chunks(4).all(|round| round.iter().map(|(id, _)| *id).eq(reported[..4].iter().map(|(id, _)| *id))));
+
+        // four threads handle the first payloads of the four streams at once: each handler reports that it began, then
+        // blocks until released, which happens only once all four have begun. Throughput scaling with the number of
+        // threads is checked only by this proxy, since timing the handlers would be at the mercy of the machine
+        let (started_tx, mut started_rx) = tokio::sync::mpsc::unbounded_channel();
+        let release = Arc::new(std::sync::Barrier::new(5));
+        let on_payload = Arc::new({
+            let release = release.clone();
+            move |seq| {
+                if seq == 0 {
+                    started_tx.send(()).unwrap();
+                    release.wait();
+                }
+            }
+        });
+
+        tokio::join!(handle_on_pool(4, on_payload), async move {
+            for _ in 0..4 {
+                started_rx.recv().await.unwrap();
+            }
+
+            tokio::task::spawn_blocking(move || { release.wait(); }).await.unwrap();
+        });
+    }
+
+    #[tokio::test]
+    async fn processing_pool_handler_panics() {
+        let (server, client) = create_streams_with_config(MultiplexConfig::new().with_processing_pool(1)).await;
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut streams = Vec::new();
+        // both streams are bound to the pool's only thread
+        for panics in [true, false] {
+            let (server_sub, client_sub) = open_pair(&server, &client).await;
+            let (tx, id) = (tx.clone(), client_sub.id());
+            client.subscribe_with_handler(id, move |payload| {
+                let seq = bincode2::deserialize::<u64>(&payload).unwrap();
+                assert!(!(panics && seq == 0), "The handler failed");
+                let _ = tx.send((id, seq));
+            }).unwrap();
+            streams.push((server_sub, client_sub));
+        }
+
+        for seq in 0..3u64 {
+            for (server_sub, _) in &streams {
+                server_sub.send_serialized(seq).await.unwrap();
+            }
+        }
+
+        // only the payload whose handler panicked is lost
+        let mut handled = Vec::new();
+        for _ in 0..5 {
+            handled.push(tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap());
+        }
+
+        let (panicking, healthy) = (streams[0].1.id(), streams[1].1.id());
+        assert_eq!(handled.iter().filter(|(id, _)| *id == panicking).map(|(_, seq)| *seq).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(handled.iter().filter(|(id, _)| *id == healthy).map(|(_, seq)| *seq).collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn substream_into_connection() {
+        let (server_stream, client_stream) = create_streams_with_addrs().await;
+
+        // promotes a substream of a level nested over `endpoint`, then runs a level over the promoted connection
+        async fn promoted_level(endpoint: NetworkEndpoint) -> NetworkEndpoint {
+            let (local_addr, peer_addr) = (endpoint.local_addr().unwrap(), endpoint.peer_addr().unwrap());
+            let outer: OwnedMultiplexedSubscription = endpoint.initiate_subscription().await.unwrap();
+            let nested = outer.multiplex::<SymmetricConvID>().await.unwrap();
+            let next_stream: OwnedMultiplexedSubscription = nested.initiate_subscription().await.unwrap();
+            let node_type = next_stream.node_type();
+            // the promoted connection has no knowledge that it is a substream, but keeps the addrs of the connection beneath
+            let conn = next_stream.into_connection();
+            assert_eq!((conn.local_addr().unwrap(), conn.peer_addr().unwrap()), (local_addr, peer_addr));
+            NetworkEndpoint::register(node_type, conn).await.unwrap()
+        }
+
+        let server = tokio::spawn(async move {
+            let next_level = promoted_level(server_stream).await;
+            let stream: OwnedMultiplexedSubscription = next_level.initiate_subscription().await.unwrap();
+            stream.send_serialized(Packet(100)).await.unwrap();
+            assert_eq!(stream.recv_serialized::<Packet>().await.unwrap().0, 101);
+        });
+
+        let client = tokio::spawn(async move {
+            let next_level = promoted_level(client_stream).await;
+            let stream: OwnedMultiplexedSubscription = next_level.initiate_subscription().await.unwrap();
+            assert_eq!(stream.recv_serialized::<Packet>().await.unwrap().0, 100);
+            stream.send_serialized(Packet(101)).await.unwrap();
+        });
+
+        let (r0, r1) = tokio::join!(server, client);
+        r0.unwrap();
+        r1.unwrap();
+    }
+
+    #[tokio::test]
+    async fn initiator_tie_breaker() {
+        let (server_stream, client_stream) = create_streams().await;
+        assert!(!server_stream.is_initiator());
+        assert!(client_stream.is_initiator());
+
+        let (server_sub, client_sub) = open_pair(&server_stream, &client_stream).await;
+        assert!(!server_sub.is_initiator());
+        assert!(client_sub.is_initiator());
+    }
+
+    #[tokio::test]
+    async fn topology() {
+        let (server_stream, client_stream) = create_streams().await;
+        let root = server_stream.clone();
+        // each level opens two substreams which are both multiplexed, but only the first is recursed into
+        let _levels = nested(0, 2, server_stream, client_stream).await;
+
+        fn depth(node: &TopologyNode) -> usize {
+            1 + node.children.iter().map(|child| depth(&child.node)).max().unwrap_or(0)
+        }
+
+        let topology = root.topology();
+        assert_eq!(topology.children.len(), 2);
+        assert_eq!(depth(&topology), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn early_data_expires() {
+        let config = MultiplexConfig::new().with_early_data_policy(EarlyDataPolicy::Buffer { max_frames_per_id: 1, max_pending_ids: 1, timeout: Duration::from_secs(1) });
+        let conn = NetworkApplication::new_with_config(RelativeNodeType::Receiver, StreamWrapper::from(tokio::io::duplex(64).0), config);
+        conn.buffer_early_frame(SymmetricConvID::from(100), vec![]).unwrap();
+        // bounded per-id and by the number of pending ids
+        assert!(conn.buffer_early_frame(SymmetricConvID::from(100), vec![]).is_err());
+        assert!(conn.buffer_early_frame(SymmetricConvID::from(101), vec![]).is_err());
+
+        tokio::time::advance(Duration::from_secs(2)).await;
+        // the expired frames for id 100 free up room
+        conn.buffer_early_frame(SymmetricConvID::from(101), vec![]).unwrap();
+        assert!(!conn.early_data.lock().contains_key(&SymmetricConvID::from(100)));
+    }
+
+    /// A transport that never yields any data until it is told to fail
+    struct KillableConn {
+        kill: tokio::sync::Mutex<tokio::sync::mpsc::UnboundedReceiver<std::io::Error>>
+    }
+
+    #[async_trait::async_trait]
+    impl ReliableOrderedStreamToTarget for KillableConn {
+        async fn send_to_peer(&self, _input: &[u8]) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        async fn recv(&self) -> std::io::Result<Bytes> {
+            Err(self.kill.lock().await.recv().await.unwrap())
+        }
+
+        fn peer_alive(&self) -> Option<bool> {
+            Some(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn subscriptions_outlive_conn() {
+        let (server, client) = create_streams().await;
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let (server_demux, client_demux) = (server.demux_result(), client.demux_result());
+        let (server_inner, client_inner) = (server.downgrade(), client.downgrade());
+        drop((server, client));
+
+        // the subscriptions' own handles keep both connections and their demultiplexers running
+        server_sub.send_serialized(Packet(0)).await.unwrap();
+        assert_eq!(client_sub.recv_serialized::<Packet>().await.unwrap().0, 0);
+        client_sub.send_serialized(Packet(1)).await.unwrap();
+        assert_eq!(server_sub.recv_serialized::<Packet>().await.unwrap().0, 1);
+
+        drop((server_sub, client_sub));
+        let server_outcome = tokio::time::timeout(Duration::from_secs(5), server_demux).await.unwrap();
+        let client_outcome = tokio::time::timeout(Duration::from_secs(5), client_demux).await.unwrap();
+
+        // the goodbye is written before the transport is shut down, so whichever side tears down second reads it before the EOF
+        server_outcome.unwrap();
+        client_outcome.unwrap();
+
+        assert!(server_inner.upgrade().is_none() && client_inner.upgrade().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drain_received() {
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default()).await;
+        let server_subs = [server.get_next_prereserved().unwrap(), server.get_next_prereserved().unwrap()];
+        let client_subs = [client.get_next_prereserved().unwrap(), client.get_next_prereserved().unwrap()];
+        for sub in &client_subs {
+            client.subscribe_polled(sub.id()).unwrap();
+        }
+
+        assert!(client.drain_received().is_empty());
+        for idx in 0..10 {
+            server_subs[idx % 2].send_serialized(Packet(idx)).await.unwrap();
+        }
+
+        settle().await;
+        let received = client.drain_received();
+        assert_eq!(received.len(), 10);
+        for (idx, (id, payload)) in received.into_iter().enumerate() {
+            assert_eq!(id, client_subs[idx % 2].id());
+            assert_eq!(bincode2::deserialize::<Packet>(&payload).unwrap().0, idx);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn poll_once() {
+        let (server_conn, client_conn) = channel_pair();
+        let (server, client) = tokio::join!(
+            NetworkApplication::register(RelativeNodeType::Receiver, server_conn),
+            NetworkApplication::register_polled(RelativeNodeType::Initiator, client_conn, MultiplexConfig::new().with_max_buffered_messages(4))
+        );
+
+        let (server, client) = (server.unwrap(), client.unwrap());
+        let mut events = client.events();
+        let server_subs = [server.get_next_prereserved().unwrap(), server.get_next_prereserved().unwrap()];
+        let client_subs = [client.get_next_prereserved().unwrap(), client.get_next_prereserved().unwrap()];
+        for sub in &client_subs {
+            client.subscribe_polled(sub.id()).unwrap();
+        }
+
+        // nothing is routed, nor spawned to route, until polled
+        for idx in 0..6 {
+            server_subs[idx % 2].send_serialized(Packet(idx)).await.unwrap();
+        }
+
+        settle().await;
+        assert!(client.drain_received().is_empty());
+
+        client.poll_once().await.unwrap();
+        let received = client.drain_received();
+        assert_eq!(received.len(), 6);
+        for (idx, (id, payload)) in received.into_iter().enumerate() {
+            assert_eq!(id, client_subs[idx % 2].id());
+            assert_eq!(bincode2::deserialize::<Packet>(&payload).unwrap().0, idx);
+        }
+
+        // payloads left undrained count against the stream's queue limit
+        let id = client_subs[0].id();
+        for idx in 0..5 {
+            server_subs[0].send_serialized(Packet(idx)).await.unwrap();
+        }
+
+        settle().await;
+        client.poll_once().await.unwrap();
+        match events.try_recv() {
+            Ok(StreamEvent::Evicted { id: evicted }) => assert_eq!(evicted, id),
+            event => panic!("Unexpected event {:?}", event)
+        }
+
+        assert!(client.drain_received().is_empty());
+        assert_eq!(client.buffered_bytes(), 0);
+
+        // polling carries the closes through, and once the adjacent level says goodbye, polling ends. Each tick routes
+        // whatever the server sent in answer to the last
+        drop((server_subs, client_subs));
+        drop(server);
+        let err = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                settle().await;
+                if let Err(err) = client.poll_once().await {
+                    break err
+                }
+            }
+        }).await.unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionAborted);
+        assert!(client.demux_result().now_or_never().is_some());
+    }
+
+    #[tokio::test]
+    async fn reserved_ids() {
+        let (_server, client) = create_streams().await;
+        let reserved = SymmetricConvID::from(0);
+        assert_eq!(client.reserved_ids(), vec![reserved]);
+
+        // wraps around onto the reserved id, which is skipped
+        client.id_gen.store(u64::MAX, std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(client.get_next_id(), SymmetricConvID::from(1));
+
+        let packet = bincode2::serialize(&MultiplexedPacket::ApplicationLayer { id: reserved, payload: vec![1] }).unwrap();
+        assert!(client.forward_packet(&packet).await.is_err());
+        assert!(client.early_data.lock().get(&reserved).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn scoped_subscription() {
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default()).await;
+        let id = SymmetricConvID::from(1000);
+        let (server_sub, client_sub) = tokio::join!(server.scoped_subscription(id), client.scoped_subscription(id));
+        let (server_sub, client_sub) = (server_sub.unwrap(), client_sub.unwrap());
+
+        let scope = tokio::spawn(async move {
+            server_sub.send_serialized(1u64).await.unwrap();
+            panic!("The scope failed")
+        });
+
+        assert!(scope.await.unwrap_err().is_panic());
+        assert_eq!(client_sub.recv_serialized::<u64>().await.unwrap(), 1);
+
+        // the close began as the scope unwound, and completes once the adjacent node closes its end
+        drop(client_sub);
+        tokio::time::timeout(Duration::from_secs(5), server.join_scoped_closes()).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), client.join_scoped_closes()).await.unwrap();
+        assert!(!server.subscribers.shard(&id).read().contains_key(&id));
+
+        // reserved ids cannot be opened
+        assert!(server.scoped_subscription(SymmetricConvID::from(0)).await.is_err());
+
+        // an explicit close returns once the handshake completes, and the finished close is pruned on the next push
+        let (server_sub, client_sub) = tokio::join!(server.scoped_subscription(id), client.scoped_subscription(id));
+        let (server_sub, client_sub) = (server_sub.unwrap(), client_sub.unwrap());
+        drop(client_sub);
+        server_sub.close().await.unwrap();
+        assert!(!server.subscribers.shard(&id).read().contains_key(&id));
+
+        let (server_sub, client_sub) = tokio::join!(server.scoped_subscription(id), client.scoped_subscription(id));
+        let (server_sub, client_sub) = (server_sub.unwrap(), client_sub.unwrap());
+        drop(server_sub);
+        drop(client_sub);
+        let finished = || server.scoped_closes.lock().iter_mut().all(|closed| !matches!(closed.try_recv(), Err(tokio::sync::oneshot::error::TryRecvError::Empty)));
+        settle().await;
+        assert!(finished());
+
+        let (server_sub, client_sub) = tokio::join!(server.scoped_subscription(id), client.scoped_subscription(id));
+        drop(server_sub.unwrap());
+        assert_eq!(server.scoped_closes.lock().len(), 1);
+        drop(client_sub);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn scoped_subscription_cancelled() {
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default()).await;
+        let id = SymmetricConvID::from(1000);
+
+        // the adjacent node never opens its end, so the open is dropped while awaiting it
+        assert!(tokio::time::timeout(Duration::from_millis(100), server.scoped_subscription(id)).await.is_err());
+        assert!(!server.subscribers.shard(&id).read().contains_key(&id));
+        assert!(server.post_close_container.recv(id).now_or_never().unwrap().is_err());
+
+        let (server_sub, client_sub) = tokio::join!(server.scoped_subscription(id), client.scoped_subscription(id));
+        let (_server_sub, _client_sub) = (server_sub.unwrap(), client_sub.unwrap());
+    }
+
+    #[tokio::test]
+    async fn open_past_scoped_id() {
+        let (server, client) = create_streams().await;
+        let mut held = drain_prereserved(&server);
+        held.extend(drain_prereserved(&client));
+
+        let id = SymmetricConvID::from(INITIAL_CAPACITY as u64 + 3);
+        let (server_sub, client_sub) = tokio::join!(server.scoped_subscription(id), client.scoped_subscription(id));
+        let (server_scoped, client_scoped) = (server_sub.unwrap(), client_sub.unwrap());
+
+        // the generators on both nodes skip the live scoped id, so each open pairs with its own counterpart
+        let mut opened = Vec::new();
+        for _ in 0..5 {
+            let (server_sub, client_sub) = tokio::time::timeout(Duration::from_secs(5), open_pair(&server, &client)).await.unwrap();
+            assert_eq!(server_sub.id(), client_sub.id());
+            assert_ne!(server_sub.id(), id);
+            server_sub.send_serialized(u64::from(server_sub.id())).await.unwrap();
+            assert_eq!(client_sub.recv_serialized::<u64>().await.unwrap(), u64::from(client_sub.id()));
+            opened.push((server_sub, client_sub));
+        }
+
+        assert!(opened.iter().any(|(server_sub, _)| u64::from(server_sub.id()) > u64::from(id)));
+        client_scoped.send_serialized(7u64).await.unwrap();
+        assert_eq!(server_scoped.recv_serialized::<u64>().await.unwrap(), 7);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn close_linger() {
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::new().with_close_linger(Duration::from_millis(300))).await;
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let id = server_sub.id();
+
+        // messages sent while the stream lingers are kept for the reacquired subscription
+        drop(server_sub);
+        client_sub.send_serialized(1u64).await.unwrap();
+        let server_sub = server.reacquire(id).unwrap();
+        assert!(server.reacquire(id).is_none());
+
+        // the close was cancelled before the Receiver signalled it to the adjacent node
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(server.pending_handshakes().closes.is_empty());
+        assert_eq!(server_sub.recv_serialized::<u64>().await.unwrap(), 1);
+        server_sub.send_serialized(2u64).await.unwrap();
+        assert_eq!(client_sub.recv_serialized::<u64>().await.unwrap(), 2);
+
+        // without a reacquire, the close still runs once the window ends
+        drop(server_sub);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(server.pending_handshakes().closes.is_empty());
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(server.pending_handshakes().closes, vec![id]);
+        assert!(server.reacquire(id).is_none());
+
+        let mut events = server.events();
+        drop(client_sub);
+        match events.recv().await.unwrap() {
+            StreamEvent::Closed { summary } => assert_eq!(summary.id, id),
+            event => panic!("Unexpected event {:?}", event)
+        }
+
+        assert!(!server.subscribers.shard(&id).read().contains_key(&id));
+    }
+
+    #[tokio::test]
+    async fn recv_opt() {
+        let (server, client) = create_streams().await;
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+
+        // the messages sent ahead of the close are received first
+        server_sub.send_serialized(1u64).await.unwrap();
+        server_sub.send_serialized(2u64).await.unwrap();
+        drop(server_sub);
+
+        assert_eq!(&client_sub.recv_opt().await.unwrap().unwrap()[..], &bincode2::serialize(&1u64).unwrap()[..]);
+        assert_eq!(&client_sub.recv_opt().await.unwrap().unwrap()[..], &bincode2::serialize(&2u64).unwrap()[..]);
+        assert!(client_sub.recv_opt().await.unwrap().is_none());
+        assert!(client_sub.recv_opt().await.unwrap().is_none());
+        drop(client_sub);
+
+        // the Initiator announces its close too
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        client_sub.send_serialized(3u64).await.unwrap();
+        drop(client_sub);
+        assert_eq!(&server_sub.recv_opt().await.unwrap().unwrap()[..], &bincode2::serialize(&3u64).unwrap()[..]);
+        assert!(tokio::time::timeout(Duration::from_secs(5), server_sub.recv_opt()).await.unwrap().unwrap().is_none());
+        drop(server_sub);
+
+        // a deadline passing on a stream the adjacent node closed is still an error
+        let (_server_sub, client_sub) = open_pair(&server, &client).await;
+        client.peer_closed.lock().insert(client_sub.id());
+        client_sub.set_deadline(Instant::now() + Duration::from_millis(50));
+        assert_eq!(client_sub.recv_opt().await.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        client.peer_closed.lock().remove(&client_sub.id());
+        drop(client_sub);
+
+        // a stream that ends without the adjacent node closing it is still an error
+        let (_server_sub, client_sub) = open_pair(&server, &client).await;
+        client.subscribers.shard(&client_sub.id()).write().remove(&client_sub.id());
+        assert_eq!(client_sub.recv_opt().await.unwrap_err().kind(), std::io::ErrorKind::ConnectionReset);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn receiver_handoff() {
+        const MESSAGES: u64 = 1000;
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default()).await;
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let client_sub = Arc::new(client_sub);
+
+        let sender = tokio::spawn(async move {
+            for seq in 0..MESSAGES {
+                server_sub.send_serialized(seq).await.unwrap();
+            }
+            server_sub
+        });
+
+        // each worker takes the receive rights, receives a few messages, then hands the rights to the other, which is
+        // already waiting for them
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let workers = (0..2).map(|worker| {
+            let (client_sub, tx) = (client_sub.clone(), tx.clone());
+            tokio::spawn(async move {
+                loop {
+                    let mut guard = client_sub.handoff().await;
+                    for _ in 0..7 {
+                        let seq: u64 = bincode2::deserialize(&guard.recv().await.unwrap()).unwrap();
+                        tx.send((worker, seq)).unwrap();
+                    }
+                    drop(guard);
+                }
+            })
+        }).collect::<Vec<_>>();
+
+        let mut received = Vec::new();
+        let mut per_worker = [0usize; 2];
+        while received.len() < MESSAGES as usize {
+            let (worker, seq) = rx.recv().await.unwrap();
+            per_worker[worker] += 1;
+            received.push(seq);
+        }
+
+        // nothing was lost or received twice, and the handoffs preserved the order the messages were sent in
+        assert_eq!(received, (0..MESSAGES).collect::<Vec<_>>());
+        assert!(per_worker.iter().all(|count| *count > 0));
+        settle().await;
+        assert!(rx.try_recv().is_err());
+
+        workers.iter().for_each(|worker| worker.abort());
+        let _server_sub = sender.await.unwrap();
+    }
+
+    #[test]
+    fn take_stats_loses_no_counts() {
+        let counters = Arc::new(StreamCounters::default());
+        let writers = (0..4).map(|_| {
+            let counters = counters.clone();
+            std::thread::spawn(move || (0..100_000).for_each(|_| counters.record_sent(3)))
+        }).collect::<Vec<_>>();
+
+        let mut total = SubStats::default();
+        let mut add = |taken: SubStats| {
+            total.messages_sent += taken.messages_sent;
+            total.bytes_sent += taken.bytes_sent;
+        };
+
+        while !writers.iter().all(|writer| writer.is_finished()) {
+            add(counters.take());
+        }
+
+        writers.into_iter().for_each(|writer| writer.join().unwrap());
+        add(counters.take());
+        assert_eq!((total.messages_sent, total.bytes_sent), (400_000, 1_200_000));
+        assert_eq!(counters.snapshot(), SubStats::default());
+    }
+
+    #[tokio::test]
+    async fn stream_stats() {
+        let (server, client) = create_streams().await;
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let id = server_sub.id();
+        assert_eq!(server.stats(id), Some(SubStats::default()));
+        assert!(server.stats(SymmetricConvID::from(u64::MAX - 1)).is_none());
+
+        for _ in 0..3 {
+            server_sub.send_to_peer(&[0u8; 10]).await.unwrap();
+        }
+
+        for _ in 0..3 {
+            client_sub.recv().await.unwrap();
+        }
+
+        // reading does not reset the counters
+        assert_eq!(server.stats(id).unwrap(), SubStats { messages_sent: 3, bytes_sent: 30, ..Default::default() });
+        assert_eq!(client.stats(id).unwrap(), SubStats { messages_received: 3, bytes_received: 30, ..Default::default() });
+
+        assert_eq!(server.take_stats(id).unwrap().messages_sent, 3);
+        server_sub.send_to_peer(&[0u8; 5]).await.unwrap();
+        assert_eq!(server.take_stats(id).unwrap(), SubStats { messages_sent: 1, bytes_sent: 5, ..Default::default() });
+        assert_eq!(server.take_stats(id).unwrap(), SubStats::default());
+    }
+
+    #[tokio::test]
+    async fn snapshot_subscribers() {
+        let (server, client) = create_streams().await;
+        assert!(server.snapshot_subscribers().is_empty());
+
+        let mut subs = Vec::new();
+        for _ in 0..3 {
+            let (server_sub, client_sub) = open_pair(&server, &client).await;
+            subs.push((server_sub, client_sub));
+        }
+
+        for (idx, (server_sub, client_sub)) in subs.iter().enumerate() {
+            for _ in 0..idx {
+                server_sub.send_to_peer(&[0u8; 10]).await.unwrap();
+                client_sub.recv().await.unwrap();
+            }
+        }
+
+        let mut snapshot = server.snapshot_subscribers();
+        snapshot.sort_by_key(|(id, _)| *id);
+        let expected = subs.iter().enumerate().map(|(idx, (server_sub, _))| (server_sub.id(), SubStats { messages_sent: idx as u64, bytes_sent: 10 * idx as u64, ..Default::default() })).collect::<Vec<_>>();
+        assert_eq!(snapshot, expected);
+    }
+
+    #[tokio::test]
+    async fn tap() {
+        let (server, client) = create_streams().await;
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let id = server_sub.id();
+        let (mut first, mut second) = (server.tap(id), server.tap(id));
+
+        client_sub.send_to_peer(b"ping").await.unwrap();
+        assert_eq!(&server_sub.recv().await.unwrap()[..], b"ping");
+        server_sub.send_to_peer(b"pong").await.unwrap();
+        assert_eq!(&client_sub.recv().await.unwrap()[..], b"pong");
+
+        for tap in [&mut first, &mut second] {
+            assert_eq!(tap.recv().await.unwrap(), (Direction::Received, Bytes::from_static(b"ping")));
+            assert_eq!(tap.recv().await.unwrap(), (Direction::Sent, Bytes::from_static(b"pong")));
+        }
+
+        // a dropped tap is removed by the next message
+        drop(second);
+        server_sub.send_to_peer(b"again").await.unwrap();
+        assert_eq!(first.recv().await.unwrap(), (Direction::Sent, Bytes::from_static(b"again")));
+        assert_eq!(server.subscribers.shard(&id).read().get(&id).unwrap().taps.lock().len(), 1);
+
+        // nothing is mirrored for an id that is not open
+        assert!(server.tap(SymmetricConvID::from(u64::MAX - 1)).recv().await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stream_summary() {
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default()).await;
+        let (mut server_events, mut client_events) = (server.events(), client.events());
+        // the pre-reserved stream was created as the connection registered, but opens only now
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let before_open = Instant::now();
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let id = server_sub.id();
+
+        for _ in 0..3 {
+            server_sub.send_to_peer(&[0u8; 10]).await.unwrap();
+            client_sub.recv().await.unwrap();
+        }
+
+        // taking the interval counters leaves the lifetime totals intact
+        assert_eq!(server.take_stats(id).unwrap().messages_sent, 3);
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop((server_sub, client_sub));
+
+        async fn closed(events: &mut tokio::sync::mpsc::UnboundedReceiver<StreamEvent<SymmetricConvID>>) -> StreamSummary<SymmetricConvID> {
+            match tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap() {
+                StreamEvent::Closed { summary } => summary,
+                event => panic!("Unexpected event {:?}", event)
+            }
+        }
+
+        let (server_summary, client_summary) = (closed(&mut server_events).await, closed(&mut client_events).await);
+        assert_eq!((server_summary.id, client_summary.id), (id, id));
+        assert_eq!(server_summary.stats, SubStats { messages_sent: 3, bytes_sent: 30, ..Default::default() });
+        assert_eq!(client_summary.stats, SubStats { messages_received: 3, bytes_received: 30, ..Default::default() });
+        assert!(server_summary.duration() >= Duration::from_millis(10));
+        assert!(server_summary.opened >= before_open && client_summary.opened >= before_open);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stream_summary_paused_clock() {
+        let (server_conn, client_conn) = channel_pair();
+        let (server, client) = tokio::join!(NetworkApplication::register(RelativeNodeType::Receiver, server_conn), NetworkApplication::register(RelativeNodeType::Initiator, client_conn));
+        let (server, client) = (server.unwrap(), client.unwrap());
+        let mut server_events = server.events();
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+
+        // both ends of the lifetime are read from the same clock, which only the sleep advances
+        tokio::time::sleep(Duration::from_secs(3600)).await;
+        drop((server_sub, client_sub));
+        let summary = match server_events.recv().await.unwrap() {
+            StreamEvent::Closed { summary } => summary,
+            event => panic!("Unexpected event {:?}", event)
+        };
+
+        assert!(summary.duration() >= Duration::from_secs(3600) && summary.duration() < Duration::from_secs(3601), "{:?}", summary.duration());
+    }
+
+    #[tokio::test]
+    async fn duplicate_subscribe() {
+        let (server, client) = create_streams().await;
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+
+        let err = client.subscribe(client_sub.id()).err().unwrap();
+        assert!(err.to_string().contains("already subscribed"));
+        assert!(client.owned_subscription(client_sub.id()).is_err());
+
+        // the existing subscription still receives
+        server_sub.send_to_peer(b"still routed").await.unwrap();
+        assert_eq!(&client_sub.recv().await.unwrap()[..], b"still routed");
+    }
+
+    #[tokio::test]
+    async fn lru_eviction() {
+        let (server, client) = create_streams_with_config(MultiplexConfig::new().with_max_buffered_bytes(4096).with_eviction_policy(EvictionPolicy::Lru)).await;
+        let mut server_events = server.events();
+        let mut subs = Vec::new();
+        for _ in 0..3 {
+            let (server_sub, client_sub) = open_pair(&server, &client).await;
+            subs.push((server_sub, client_sub));
+        }
+
+        let (oldest, idle, active) = (&subs[0], &subs[1], &subs[2]);
+        active.0.send_to_peer(b"ping").await.unwrap();
+        assert_eq!(&active.1.recv().await.unwrap()[..], b"ping");
+
+        for (server_sub, _) in [oldest, idle] {
+            for _ in 0..2 {
+                server_sub.send_to_peer(&[0u8; 1000]).await.unwrap();
+            }
+        }
+
+        // the active stream was received on most recently, but the oldest idle stream least recently
+        active.0.send_to_peer(&[1u8; 1000]).await.unwrap();
+        assert_eq!(&active.1.recv().await.unwrap()[..], &[1u8; 1000][..]);
+        assert_eq!(client.buffered_bytes(), 2000);
+
+        assert_eq!(oldest.1.recv().await.unwrap_err().kind(), std::io::ErrorKind::ConnectionReset);
+        assert_eq!(&idle.1.recv().await.unwrap()[..], &[0u8; 1000][..]);
+        let evicted = oldest.0.id();
+        assert!(matches!(server_events.recv().await.unwrap(), StreamEvent::Evicted { id } if id == evicted));
+
+        // without an eviction policy, the stream that would overflow is evicted itself rather than skipping the payload
+        let (server, client) = create_streams_with_config(MultiplexConfig::new().with_max_buffered_bytes(1024)).await;
+        let _ = tokio::join!(server.peer_capabilities(), client.peer_capabilities());
+        let (mut server_events, mut client_events) = (server.events(), client.events());
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        server_sub.send_to_peer(&[0u8; 800]).await.unwrap();
+        server_sub.send_to_peer(&[1u8; 800]).await.unwrap();
+
+        let evicted = client_sub.id();
+        assert!(matches!(client_events.recv().await.unwrap(), StreamEvent::Evicted { id } if id == evicted));
+        assert!(matches!(server_events.recv().await.unwrap(), StreamEvent::Evicted { id } if id == evicted));
+        assert_eq!(client_sub.recv().await.unwrap_err().kind(), std::io::ErrorKind::ConnectionReset);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn eviction_spares_unqueued_payloads() {
+        let early_data = EarlyDataPolicy::Buffer { max_frames_per_id: 4, max_pending_ids: 4, timeout: Duration::from_secs(10) };
+        let config = MultiplexConfig::new().with_max_buffered_bytes(1024).with_eviction_policy(EvictionPolicy::Lru).with_early_data_policy(early_data);
+        let (server, client) = create_channel_streams_with_config(config).await;
+        let _ = tokio::join!(server.peer_capabilities(), client.peer_capabilities());
+        let mut client_events = client.events();
+
+        let (idle_server, idle_client) = open_pair(&server, &client).await;
+        let (handled_server, handled_client) = open_pair(&server, &client).await;
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        client.subscribe_with_handler(handled_client.id(), move |payload| { let _ = tx.send(payload); }).unwrap();
+
+        idle_server.send_to_peer(&[0u8; 800]).await.unwrap();
+        settle().await;
+        assert_eq!(client.buffered_bytes(), 800);
+
+        // handed to the handler rather than queued, so the idle stream's bytes need not make room for it
+        handled_server.send_to_peer(&[1u8; 800]).await.unwrap();
+        assert_eq!(&rx.recv().await.unwrap()[..], &[1u8; 800][..]);
+
+        // nor for data that arrives ahead of its stream's open, which the early data policy bounds instead
+        let early = bincode2::serialize(&MultiplexedPacket::ApplicationLayer { id: SymmetricConvID::from(1000), payload: vec![2u8; 800] }).unwrap();
+        client.forward_packet(&early).await.unwrap();
+        assert_eq!(client.debug_state().early_data_ids, 1);
+
+        settle().await;
+        assert_eq!(client.buffered_bytes(), 800);
+        assert!(client_events.try_recv().is_err());
+        assert_eq!(&idle_client.recv().await.unwrap()[..], &[0u8; 800][..]);
+    }
+
+    #[cfg(debug_assertions)]
+    #[tokio::test]
+    async fn sole_demux_reader() {
+        let (server, client) = create_streams().await;
+        let _ = tokio::join!(server.peer_capabilities(), client.peer_capabilities());
+
+        // the demultiplexer is waiting on its next read, which this one would race
+        let stray = server.clone();
+        let err = tokio::spawn(async move {
+            let _ = stray.underlying_conn().recv().await;
+        }).await.unwrap_err();
+
+        let message = err.into_panic().downcast::<&str>().unwrap();
+        assert!(message.contains("sole reader"));
+
+        // the demultiplexer still owns the connection
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        client_sub.send_serialized(1u64).await.unwrap();
+        assert_eq!(server_sub.recv_serialized::<u64>().await.unwrap(), 1);
+    }
+
+    #[test]
+    fn decode_arbitrary_frames() {
+        use rand::{Rng, SeedableRng};
+        let id = SymmetricConvID::from(9);
+        let mut seeds = [
+            MultiplexedPacket::ApplicationLayer { id, payload: vec![1, 2, 3] },
+            MultiplexedPacket::PreCreate { id },
+            MultiplexedPacket::Greeter,
+            MultiplexedPacket::Hello { capabilities: Capabilities::local() },
+            MultiplexedPacket::Fragment { id, message: 4, last: true, chunk: vec![5; 8] },
+            MultiplexedPacket::Batch { frames: vec![bincode2::serialize(&MultiplexedPacket::PostDrop { id }).unwrap()] }
+        ].iter().map(|packet| bincode2::serialize(packet).unwrap()).collect::<Vec<_>>();
+        seeds.push(encode_compact_frame(id, &[6, 7]).unwrap().unwrap());
+
+        for seed in &seeds {
+            assert!(decode_packet::<SymmetricConvID>(seed).is_ok());
+        }
+
+        assert_eq!(decode_packet::<SymmetricConvID>(&seeds[1][..6]).unwrap_err(), DecodeError::Truncated);
+        assert!(matches!(decode_packet::<SymmetricConvID>(&[0xEE, 0, 0, 0]), Err(DecodeError::Malformed(_))));
+
+        // truncated, mutated and random frames are rejected or decoded, never panicking
+        let mut rng = rand::rngs::StdRng::seed_from_u64(450);
+        for seed in &seeds {
+            for len in 0..seed.len() {
+                let _ = decode_packet::<SymmetricConvID>(&seed[..len]);
+            }
+
+            for _ in 0..1000 {
+                let mut mutated = seed.clone();
+                let idx = rng.gen_range(0..mutated.len());
+                mutated[idx] = rng.gen();
+                let _ = decode_packet::<SymmetricConvID>(&mutated);
+            }
+        }
+
+        for _ in 0..10_000 {
+            let frame = (0..rng.gen_range(0..64)).map(|_| rng.gen()).collect::<Vec<u8>>();
+            let _ = decode_packet::<SymmetricConvID>(&frame);
+        }
+    }
+
+    #[tokio::test]
+    async fn decode_error_policy() {
+        // a frame type introduced by a newer protocol version
+        let unknown = [200u8, 0, 0, 0, 1, 2, 3];
+
+        let (server, client) = create_streams_with_config(MultiplexConfig::new().on_decode_error(DecodeErrorPolicy::Skip)).await;
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        server.underlying_conn().send_to_peer(&unknown).await.unwrap();
+
+        // the connection and its streams carry on
+        server_sub.send_serialized(1u64).await.unwrap();
+        assert_eq!(client_sub.recv_serialized::<u64>().await.unwrap(), 1);
+        assert!(client.demux_result().now_or_never().is_none());
+        drop((server_sub, client_sub));
+
+        let (server, client) = create_streams_with_config(MultiplexConfig::new().on_decode_error(DecodeErrorPolicy::Abort)).await;
+        server.underlying_conn().send_to_peer(&unknown).await.unwrap();
+        let err = tokio::time::timeout(Duration::from_secs(1), client.demux_result()).await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn deadlines() {
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default()).await;
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+
+        // a receive on a silent stream fails at the stream's deadline
+        let start = Instant::now();
+        server_sub.set_deadline(start + Duration::from_millis(100));
+        let err = server_sub.recv().await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+
+        // once passed, sends fail without writing anything
+        assert_eq!(server_sub.send_serialized(1u64).await.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        server_sub.clear_deadline();
+        server_sub.send_serialized(2u64).await.unwrap();
+        assert_eq!(client_sub.recv_serialized::<u64>().await.unwrap(), 2);
+
+        // the earlier of the connection's and the stream's deadlines applies
+        let start = Instant::now();
+        client_sub.set_deadline(start + Duration::from_secs(10));
+        client.set_deadline(start + Duration::from_millis(100));
+        assert_eq!(client_sub.recv().await.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+
+        // as well as a tighter timeout around the call
+        let start = Instant::now();
+        client.set_deadline(start + Duration::from_secs(10));
+        assert!(tokio::time::timeout(Duration::from_millis(50), client_sub.recv()).await.is_err());
+        assert_eq!(start.elapsed(), Duration::from_millis(50));
+
+        // opens are bounded by the connection's deadline
+        let _held = drain_prereserved(&client);
+
+        let start = Instant::now();
+        client.set_deadline(start + Duration::from_millis(100));
+        assert!(client.initiate_subscription().await.is_err());
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+        client.clear_deadline();
+    }
+
+    #[tokio::test]
+    async fn shutdown_after_goodbye() {
+        async fn last_packet(peer: crate::sync::test_utils::TcpCodecFramed) -> (Option<MultiplexedPacket<SymmetricConvID>>, std::io::Error) {
+            let mut last = None;
+            loop {
+                match peer.recv_serialized::<MultiplexedPacket<SymmetricConvID>>().await {
+                    Ok(packet) => last = Some(packet),
+                    Err(err) => return (last, err)
+                }
+            }
+        }
+
+        let (conn, peer) = create_framed_pair().await;
+        let conn = NetworkApplication::register(RelativeNodeType::Receiver, conn).await.unwrap();
+        peer.send_serialized(MultiplexedPacket::<SymmetricConvID>::Hello { capabilities: Capabilities::local() }).await.unwrap();
+        let _ = conn.peer_capabilities().await.unwrap();
+        drop(conn);
+
+        // the goodbye is followed by a clean end of stream rather than a reset
+        let (last, err) = last_packet(peer).await;
+        assert!(matches!(last, Some(MultiplexedPacket::Goodbye)));
+        assert_eq!(err.to_string(), "Stream died");
+
+        // an adjacent node that has not advertised the goodbye is not sent one
+        let (conn, peer) = create_framed_pair().await;
+        drop(NetworkApplication::register(RelativeNodeType::Receiver, conn).await.unwrap());
+        let (last, err) = last_packet(peer).await;
+        assert!(!matches!(last, Some(MultiplexedPacket::Goodbye)));
+        assert_eq!(err.to_string(), "Stream died");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn nested_teardown() {
+        async fn level(server: &NetworkApplication, client: &NetworkApplication) -> (NetworkApplication, NetworkApplication) {
+            let (server_sub, client_sub) = open_pair(server, client).await;
+            let (server, client) = tokio::join!(server_sub.multiplex::<SymmetricConvID>(), client_sub.multiplex::<SymmetricConvID>());
+            let (server, client) = (server.unwrap(), client.unwrap());
+            // each level says goodbye only once the adjacent level has advertised support for it
+            let _ = tokio::join!(server.peer_capabilities(), client.peer_capabilities());
+            (server, client)
+        }
+
+        let (server0, client0) = create_channel_streams_with_config(MultiplexConfig::default()).await;
+        let _ = tokio::join!(server0.peer_capabilities(), client0.peer_capabilities());
+        let (server1, client1) = level(&server0, &client0).await;
+        let (server2, client2) = level(&server1, &client1).await;
+        let server_demux = [server0.demux_result(), server1.demux_result(), server2.demux_result()];
+        let [client_demux0, client_demux1, client_demux2] = [client0.demux_result(), client1.demux_result(), client2.demux_result()];
+
+        // dropped from the top, yet torn down from the innermost level
+        drop(server0);
+        drop(server1);
+        drop(server2);
+
+        async fn wait<F: std::future::Future<Output=std::io::Result<()>>>(demux: F) -> std::io::Result<()> {
+            tokio::time::timeout(Duration::from_secs(5), demux).await.unwrap()
+        }
+
+        wait(client_demux2).await.unwrap();
+        // the level beneath stays up until the inner level's handles drop on this node too
+        tokio::pin!(client_demux1);
+        settle().await;
+        assert!((&mut client_demux1).now_or_never().is_none());
+
+        drop(client2);
+        wait(client_demux1).await.unwrap();
+        drop(client1);
+        wait(client_demux0).await.unwrap();
+        drop(client0);
+
+        for demux in server_demux {
+            wait(demux).await.unwrap();
+        }
+    }
+
+    /// A key type for nested levels that is distinct from the top level's
+    #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+    struct LaneID(u16);
+
+    impl crate::multiplex::IDGen<LaneID> for LaneID {
+        type Container = Arc<AtomicUsize>;
+
+        fn generate_container() -> Self::Container {
+            Arc::new(AtomicUsize::new(0))
+        }
+
+        fn generate_next(container: &Self::Container) -> LaneID {
+            LaneID(1 + container.fetch_add(1, Ordering::Relaxed) as u16)
+        }
+
+        fn get_proposed_next(container: &Self::Container) -> LaneID {
+            LaneID(1 + container.load(Ordering::Relaxed) as u16)
+        }
+    }
+
+    #[tokio::test]
+    async fn parent_id() {
+        let (server0, client0) = create_streams().await;
+        assert_eq!(server0.parent_id::<SymmetricConvID>(), None);
+
+        let (server_sub, client_sub) = open_pair(&server0, &client0).await;
+        let outer_id = server_sub.id();
+        let (server1, client1) = tokio::join!(server_sub.multiplex::<LaneID>(), client_sub.multiplex::<LaneID>());
+        let (server1, client1) = (server1.unwrap(), client1.unwrap());
+
+        let (server_sub, client_sub) = tokio::join!(server1.initiate_subscription(), client1.initiate_subscription());
+        let (server_sub, client_sub): (OwnedMultiplexedSubscription<LaneID>, OwnedMultiplexedSubscription<LaneID>) = (server_sub.unwrap(), client_sub.unwrap());
+        let lane_id = server_sub.id();
+        let (server2, client2) = tokio::join!(server_sub.multiplex::<SymmetricConvID>(), client_sub.multiplex::<SymmetricConvID>());
+        let (server2, client2) = (server2.unwrap(), client2.unwrap());
+
+        assert_eq!(server1.parent_id::<SymmetricConvID>(), Some(outer_id));
+        assert_eq!(client1.parent_id::<SymmetricConvID>(), Some(outer_id));
+        assert_eq!(server2.parent_id::<LaneID>(), Some(lane_id));
+        assert_eq!(client2.parent_id::<LaneID>(), Some(lane_id));
+        // the parent's key type must match
+        assert_eq!(server2.parent_id::<SymmetricConvID>(), None);
+    }
+
+    #[tokio::test]
+    async fn inherited_config() {
+        let (server0, client0) = create_streams_with_config(MultiplexConfig::new().with_max_recv_frame(1024)).await;
+        let (server_sub, client_sub) = open_pair(&server0, &client0).await;
+        let (server1, client1) = tokio::join!(server_sub.multiplex::<SymmetricConvID>(), client_sub.multiplex::<SymmetricConvID>());
+        let (server1, client1) = (server1.unwrap(), client1.unwrap());
+
+        // the nested level advertises, and so enforces, the outer level's cap
+        assert_eq!(server1.config().max_recv_frame(), Some(1024));
+        assert_eq!(client1.peer_capabilities().await.unwrap().max_recv_frame, Some(1024));
+        let (server_sub, client_sub) = open_pair(&server1, &client1).await;
+        let err = client_sub.send_to_peer(&[0u8; 2048]).await.unwrap_err();
+        assert!(err.to_string().contains("limit of 1024 bytes"));
+        client_sub.send_to_peer(&[1u8; 512]).await.unwrap();
+        assert_eq!(&server_sub.recv().await.unwrap()[..], &[1u8; 512][..]);
+
+        // unless overridden
+        let (server_sub, client_sub) = open_pair(&server1, &client1).await;
+        let (server2, _client2) = tokio::join!(server_sub.multiplex_with::<SymmetricConvID>(MultiplexConfig::new()), client_sub.multiplex_with::<SymmetricConvID>(MultiplexConfig::new()));
+        assert_eq!(server2.unwrap().config().max_recv_frame(), None);
+
+        // what is tied to the outer level is not inherited
+        let outer = MultiplexConfig::new().with_max_recv_frame(1024).with_processing_pool(2).with_keepalive_policy(Duration::from_secs(1), Duration::from_secs(3)).on_stream::<SymmetricConvID, _, _>("label", |_| async {});
+        let inherited = outer.inherited();
+        assert_eq!(inherited.max_recv_frame(), Some(1024));
+        assert_eq!((inherited.processing_pool(), inherited.keepalive_policy()), (None, None));
+        assert_eq!((format!("{:?}", outer.stream_handlers).as_str(), format!("{:?}", inherited.stream_handlers).as_str()), ("{\"label\"}", "{}"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn partitions() {
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default()).await;
+        let ranges = [1000..2000, 2000..3000];
+        let server_parts = ranges.iter().map(|range| server.partition(range.clone()).unwrap()).collect::<Vec<_>>();
+        let client_parts = ranges.iter().map(|range| client.partition(range.clone()).unwrap()).collect::<Vec<_>>();
+        assert!(server.partition(1500..2500).is_err());
+
+        // each partition runs its own opens, independent of the other
+        let (first, second) = tokio::join!(
+            async {
+                let mut subs = Vec::new();
+                for _ in 0..40 {
+                    let (server_sub, client_sub) = tokio::join!(server_parts[0].initiate_subscription(), client_parts[0].initiate_subscription());
+                    subs.push((server_sub.unwrap(), client_sub.unwrap()));
+                }
+                subs
+            },
+            async {
+                let (server_sub, client_sub) = tokio::join!(server_parts[1].initiate_subscription(), client_parts[1].initiate_subscription());
+                vec![(server_sub.unwrap(), client_sub.unwrap())]
+            }
+        );
+
+        for (subs, range) in [(&first, &ranges[0]), (&second, &ranges[1])] {
+            for (server_sub, client_sub) in subs {
+                let id = u64::from(server_sub.id());
+                assert_eq!(server_sub.id(), client_sub.id());
+                assert!(range.contains(&id));
+
+                server_sub.send_serialized(id).await.unwrap();
+                assert_eq!(client_sub.recv_serialized::<u64>().await.unwrap(), id);
+            }
+        }
+
+        // the parent's own streams are unaffected
+        assert!(server.debug_state().open_ids.iter().all(|id| u64::from(*id) < 1000));
+        assert_eq!(server_parts[0].debug_state().open_ids.len(), 40);
+        assert_eq!(client_parts[1].debug_state().open_ids.len(), 1);
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        server_sub.send_serialized(Packet(1)).await.unwrap();
+        assert_eq!(client_sub.recv_serialized::<Packet>().await.unwrap().0, 1);
+
+        // once the parent's ids reach the partitions, they skip over them
+        for container in [&server.id_gen, &server.current_latest_subscribed, &client.id_gen, &client.current_latest_subscribed] {
+            container.store(998, Ordering::Relaxed);
+        }
+
+        let mut parent_subs = Vec::new();
+        for expected in [999, 3000, 3001] {
+            let (server_sub, client_sub) = open_pair(&server, &client).await;
+            assert_eq!((u64::from(server_sub.id()), u64::from(client_sub.id())), (expected, expected));
+            parent_subs.push((server_sub, client_sub));
+        }
+
+        // closes within a partition complete independently
+        drop((first, second));
+        settle().await;
+        assert!(server_parts[0].debug_state().open_ids.is_empty() && client_parts[1].debug_state().open_ids.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn debug_state() {
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default()).await;
+        let mut subs = Vec::new();
+
+        for _ in 0..3 {
+            subs.push(open_pair(&server, &client).await);
+        }
+
+        assert_eq!(server.debug_state().open_ids, client.debug_state().open_ids);
+        assert_eq!(server.debug_state().open_ids.len(), 3);
+
+        let (server_sub, client_sub) = subs.remove(1);
+        let closed_id = server_sub.id();
+        drop((server_sub, client_sub));
+        settle().await;
+
+        assert!(!server.debug_state().open_ids.contains(&closed_id));
+        assert_eq!(server.debug_state().open_ids, client.debug_state().open_ids);
+        assert_eq!(server.debug_state().open_ids, subs.iter().map(|(sub, _)| sub.id()).collect::<Vec<_>>());
+        assert_ne!(server.debug_state(), client.debug_state());
+    }
+
+    #[test]
+    fn close_runs_on_designated_runtime() {
+        let designated = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let ambient = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let config = MultiplexConfig::new().with_runtime(designated.handle().clone());
+
+        let (server, client, server_sub, client_sub) = designated.block_on(async move {
+            let (server, client) = create_streams_with_config(config).await;
+            let (server_sub, client_sub) = open_pair(&server, &client).await;
+            (server, client, server_sub, client_sub)
+        });
+
+        let (mut server_events, mut client_events) = (server.events(), client.events());
+
+        let id = server_sub.id();
+        // the subscriptions are dropped on a runtime that is shut down right after, so any close task spawned onto it never runs
+        ambient.block_on(async move { drop((server_sub, client_sub)) });
+        drop(ambient);
+
+        designated.block_on(async move {
+            for events in [&mut server_events, &mut client_events] {
+                match tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap() {
+                    StreamEvent::Closed { summary } => assert_eq!(summary.id, id),
+                    event => panic!("Unexpected event {:?}", event)
+                }
+            }
+
+            assert!(!server.subscriptions().shard(&id).read().contains_key(&id) && !client.subscriptions().shard(&id).read().contains_key(&id));
+        });
+    }
+
+    #[test]
+    fn shared_payload_frames() {
+        let payload = (0..200u8).collect::<Vec<u8>>();
+        let mut encoder = SharedPayloadEncoder::new(&payload).unwrap();
+
+        for id in [1u64, 2, u64::MAX] {
+            let id = SymmetricConvID::from(id);
+            let expected = bincode2::serialize(&MultiplexedPacket::ApplicationLayer { id, payload: payload.clone() }).unwrap();
+            assert_eq!(encoder.frame_for(id).unwrap(), expected.as_slice());
+            // sends borrow the payload rather than copying it into an owned packet
+            assert_eq!(bincode2::serialize(&ApplicationLayerRef::ApplicationLayer { id, payload: &payload }).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn sharded_subscriber_map() {
+        let map = SubscriberMap::<SymmetricConvID>::with_capacity(8, 0);
+        assert_eq!(map.shard_count(), 8);
+
+        for id in 0..1000u64 {
+            let (sender, _) = inbound_channel(&Arc::default());
+            let id = SymmetricConvID::from(id);
+            assert!(map.shard(&id).write().insert(id, sender).is_none());
+        }
+
+        assert_eq!(map.len(), 1000);
+        // every id must consistently land in the same shard, and the load should be spread out
+        for id in 0..1000u64 {
+            let id = SymmetricConvID::from(id);
+            assert!(map.shard(&id).read().contains_key(&id));
+        }
+
+        assert!(map.shards.iter().all(|shard| !shard.read().is_empty()));
+
+        let mut visited = 0;
+        map.for_each(|_, _| visited += 1);
+        assert_eq!(visited, 1000);
+    }
+
+    #[tokio::test]
+    async fn subscriber_capacity() {
+        let map = SubscriberMap::<SymmetricConvID>::with_capacity(8, 10_000);
+        assert!(map.shards.iter().all(|shard| shard.read().capacity() >= 1250));
+
+        let (server, client) = create_streams_with_config(MultiplexConfig::new().with_subscriber_shards(4).with_subscriber_capacity(10_000)).await;
+        for conn in [&server, &client] {
+            assert!(conn.subscribers.shards.iter().all(|shard| shard.read().capacity() >= 2500));
+        }
+
+        // the pre-reserved streams fit within the allocation
+        let (server, _client) = create_streams().await;
+        assert!(server.subscribers.shards.iter().all(|shard| shard.read().capacity() < 2500));
+    }
+
+    #[tokio::test]
+    async fn demux_result() {
+        let (kill_tx, kill) = tokio::sync::mpsc::unbounded_channel();
+        let conn = NetworkApplication::register(RelativeNodeType::Receiver, KillableConn { kill: tokio::sync::Mutex::new(kill) }).await.unwrap();
+        let result = conn.demux_result();
+        kill_tx.send(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "transport killed")).unwrap();
+
+        let err = tokio::time::timeout(Duration::from_secs(1), result).await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+        assert_eq!(err.to_string(), "transport killed");
+
+        // a clean EOF from the peer ends the demultiplexer without an error
+        let (local, remote) = tokio::io::duplex(64);
+        let conn = NetworkApplication::register(RelativeNodeType::Receiver, StreamWrapper::from(local)).await.unwrap();
+        std::mem::drop(remote);
+        tokio::time::timeout(Duration::from_secs(1), conn.demux_result()).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn peer_alive() {
+        let (kill_tx, kill) = tokio::sync::mpsc::unbounded_channel();
+        let conn = NetworkApplication::register(RelativeNodeType::Receiver, KillableConn { kill: tokio::sync::Mutex::new(kill) }).await.unwrap();
+        // the transport's signal is forwarded by the connection and its substreams
+        assert_eq!(conn.peer_alive(), Some(true));
+        assert_eq!(conn.get_next_prereserved().unwrap().peer_alive(), Some(true));
+
+        kill_tx.send(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "transport killed")).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), conn.demux_result()).await.unwrap().unwrap_err();
+        assert_eq!(conn.peer_alive(), Some(false));
+
+        // transports without a cheap signal report unknown
+        let (local, _remote) = tokio::io::duplex(64);
+        let conn = NetworkApplication::register(RelativeNodeType::Receiver, StreamWrapper::from(local)).await.unwrap();
+        assert_eq!(conn.peer_alive(), None);
+    }
+
+    /// Counts the frames written to the wrapped transport
+    struct CountingConn<T> {
+        inner: T,
+        writes: Arc<AtomicUsize>
+    }
+
+    #[async_trait::async_trait]
+    impl<T: ReliableOrderedStreamToTarget> ReliableOrderedStreamToTarget for CountingConn<T> {
+        async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            self.inner.send_to_peer(input).await
+        }
+
+        async fn recv(&self) -> std::io::Result<Bytes> {
+            self.inner.recv().await
+        }
+    }
+
+    /// Delays every write to the wrapped transport, so that concurrent sends queue up behind one another
+    struct ThrottledConn<T> {
+        inner: T,
+        delay: Duration
+    }
+
+    #[async_trait::async_trait]
+    impl<T: ReliableOrderedStreamToTarget> ReliableOrderedStreamToTarget for ThrottledConn<T> {
+        async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+            tokio::time::sleep(self.delay).await;
+            self.inner.send_to_peer(input).await
+        }
+
+        async fn recv(&self) -> std::io::Result<Bytes> {
+            self.inner.recv().await
+        }
+    }
+
+    /// Holds each write to the wrapped transport until a permit is added to `gate`, once `gated` is set. Records the
+    /// frames written, in order
+    struct GatedConn<T> {
+        inner: T,
+        gated: Arc<AtomicBool>,
+        gate: Arc<tokio::sync::Semaphore>,
+        written: Arc<parking_lot::Mutex<Vec<Vec<u8>>>>
+    }
+
+    impl<T> GatedConn<T> {
+        fn new(inner: T) -> Self {
+            Self { inner, gated: Arc::new(AtomicBool::new(false)), gate: Arc::new(tokio::sync::Semaphore::new(0)), written: Arc::new(parking_lot::Mutex::new(Vec::new())) }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl<T: ReliableOrderedStreamToTarget> ReliableOrderedStreamToTarget for GatedConn<T> {
+        async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+            if self.gated.load(Ordering::SeqCst) {
+                self.gate.acquire().await.unwrap().forget();
+            }
+
+            self.written.lock().push(input.to_vec());
+            self.inner.send_to_peer(input).await
+        }
+
+        async fn recv(&self) -> std::io::Result<Bytes> {
+            self.inner.recv().await
+        }
+    }
+
+    /// Returns once every task has run as far as it can, since the paused clock only advances when all are idle
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(1)).await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn max_outbound_queue() {
+        let (server_conn, client_conn) = channel_pair();
+        let server_conn = GatedConn::new(server_conn);
+        let (gated, gate) = (server_conn.gated.clone(), server_conn.gate.clone());
+        let config = MultiplexConfig::new().with_max_outbound_queue(4);
+        let (server, client) = tokio::join!(
+            NetworkApplication::register_with_config(RelativeNodeType::Receiver, server_conn, config.clone()),
+            NetworkApplication::register_with_config(RelativeNodeType::Initiator, client_conn, config)
+        );
+
+        let (server, client) = (server.unwrap(), client.unwrap());
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let server_sub = Arc::new(server_sub);
+        let id = server_sub.id();
+        settle().await;
+        assert_eq!(server.outbound_queue_len(id), Some(0));
+
+        // the producers outrun a transport that writes nothing until let through
+        gated.store(true, Ordering::SeqCst);
+        let sent = Arc::new(AtomicUsize::new(0));
+        let producers = (0..20).map(|_| {
+            let (server_sub, sent) = (server_sub.clone(), sent.clone());
+            tokio::spawn(async move {
+                server_sub.send_to_peer(&[0u8; 64]).await.unwrap();
+                sent.fetch_add(1, Ordering::SeqCst);
+            })
+        }).collect::<Vec<_>>();
+
+        settle().await;
+        assert_eq!(server.outbound_queue_len(id), Some(4));
+        assert_eq!(sent.load(Ordering::SeqCst), 0);
+
+        // each write lets one more send into the queue
+        for written in 1..=16 {
+            gate.add_permits(1);
+            settle().await;
+            assert_eq!(sent.load(Ordering::SeqCst), written);
+            assert_eq!(server.outbound_queue_len(id), Some(4));
+        }
+
+        gate.add_permits(4);
+        futures::future::try_join_all(producers).await.unwrap();
+        for _ in 0..20 {
+            client_sub.recv().await.unwrap();
+        }
+
+        assert_eq!(server.outbound_queue_len(id), Some(0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn priority_latency() {
+        let (server_conn, client_conn) = channel_pair();
+        let server_conn = GatedConn::new(server_conn);
+        let (gated, gate, written) = (server_conn.gated.clone(), server_conn.gate.clone(), server_conn.written.clone());
+        let (server, client) = tokio::join!(
+            NetworkApplication::register(RelativeNodeType::Receiver, server_conn),
+            NetworkApplication::register(RelativeNodeType::Initiator, client_conn)
+        );
+
+        let (server, client) = (server.unwrap(), client.unwrap());
+        let (server_bulk, client_bulk) = tokio::join!(server.subscribe_with_priority(Priority::Low), client.subscribe_with_priority(Priority::Low));
+        let (server_urgent, client_urgent) = tokio::join!(server.subscribe_with_priority(Priority::High), client.subscribe_with_priority(Priority::High));
+        let (server_bulk, client_bulk, server_urgent, client_urgent) = (Arc::new(server_bulk.unwrap()), client_bulk.unwrap(), server_urgent.unwrap(), client_urgent.unwrap());
+        assert_eq!(server_urgent.priority(), Priority::High);
+        let (bulk_id, urgent_id) = (server_bulk.id(), server_urgent.id());
+        settle().await;
+
+        // ten bulk frames queue up behind the one being written
+        gated.store(true, Ordering::SeqCst);
+        let first_written = written.lock().len();
+        let bulk = (0..10).map(|_| {
+            let server_bulk = server_bulk.clone();
+            tokio::spawn(async move { server_bulk.send_to_peer(&[0u8; 1024]).await.unwrap() })
+        }).collect::<Vec<_>>();
+
+        settle().await;
+        let urgent = tokio::spawn(async move {
+            server_urgent.send_serialized(Packet(0)).await.unwrap();
+            server_urgent
+        });
+
+        settle().await;
+        gate.add_permits(11);
+        futures::future::try_join_all(bulk).await.unwrap();
+        let _server_urgent = urgent.await.unwrap();
+
+        // the urgent frame is written right after the bulk frame that was already being written
+        let order = written.lock()[first_written..].iter().map(|frame| match decode_packet::<SymmetricConvID>(frame).unwrap() {
+            MultiplexedPacket::ApplicationLayer { id, .. } => id,
+            packet => panic!("Unexpected packet {:?}", packet)
+        }).collect::<Vec<_>>();
+
+        let mut expected = vec![bulk_id; 11];
+        expected[1] = urgent_id;
+        assert_eq!(order, expected);
+
+        assert_eq!(client_urgent.recv_serialized::<Packet>().await.unwrap().0, 0);
+        for _ in 0..10 {
+            let _ = client_bulk.recv().await.unwrap();
+        }
+    }
+
+    /// Holds back reads from the wrapped transport while `held` is set, so that frames pile up unread
+    struct HeldConn<T> {
+        inner: T,
+        held: tokio::sync::watch::Receiver<bool>
+    }
+
+    #[async_trait::async_trait]
+    impl<T: ReliableOrderedStreamToTarget> ReliableOrderedStreamToTarget for HeldConn<T> {
+        async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+            self.inner.send_to_peer(input).await
+        }
+
+        /// Waits before reading rather than after, so that a dropped receive loses no frame
+        async fn recv(&self) -> std::io::Result<Bytes> {
+            let mut held = self.held.clone();
+            while *held.borrow() {
+                held.changed().await.unwrap();
+            }
+
+            self.inner.recv().await
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn inbound_priority() {
+        let (server_conn, client_conn) = channel_pair();
+        let (hold, held) = tokio::sync::watch::channel(false);
+        let (server, client) = tokio::join!(
+            NetworkApplication::register(RelativeNodeType::Receiver, server_conn),
+            NetworkApplication::register(RelativeNodeType::Initiator, HeldConn { inner: client_conn, held })
+        );
+
+        let (server, client) = (server.unwrap(), client.unwrap());
+        let (server_bulk, client_bulk) = tokio::join!(server.subscribe_with_priority(Priority::Low), client.subscribe_with_priority(Priority::Low));
+        let (server_urgent, client_urgent) = tokio::join!(server.subscribe_with_priority(Priority::High), client.subscribe_with_priority(Priority::High));
+        let (server_bulk, client_bulk, server_urgent, client_urgent) = (server_bulk.unwrap(), client_bulk.unwrap(), server_urgent.unwrap(), client_urgent.unwrap());
+        for sub in [&client_bulk, &client_urgent] {
+            client.subscribe_polled(sub.id()).unwrap();
+        }
+
+        // the bulk stream's frames pile up unread ahead of the urgent one
+        hold.send(true).unwrap();
+        for idx in 0..20 {
+            server_bulk.send_serialized(Packet(idx)).await.unwrap();
+        }
+
+        server_urgent.send_serialized(Packet(0)).await.unwrap();
+        settle().await;
+        assert!(client.drain_received().is_empty());
+
+        hold.send(false).unwrap();
+        settle().await;
+        let received = client.drain_received().into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+        let mut expected = vec![client_bulk.id(); 21];
+        expected[0] = client_urgent.id();
+        assert_eq!(received, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn send_ready() {
+        let (server_conn, client_conn) = channel_pair();
+        let delay = Duration::from_millis(200);
+        let (server, client) = tokio::join!(
+            NetworkApplication::register(RelativeNodeType::Receiver, ThrottledConn { inner: server_conn, delay }),
+            NetworkApplication::register(RelativeNodeType::Initiator, client_conn)
+        );
+
+        let (server, client) = (server.unwrap(), client.unwrap());
+        let (server_sub, client_sub) = (Arc::new(server.get_next_prereserved().unwrap()), client.get_next_prereserved().unwrap());
+        server_sub.send_ready().await.unwrap();
+
+        let sender = server_sub.clone();
+        let send = tokio::spawn(async move { sender.send_serialized(Packet(0)).await.unwrap() });
+        tokio::time::sleep(delay / 4).await;
+
+        // the in-flight send holds the connection
+        assert!(server_sub.send_ready().now_or_never().is_none());
+        let start = Instant::now();
+        server_sub.send_ready().await.unwrap();
+        assert!(start.elapsed() >= delay / 2);
+
+        send.await.unwrap();
+        assert_eq!(client_sub.recv_serialized::<Packet>().await.unwrap().0, 0);
+    }
+
+    #[tokio::test]
+    async fn coalesced_frames() {
+        let (server_conn, client_conn) = create_framed_pair().await;
+        let writes = Arc::new(AtomicUsize::new(0));
+        let config = MultiplexConfig::new().with_coalesce_window(Duration::from_millis(100));
+        let (server, client) = tokio::join!(
+            NetworkApplication::register_with_config(RelativeNodeType::Receiver, CountingConn { inner: server_conn, writes: writes.clone() }, config.clone()),
+            NetworkApplication::register_with_config(RelativeNodeType::Initiator, client_conn, config)
+        );
+
+        let (server, client) = (server.unwrap(), client.unwrap());
+        let (server_sub, client_sub) = (server.get_next_prereserved().unwrap(), client.get_next_prereserved().unwrap());
+        let greeter_writes = writes.load(Ordering::SeqCst);
+
+        // each send returns once its batch is written, so the packets are sent concurrently to share one
+        futures::future::try_join_all((0..50).map(|idx| server_sub.send_serialized(Packet(idx)))).await.unwrap();
+        for idx in 0..50 {
+            assert_eq!(client_sub.recv_serialized::<Packet>().await.unwrap().0, idx);
+        }
+
+        // all 50 packets were queued well within a single window
+        assert!(writes.load(Ordering::SeqCst) - greeter_writes < 5);
+    }
+
+    #[tokio::test]
+    async fn replace_transport() {
+        let (server, client) = create_streams().await;
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+
+        // application data keeps flowing while the transport is swapped underneath it
+        let sender = tokio::spawn(async move {
+            for idx in 0..500 {
+                server_sub.send_serialized(Packet(idx)).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+
+            server_sub
+        });
+
+        let (new_server, new_client) = create_framed_pair().await;
+        let (res0, res1) = tokio::join!(server.replace_transport(new_server), client.replace_transport(new_client));
+        res0.unwrap();
+        res1.unwrap();
+
+        for idx in 0..500 {
+            assert_eq!(client_sub.recv_serialized::<Packet>().await.unwrap().0, idx);
+        }
+
+        let server_sub = sender.await.unwrap();
+        client_sub.send_serialized(Packet(500)).await.unwrap();
+        assert_eq!(server_sub.recv_serialized::<Packet>().await.unwrap().0, 500);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replace_transport_in_memory() {
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default()).await;
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+
+        // the old transport ends as soon as both nodes have switched, which may already be waiting behind the swap marker
+        let (new_server, new_client) = channel_pair();
+        let (res0, res1) = tokio::join!(server.replace_transport(new_server), client.replace_transport(new_client));
+        res0.unwrap();
+        res1.unwrap();
+        settle().await;
+        assert!(server.demux_result().now_or_never().is_none());
+        assert!(client.demux_result().now_or_never().is_none());
+
+        server_sub.send_serialized(Packet(0)).await.unwrap();
+        assert_eq!(client_sub.recv_serialized::<Packet>().await.unwrap().0, 0);
+        client_sub.send_serialized(Packet(1)).await.unwrap();
+        assert_eq!(server_sub.recv_serialized::<Packet>().await.unwrap().0, 1);
     }
 
     #[async_recursion]
-    async fn nested(idx: usize, max: usize, server_stream: NetworkApplication, client_stream: NetworkApplication) -> (NetworkApplication, NetworkApplication) {
+    /// Returns every level created. A level stays alive only while a handle to it, or to a level nested on top of it, does
+    async fn nested(idx: usize, max: usize, server_stream: NetworkApplication, client_stream: NetworkApplication) -> Vec<NetworkApplication> {
         if idx == max {
-            return (server_stream, client_stream)
+            return vec![server_stream, client_stream]
         }
 
         let (tx, rx) = tokio::sync::oneshot::channel::<()>();
@@ -319,8 +3829,10 @@ mod tests {
             next_stream.multiplex::<SymmetricConvID>().await.unwrap()
         });
 
-        let (next_server_stream, next_client_stream, _, _) = tokio::join!(server, client, server1, client1);
+        let (next_server_stream, next_client_stream, server1, client1) = tokio::join!(server, client, server1, client1);
 
-        return nested(idx+1, max,next_server_stream.unwrap(), next_client_stream.unwrap()).await
+        let mut levels = nested(idx+1, max,next_server_stream.unwrap(), next_client_stream.unwrap()).await;
+        levels.extend([server1.unwrap(), client1.unwrap()]);
+        levels
     }
 }
\ No newline at end of file
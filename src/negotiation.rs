@@ -0,0 +1,271 @@
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use tokio::sync::watch;
+
+use std::convert::TryFrom;
+use std::sync::atomic::Ordering;
+
+use crate::multiplex::{MultiplexedConn, MultiplexedConnKey, MultiplexedPacket, StreamEvent};
+use crate::reliable_conn::ReliableOrderedStreamToTargetExt;
+use crate::config::KeepalivePolicy;
+
+/// The version of the multiplexing protocol spoken by this build. Version 2 added the keepalive proposal to [`Capabilities`]
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Optional protocol features this build understands, advertised to the adjacent node
+const LOCAL_FEATURES: &[&str] = &["batch", "stream-probe", "accept", "transport-swap", "stream-pool", "goodbye", "compact-ids", "trace-context", "keepalive", "last-will", "reset", "fragment", "close-notice", "open-reject"];
+
+/// Returns true once the capabilities observed by `capabilities` include `feature`
+pub(crate) fn supports(capabilities: &watch::Receiver<Option<Capabilities>>, feature: &str) -> bool {
+    matches!(capabilities.borrow().as_ref(), Some(capabilities) if capabilities.supports(feature))
+}
+
+/// What a node advertises about itself to the adjacent node once the connection is registered
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    pub protocol_version: u32,
+    pub features: Vec<String>,
+    /// The maximum number of concurrently open streams the node accepts. None if unlimited
+    pub max_streams: Option<u64>,
+    /// The largest frame, in bytes, the node accepts (see [`crate::config::MultiplexConfig::with_max_recv_frame`]). None if unlimited
+    pub max_recv_frame: Option<u64>,
+    /// The keepalive the node proposes (see [`crate::config::MultiplexConfig::with_keepalive_policy`]). None if it proposes none
+    pub keepalive: Option<KeepalivePolicy>
+}
+
+impl Capabilities {
+    pub(crate) fn local() -> Self {
+        Self { protocol_version: PROTOCOL_VERSION, features: LOCAL_FEATURES.iter().map(|feature| feature.to_string()).collect(), max_streams: None, max_recv_frame: None, keepalive: None }
+    }
+
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|supported| supported == feature)
+    }
+}
+
+/// A point-in-time view of the adjacent node, assembled from state the connection already maintains
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerInfo {
+    /// The protocol version both nodes speak (the lower of the two). None until the adjacent node's capabilities arrive
+    pub protocol_version: Option<u32>,
+    /// The capabilities advertised by the adjacent node. None until they arrive
+    pub capabilities: Option<Capabilities>,
+    /// The round-trip time measured by the most recent successful [`MultiplexedConn::probe_stream`], if any
+    pub last_rtt: Option<Duration>
+}
+
+/// What this node has learned about the adjacent node
+pub(crate) struct PeerState {
+    capabilities_tx: watch::Sender<Option<Capabilities>>,
+    capabilities_rx: watch::Receiver<Option<Capabilities>>,
+    last_rtt: parking_lot::Mutex<Option<Duration>>,
+    local_keepalive: Option<KeepalivePolicy>,
+    // the keepalive in force. None until the adjacent node advertises support for it
+    keepalive_tx: watch::Sender<Option<KeepalivePolicy>>,
+    keepalive_rx: watch::Receiver<Option<KeepalivePolicy>>,
+    // the name and payload the adjacent node registered through set_last_will
+    last_will: parking_lot::Mutex<Option<(String, Vec<u8>)>>
+}
+
+impl PeerState {
+    pub(crate) fn new(local_keepalive: Option<KeepalivePolicy>) -> Self {
+        let (capabilities_tx, capabilities_rx) = watch::channel(None);
+        let (keepalive_tx, keepalive_rx) = watch::channel(None);
+        Self { capabilities_tx, capabilities_rx, last_rtt: parking_lot::Mutex::new(None), local_keepalive, keepalive_tx, keepalive_rx, last_will: parking_lot::Mutex::new(None) }
+    }
+
+    pub(crate) fn on_hello(&self, capabilities: Capabilities) {
+        // a node that does not advertise keepalives neither sends keepalive frames nor expects to receive any
+        let keepalive = if capabilities.supports("keepalive") { KeepalivePolicy::stricter(self.local_keepalive, capabilities.keepalive) } else { None };
+        let _ = self.keepalive_tx.send(keepalive);
+        let _ = self.capabilities_tx.send(Some(capabilities));
+    }
+
+    pub(crate) fn record_rtt(&self, rtt: Duration) {
+        *self.last_rtt.lock() = Some(rtt);
+    }
+}
+
+impl<K: MultiplexedConnKey> MultiplexedConn<K> {
+    /// Returns everything currently known about the adjacent node. This does not communicate with the adjacent node
+    pub fn peer_info(&self) -> PeerInfo {
+        let capabilities = self.peer.capabilities_rx.borrow().clone();
+        let protocol_version = capabilities.as_ref().map(|capabilities| std::cmp::min(capabilities.protocol_version, PROTOCOL_VERSION));
+        PeerInfo { protocol_version, capabilities, last_rtt: *self.peer.last_rtt.lock() }
+    }
+
+    /// The largest frame the adjacent node accepts, as advertised in its capabilities. Sends of larger frames fail locally.
+    /// None if the adjacent node is unlimited or has not yet advertised its capabilities
+    pub fn peer_max_frame(&self) -> Option<usize> {
+        match self.peer_max_frame.load(Ordering::Relaxed) {
+            usize::MAX => None,
+            max => Some(max)
+        }
+    }
+
+    /// The keepalive in force: the stricter of the two nodes' proposals once the adjacent node's capabilities arrive. None
+    /// until then, if neither node proposes one, or if the adjacent node does not advertise support for keepalives
+    pub fn keepalive_policy(&self) -> Option<KeepalivePolicy> {
+        *self.peer.keepalive_rx.borrow()
+    }
+
+    /// Observes the keepalive in force as it is negotiated
+    pub(crate) fn keepalive_watch(&self) -> watch::Receiver<Option<KeepalivePolicy>> {
+        self.peer.keepalive_rx.clone()
+    }
+
+    /// Registers a last will with the adjacent node, replacing any registered before. Should this connection die without
+    /// a clean goodbye, as when this node crashes or the adjacent node's keepalive times out (see
+    /// [`crate::config::MultiplexConfig::with_keepalive_policy`]), the adjacent node emits it locally as a
+    /// [`StreamEvent::LastWill`]. Nothing is emitted if either node closes the connection cleanly.
+    ///
+    /// Waits for the adjacent node's capabilities, and fails if it does not advertise support for last wills
+    pub async fn set_last_will<N: Into<String>>(&self, stream_name: N, payload: Vec<u8>) -> Result<(), anyhow::Error> {
+        match self.peer_capabilities().await {
+            Some(capabilities) if capabilities.supports("last-will") => {}
+            Some(_) => return Err(anyhow::Error::msg("The adjacent node does not support last wills")),
+            None => return Err(anyhow::Error::msg("The connection ended before the adjacent node advertised its capabilities"))
+        }
+
+        Ok(self.conn.send_serialized(MultiplexedPacket::<K>::LastWill { name: stream_name.into(), payload }).await?)
+    }
+
+    pub(crate) fn on_last_will(&self, name: String, payload: Vec<u8>) {
+        *self.peer.last_will.lock() = Some((name, payload))
+    }
+
+    /// Emits the adjacent node's last will, if it registered one, once the connection dies without a clean goodbye
+    pub(crate) fn on_peer_died(&self) {
+        if let Some((name, payload)) = self.peer.last_will.lock().take() {
+            log::info!("The adjacent node went away without a goodbye. Emitting its last will for {:?}", name);
+            self.emit_event(StreamEvent::LastWill { name, payload })
+        }
+    }
+
+    /// Returns true once the adjacent node has advertised support for `feature`
+    pub(crate) fn peer_supports(&self, feature: &str) -> bool {
+        supports(&self.peer.capabilities_rx, feature)
+    }
+
+    /// Observes the adjacent node's capabilities as they arrive, without holding a handle to the connection
+    pub(crate) fn capabilities_watch(&self) -> watch::Receiver<Option<Capabilities>> {
+        self.peer.capabilities_rx.clone()
+    }
+
+    pub(crate) fn on_hello(&self, capabilities: Capabilities) {
+        let max_frame = capabilities.max_recv_frame.map(|max| usize::try_from(max).unwrap_or(usize::MAX)).unwrap_or(usize::MAX);
+        self.peer_max_frame.store(max_frame, Ordering::Relaxed);
+        self.compact_frames.store(capabilities.supports("compact-ids"), Ordering::Relaxed);
+        self.peer.on_hello(capabilities)
+    }
+
+    /// Waits for the adjacent node's capabilities, which it sends once after registering. Returns None if the
+    /// demultiplexer ends first
+    pub async fn peer_capabilities(&self) -> Option<Capabilities> {
+        let mut rx = self.peer.capabilities_rx.clone();
+        let demux_ended = self.demux_result();
+        tokio::pin!(demux_ended);
+
+        loop {
+            if let Some(capabilities) = rx.borrow().clone() {
+                return Some(capabilities)
+            }
+
+            tokio::select! {
+                res = rx.changed() => if res.is_err() { return None },
+                _ = &mut demux_ended => return rx.borrow().clone()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::test_utils::{create_streams, create_streams_with_config, open_pair};
+    use crate::sync::subscription::{Subscribable, SubscriptionBiStream};
+    use crate::multiplex::{MultiplexedPacket, encode_compact_frame};
+    use crate::sync::SymmetricConvID;
+    use crate::config::MultiplexConfig;
+    use crate::reliable_conn::ReliableOrderedStreamToTarget;
+    use crate::negotiation::{PROTOCOL_VERSION, Capabilities};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn peer_max_frame() {
+        let (server, client) = create_streams_with_config(MultiplexConfig::new().with_max_recv_frame(1024)).await;
+        let _ = server.peer_capabilities().await.unwrap();
+        assert_eq!(server.peer_max_frame(), Some(1024));
+
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+
+        let err = server_sub.send_to_peer(&[0u8; 2048]).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("limit of 1024 bytes"));
+
+        // frames within the limit are unaffected
+        server_sub.send_to_peer(&[1u8; 512]).await.unwrap();
+        assert_eq!(&client_sub.recv().await.unwrap()[..], &[1u8; 512][..]);
+
+        // oversized inbound frames are refused before they are decoded, batches included
+        let oversized = bincode2::serialize(&MultiplexedPacket::ApplicationLayer { id: client_sub.id(), payload: vec![0u8; 2048] }).unwrap();
+        assert!(client.forward_packet(&oversized).await.unwrap_err().to_string().contains("exceeds the limit"));
+        let batch = bincode2::serialize(&MultiplexedPacket::<SymmetricConvID>::Batch { frames: vec![vec![0u8; 512]; 4] }).unwrap();
+        assert!(client.forward_packet(&batch).await.unwrap_err().to_string().contains("exceeds the limit"));
+
+        // coalesced frames are batched within the limit
+        let config = MultiplexConfig::new().with_max_recv_frame(1024).with_coalesce_window(Duration::from_millis(10));
+        let (server, client) = create_streams_with_config(config).await;
+        let _ = tokio::join!(server.peer_capabilities(), client.peer_capabilities());
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let frames = (0..8u8).map(|idx| vec![idx; 400]).collect::<Vec<_>>();
+        futures::future::try_join_all(frames.iter().map(|frame| server_sub.send_to_peer(frame))).await.unwrap();
+        for idx in 0..8u8 {
+            let received = tokio::time::timeout(Duration::from_secs(5), client_sub.recv()).await.unwrap().unwrap();
+            assert_eq!(&received[..], &[idx; 400][..]);
+        }
+    }
+
+    #[tokio::test]
+    async fn compact_frames() {
+        let id = SymmetricConvID::from(40);
+        let payload = [7u8; 4];
+        let full = bincode2::serialize(&MultiplexedPacket::ApplicationLayer { id, payload: payload.to_vec() }).unwrap();
+        let compact = encode_compact_frame(id, &payload).unwrap().unwrap();
+        assert_eq!(full.len(), payload.len() + 20);
+        assert_eq!(compact.len(), payload.len() + 4);
+
+        for frame in [full, compact] {
+            match MultiplexedPacket::<SymmetricConvID>::decode(&frame).unwrap() {
+                MultiplexedPacket::ApplicationLayer { id: decoded, payload: decoded_payload } => assert_eq!((decoded, &decoded_payload[..]), (id, &payload[..])),
+                _ => panic!("Unexpected packet")
+            }
+        }
+
+        // used once both nodes have advertised support
+        let (server, client) = create_streams().await;
+        let _ = tokio::join!(server.peer_capabilities(), client.peer_capabilities());
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        assert!(server_sub.compact_frames() && client_sub.compact_frames());
+
+        server_sub.send_to_peer(&payload).await.unwrap();
+        assert_eq!(&client_sub.recv().await.unwrap()[..], &payload[..]);
+        client_sub.send_to_peer(&[]).await.unwrap();
+        assert!(server_sub.recv().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn peer_info() {
+        let (server, client) = create_streams().await;
+        assert_eq!(client.peer_capabilities().await.unwrap(), Capabilities::local());
+        assert_eq!(server.peer_capabilities().await.unwrap(), Capabilities::local());
+
+        let info = client.peer_info();
+        assert_eq!(info.protocol_version, Some(PROTOCOL_VERSION));
+        assert!(info.capabilities.unwrap().supports("batch"));
+        assert!(info.last_rtt.is_none());
+
+        let client_sub = client.get_next_prereserved().unwrap();
+        let rtt = client.probe_stream(client_sub.id()).await.unwrap();
+        assert_eq!(client.peer_info().last_rtt, Some(rtt));
+    }
+}
@@ -1,5 +1,5 @@
 use async_trait::async_trait;
-use bytes::{Bytes, BytesMut};
+use bytes::{Buf, Bytes, BytesMut};
 use tokio::net::TcpStream;
 use std::net::SocketAddr;
 use std::sync::Arc;
@@ -7,14 +7,55 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncReadExt, AsyncWriteExt};
 use tokio::sync::Mutex;
+use std::ops::Deref;
+use std::convert::TryFrom;
+use std::task::{Context, Poll};
+use crate::sync::priority::Priority;
 
 #[async_trait]
-/// This represents a direct client to server or client->server->peer connection (usually just TCP) for establishing the hole-punching process
+/// This represents a direct client to server or client->server->peer connection (usually just TCP) for establishing the hole-punching process.
+///
+/// Implementations carry whole messages, not bytes: `send_to_peer` either sends all of `input` as one message or fails,
+/// and `recv` returns exactly one message as it was sent. A multiplexed connection encodes one packet per message and
+/// decodes one packet per message received, so a transport that splits or merges messages corrupts every stream
+/// multiplexed over it. A transport over a byte stream must therefore delimit its messages, as [`TcpConn`] does, and
+/// keep writing until the whole message is out, however many writes that takes. [`TcpStream`] and [`StreamWrapper`]
+/// write whole messages, but read whatever bytes have arrived, so they do not preserve message boundaries
 pub trait ReliableOrderedStreamToTarget: Send + Sync {
-    /// Accepts plaintext from the NAT traversal driver. Encryption can be optionally applied
+    /// Accepts plaintext from the NAT traversal driver. Encryption can be optionally applied. Returns once the whole
+    /// of `input` has been handed to the transport as one message, or fails. A send cancelled part-way through may
+    /// leave part of a message behind, after which the transport should be discarded
     async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()>;
-    /// returns the plaintext
+    /// Sends ahead of any lower-priority sends waiting on the same connection. Transports that do not queue sends
+    /// ignore the priority
+    async fn send_to_peer_with_priority(&self, input: &[u8], _priority: Priority) -> std::io::Result<()> {
+        self.send_to_peer(input).await
+    }
+    /// returns the plaintext of the next message, exactly as it was passed to the peer's `send_to_peer`.
+    ///
+    /// Must be cancel-safe. A multiplexed connection's demultiplexer drops a pending `recv` whenever something else
+    /// happens first, such as the keepalive timeout passing or the last handle dropping, and polls it only once when
+    /// reading ahead. A `recv` dropped before it returns must therefore neither lose nor consume any part of a message, so
+    /// a transport that reads a message in several steps keeps what it has read for the next call, as [`TcpConn`] does
     async fn recv(&self) -> std::io::Result<Bytes>;
+
+    /// A cheap, non-intrusive indication of whether the peer is still reachable, as known by the transport without
+    /// sending any data. Returns None when the transport cannot answer cheaply
+    fn peer_alive(&self) -> Option<bool> {
+        None
+    }
+
+    /// Returns Ready if a send would begin writing immediately rather than queue behind other sends, so that the cost of
+    /// serializing a message can be deferred until it can go out. Transports that do not queue sends are always ready
+    fn poll_send_ready(&self, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    /// Flushes anything buffered and closes the sending direction, so that the peer reads a clean end of stream rather
+    /// than a reset. A multiplexed connection calls this after saying goodbye. Transports that cannot close do nothing
+    async fn shutdown(&self) -> std::io::Result<()> {
+        Ok(())
+    }
 }
 
 pub trait ConnAddr {
@@ -51,32 +92,56 @@ pub trait ReliableOrderedStreamToTargetExt: ReliableOrderedStreamToTarget {
         }
     }
 
+    /// Waits until a send would begin writing immediately (see [`ReliableOrderedStreamToTarget::poll_send_ready`])
+    async fn send_ready(&self) -> std::io::Result<()> {
+        futures::future::poll_fn(|cx| self.poll_send_ready(cx)).await
+    }
+
     async fn send_serialized<T: Serialize + Send + Sync>(&self, t: T) -> std::io::Result<()> {
-        let packet = &bincode2::serialize(&t).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
-        self.send_to_peer(packet).await
+        let packet = serialize_to_buffer(&t)?;
+        self.send_to_peer(&packet).await
+    }
+}
+
+/// Messages whose serialized form fits within this many bytes are serialized into a stack buffer, avoiding a heap allocation per send
+pub const SMALL_MESSAGE_THRESHOLD: usize = 64;
+
+pub(crate) enum SerializedBuffer {
+    Stack([u8; SMALL_MESSAGE_THRESHOLD], usize),
+    Heap(Vec<u8>)
+}
+
+impl Deref for SerializedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Stack(buf, len) => &buf[..*len],
+            Self::Heap(buf) => buf.as_slice()
+        }
     }
 }
 
+/// Serializes onto the stack when the output fits within [`SMALL_MESSAGE_THRESHOLD`], otherwise falls back to the heap
+pub(crate) fn serialize_to_buffer<T: Serialize + ?Sized>(t: &T) -> std::io::Result<SerializedBuffer> {
+    let mut buf = [0u8; SMALL_MESSAGE_THRESHOLD];
+    let mut cursor = &mut buf[..];
+
+    if bincode2::serialize_into(&mut cursor, t).is_ok() {
+        let len = SMALL_MESSAGE_THRESHOLD - cursor.len();
+        return Ok(SerializedBuffer::Stack(buf, len))
+    }
+
+    bincode2::serialize(t).map(SerializedBuffer::Heap).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))
+}
+
 impl<T: ReliableOrderedStreamToTarget> ReliableOrderedStreamToTargetExt for T {}
 
 #[async_trait]
 impl ReliableOrderedStreamToTarget for TcpStream {
+    /// Writes the whole of `input`, which may take several writes if the socket's send buffer is smaller
     async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
-        loop {
-            self.writable().await?;
-
-            match self.try_write(input) {
-                Ok(_) => {
-                    return Ok(())
-                }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    continue;
-                }
-                Err(e) => {
-                    return Err(e);
-                }
-            }
-        }
+        write_all(self, input).await
     }
 
     async fn recv(&self) -> std::io::Result<Bytes> {
@@ -101,6 +166,20 @@ impl ReliableOrderedStreamToTarget for TcpStream {
             }
         }
     }
+
+    /// Sends a FIN. Receiving remains possible until the peer closes its own direction
+    async fn shutdown(&self) -> std::io::Result<()> {
+        socket2::SockRef::from(self).shutdown(std::net::Shutdown::Write)
+    }
+
+    /// The kernel drops the peer address once the connection has been reset or closed. A known peer address does not
+    /// prove the peer is still there, so that case is reported as unknown
+    fn peer_alive(&self) -> Option<bool> {
+        match TcpStream::peer_addr(self) {
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotConnected => Some(false),
+            _ => None
+        }
+    }
 }
 
 impl ConnAddr for TcpStream {
@@ -113,15 +192,268 @@ impl ConnAddr for TcpStream {
     }
 }
 
+/// A TCP transport that preserves message boundaries by prefixing each message with its length, and exposes the
+/// socket options that matter beneath a multiplexed connection. As with [`TcpStream`], an empty message reads the same
+/// as the peer closing the connection.
+///
+/// A multiplexed connection with a coalesce window (see [`crate::config::MultiplexConfig::with_coalesce_window`])
+/// already batches small frames, so Nagle's algorithm only delays each batch further. Call [`TcpConn::set_nodelay`]
+/// in that case
+pub struct TcpConn {
+    stream: TcpStream,
+    // held for the whole of a write, so that the frames of concurrent sends do not interleave
+    write_lock: Mutex<()>,
+    read_buf: Mutex<BytesMut>,
+    max_frame: usize
+}
+
+impl TcpConn {
+    const LEN_PREFIX: usize = 4;
+    /// The default for [`Self::with_max_frame`]
+    pub const DEFAULT_MAX_FRAME: usize = 64 * 1024 * 1024;
+
+    pub fn new(stream: TcpStream) -> Self {
+        Self { stream, write_lock: Mutex::new(()), read_buf: Mutex::new(BytesMut::with_capacity(4096)), max_frame: Self::DEFAULT_MAX_FRAME }
+    }
+
+    /// The longest message, in bytes, accepted from the peer. A longer length prefix fails the receive with `InvalidData`
+    /// before any of the message is buffered. Defaults to [`Self::DEFAULT_MAX_FRAME`]
+    pub fn with_max_frame(mut self, max_frame: usize) -> Self {
+        self.max_frame = max_frame;
+        self
+    }
+
+    /// Enables or disables Nagle's algorithm, which holds back small writes until earlier ones are acknowledged
+    pub fn set_nodelay(&self, nodelay: bool) -> std::io::Result<()> {
+        self.stream.set_nodelay(nodelay)
+    }
+
+    pub fn nodelay(&self) -> std::io::Result<bool> {
+        self.stream.nodelay()
+    }
+
+    /// Sets the size of the kernel's send buffer. The kernel may round the size, or double it to account for bookkeeping
+    pub fn set_send_buffer_size(&self, size: usize) -> std::io::Result<()> {
+        socket2::SockRef::from(&self.stream).set_send_buffer_size(size)
+    }
+
+    pub fn send_buffer_size(&self) -> std::io::Result<usize> {
+        socket2::SockRef::from(&self.stream).send_buffer_size()
+    }
+
+}
+
+/// Writes the whole of `frame`. A socket accepts at most as much as its send buffer has room for in each write, so
+/// large frames take several
+async fn write_all(stream: &TcpStream, mut frame: &[u8]) -> std::io::Result<()> {
+    while !frame.is_empty() {
+        stream.writable().await?;
+
+        match stream.try_write(frame) {
+            Ok(len) => frame = &frame[len..],
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e)
+        }
+    }
+
+    Ok(())
+}
+
+#[async_trait]
+impl ReliableOrderedStreamToTarget for TcpConn {
+    async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+        let len = u32::try_from(input.len()).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Message too large for a TCP frame"))?;
+        let mut frame = Vec::with_capacity(Self::LEN_PREFIX + input.len());
+        frame.extend_from_slice(&len.to_be_bytes());
+        frame.extend_from_slice(input);
+
+        let _guard = self.write_lock.lock().await;
+        write_all(&self.stream, &frame).await
+    }
+
+    /// Returns an empty message once the peer closes the connection
+    async fn recv(&self) -> std::io::Result<Bytes> {
+        let mut buf = self.read_buf.lock().await;
+        loop {
+            if buf.len() >= Self::LEN_PREFIX {
+                let mut len = [0u8; Self::LEN_PREFIX];
+                len.copy_from_slice(&buf[..Self::LEN_PREFIX]);
+                let len = u32::from_be_bytes(len) as usize;
+                // checked before buffering the message, so that a hostile prefix cannot exhaust memory
+                if len > self.max_frame {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Frame of {} bytes exceeds the limit of {} bytes", len, self.max_frame)))
+                }
+
+                if buf.len() >= Self::LEN_PREFIX + len {
+                    buf.advance(Self::LEN_PREFIX);
+                    return Ok(buf.split_to(len).freeze())
+                }
+            }
+
+            self.stream.readable().await?;
+
+            match self.stream.try_read_buf(&mut *buf) {
+                Ok(0) => return Ok(Bytes::new()),
+                Ok(_) => {}
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e)
+            }
+        }
+    }
+
+    fn peer_alive(&self) -> Option<bool> {
+        self.stream.peer_alive()
+    }
+
+    /// Waits for any write in progress, then sends a FIN
+    async fn shutdown(&self) -> std::io::Result<()> {
+        let _guard = self.write_lock.lock().await;
+        ReliableOrderedStreamToTarget::shutdown(&self.stream).await
+    }
+}
+
+impl ConnAddr for TcpConn {
+    fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.stream.local_addr()
+    }
+
+    fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+}
+
 #[async_trait]
 impl<T: ReliableOrderedStreamToTarget + ?Sized> ReliableOrderedStreamToTarget for Arc<T> {
     async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
         T::send_to_peer(self, input).await
     }
 
+    async fn send_to_peer_with_priority(&self, input: &[u8], priority: Priority) -> std::io::Result<()> {
+        T::send_to_peer_with_priority(self, input, priority).await
+    }
+
     async fn recv(&self) -> std::io::Result<Bytes> {
         T::recv(self).await
     }
+
+    fn peer_alive(&self) -> Option<bool> {
+        T::peer_alive(self)
+    }
+
+    fn poll_send_ready(&self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        T::poll_send_ready(self, cx)
+    }
+
+    async fn shutdown(&self) -> std::io::Result<()> {
+        T::shutdown(self).await
+    }
+}
+
+/// Whether a frame was written to or read from a connection
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Sent,
+    Received
+}
+
+/// The frames observed by a [`TappedConn`], in the order they completed. Clones share the same log
+#[derive(Clone, Default)]
+pub struct WireLog {
+    frames: Arc<parking_lot::Mutex<Vec<(Direction, Bytes)>>>
+}
+
+impl WireLog {
+    pub fn frames(&self) -> Vec<(Direction, Bytes)> {
+        self.frames.lock().clone()
+    }
+
+    pub fn sent(&self) -> Vec<Bytes> {
+        self.filtered(Direction::Sent)
+    }
+
+    pub fn received(&self) -> Vec<Bytes> {
+        self.filtered(Direction::Received)
+    }
+
+    pub fn clear(&self) {
+        self.frames.lock().clear()
+    }
+
+    fn filtered(&self, direction: Direction) -> Vec<Bytes> {
+        self.frames.lock().iter().filter(|(observed, _)| *observed == direction).map(|(_, frame)| frame.clone()).collect()
+    }
+
+    fn record(&self, direction: Direction, frame: Bytes) {
+        self.frames.lock().push((direction, frame))
+    }
+}
+
+/// Records every frame written to or read from the inner connection, exactly as it crossed the connection, without
+/// otherwise altering its behavior. Intended for conformance tests that assert the bytes a node puts on the wire.
+///
+/// A frame is recorded once its write or read succeeds. Sends that race each other are recorded in the order they
+/// complete, which for a [`crate::multiplex::MultiplexedConn`]'s transport is the order they were written
+pub struct TappedConn<C> {
+    inner: C,
+    log: WireLog
+}
+
+impl<C: ReliableOrderedStreamToTarget> TappedConn<C> {
+    pub fn new(inner: C) -> Self {
+        Self { inner, log: WireLog::default() }
+    }
+
+    /// A handle to the log of observed frames, which remains readable after the connection is handed off
+    pub fn log(&self) -> WireLog {
+        self.log.clone()
+    }
+
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<C: ReliableOrderedStreamToTarget> ReliableOrderedStreamToTarget for TappedConn<C> {
+    async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+        self.inner.send_to_peer(input).await?;
+        self.log.record(Direction::Sent, Bytes::copy_from_slice(input));
+        Ok(())
+    }
+
+    async fn send_to_peer_with_priority(&self, input: &[u8], priority: Priority) -> std::io::Result<()> {
+        self.inner.send_to_peer_with_priority(input, priority).await?;
+        self.log.record(Direction::Sent, Bytes::copy_from_slice(input));
+        Ok(())
+    }
+
+    async fn recv(&self) -> std::io::Result<Bytes> {
+        let frame = self.inner.recv().await?;
+        self.log.record(Direction::Received, frame.clone());
+        Ok(frame)
+    }
+
+    fn peer_alive(&self) -> Option<bool> {
+        self.inner.peer_alive()
+    }
+
+    fn poll_send_ready(&self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.inner.poll_send_ready(cx)
+    }
+
+    async fn shutdown(&self) -> std::io::Result<()> {
+        self.inner.shutdown().await
+    }
+}
+
+impl<C: ConnAddr> ConnAddr for TappedConn<C> {
+    fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
 }
 
 pub struct StreamWrapper<T> {
@@ -144,6 +476,10 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin> ReliableOrderedStreamToTarget for
         let mut buf = BytesMut::with_capacity(4096);
         self.inner.lock().await.read_buf(&mut buf).await.map(|r| buf.split_to(r).freeze())
     }
+
+    async fn shutdown(&self) -> std::io::Result<()> {
+        AsyncWriteExt::shutdown(&mut *self.inner.lock().await).await
+    }
 }
 
 pub mod simulator {
@@ -196,6 +532,10 @@ pub mod simulator {
         async fn recv(&self) -> std::io::Result<Bytes> {
             self.inner.recv().await
         }
+
+        fn peer_alive(&self) -> Option<bool> {
+            self.inner.peer_alive()
+        }
     }
 
     impl<T: ReliableOrderedConnectionToTarget + 'static> ConnAddr for NetworkConnSimulator<T> {
@@ -207,4 +547,193 @@ pub mod simulator {
             self.inner.peer_addr()
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reliable_conn::{serialize_to_buffer, SerializedBuffer, SMALL_MESSAGE_THRESHOLD, ReliableOrderedStreamToTarget, TcpConn, TappedConn, Direction};
+    use crate::sync::test_utils::{channel_pair, open_pair, drain_prereserved};
+    use crate::sync::network_application::NetworkApplication;
+    use crate::sync::subscription::SubscriptionBiStream;
+    use crate::sync::RelativeNodeType;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::io::AsyncReadExt;
+    use futures::FutureExt;
+    use std::time::Duration;
+
+    #[test]
+    fn small_messages_use_stack() {
+        let small = 8u64;
+        let buf = serialize_to_buffer(&small).unwrap();
+        assert!(matches!(buf, SerializedBuffer::Stack(..)));
+        assert_eq!(&*buf, bincode2::serialize(&small).unwrap().as_slice());
+
+        // exactly fills the stack buffer
+        let exact = [7u64; SMALL_MESSAGE_THRESHOLD / 8];
+        let buf = serialize_to_buffer(&exact).unwrap();
+        assert!(matches!(buf, SerializedBuffer::Stack(..)));
+        assert_eq!(&*buf, bincode2::serialize(&exact).unwrap().as_slice());
+
+        let large = vec![7u8; SMALL_MESSAGE_THRESHOLD];
+        let buf = serialize_to_buffer(&large).unwrap();
+        assert!(matches!(buf, SerializedBuffer::Heap(..)));
+        assert_eq!(bincode2::deserialize::<Vec<u8>>(&buf).unwrap(), large);
+    }
+
+    #[tokio::test]
+    async fn tcp_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (server, client) = tokio::join!(listener.accept(), TcpStream::connect(addr));
+        let (server, client) = (server.unwrap().0, client.unwrap());
+
+        server.send_to_peer(b"last").await.unwrap();
+        server.shutdown().await.unwrap();
+        assert_eq!(&client.recv().await.unwrap()[..], b"last");
+        // a clean end of stream rather than a reset
+        assert!(client.recv().await.unwrap().is_empty());
+
+        // the other direction remains open
+        client.send_to_peer(b"reply").await.unwrap();
+        assert_eq!(&server.recv().await.unwrap()[..], b"reply");
+    }
+
+    #[tokio::test]
+    async fn tcp_conn_options() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (server, client) = tokio::join!(listener.accept(), TcpStream::connect(addr));
+        let (server, client) = (TcpConn::new(server.unwrap().0), TcpConn::new(client.unwrap()));
+
+        client.set_nodelay(true).unwrap();
+        assert!(client.nodelay().unwrap());
+        client.set_nodelay(false).unwrap();
+        assert!(!client.nodelay().unwrap());
+
+        client.set_send_buffer_size(256 * 1024).unwrap();
+        assert!(client.send_buffer_size().unwrap() >= 256 * 1024);
+
+        // message boundaries survive, however the writes were segmented
+        let large = vec![3u8; 1024 * 1024];
+        let (_, (small, received)) = tokio::join!(async {
+            client.send_to_peer(b"small").await.unwrap();
+            client.send_to_peer(&large).await.unwrap();
+        }, async {
+            (server.recv().await.unwrap(), server.recv().await.unwrap())
+        });
+
+        assert_eq!(&small[..], b"small");
+        assert_eq!(&received[..], &large[..]);
+
+        client.shutdown().await.unwrap();
+        assert!(server.recv().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tcp_conn_max_frame() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (server, client) = tokio::join!(listener.accept(), TcpStream::connect(addr));
+        let (server, client) = (TcpConn::new(server.unwrap().0).with_max_frame(1024), client.unwrap());
+
+        let frame = |len: u32| [&len.to_be_bytes()[..], &vec![1u8; len as usize][..]].concat();
+        client.send_to_peer(&frame(1024)).await.unwrap();
+        assert_eq!(server.recv().await.unwrap().len(), 1024);
+
+        // the prefix alone fails the receive, though none of the message follows it
+        client.send_to_peer(&u32::MAX.to_be_bytes()).await.unwrap();
+        assert_eq!(server.recv().await.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert!(server.read_buf.lock().await.capacity() < 64 * 1024);
+    }
+
+    #[tokio::test]
+    async fn tcp_conn_recv_cancel_safe() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (server, client) = tokio::join!(listener.accept(), TcpStream::connect(addr));
+        let (server, client) = (TcpConn::new(server.unwrap().0), client.unwrap());
+
+        let frame = [&8u32.to_be_bytes()[..], b"complete"].concat();
+        let (partial, rest) = frame.split_at(8);
+        client.send_to_peer(partial).await.unwrap();
+        server.stream.readable().await.unwrap();
+
+        // dropped part-way through the message, having read what has arrived so far
+        assert!(server.recv().now_or_never().is_none());
+        assert_eq!(server.read_buf.lock().await.len(), partial.len());
+
+        client.send_to_peer(rest).await.unwrap();
+        assert_eq!(&server.recv().await.unwrap()[..], b"complete");
+    }
+
+    #[tokio::test]
+    async fn large_frames_written_whole() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (server, client) = tokio::join!(listener.accept(), TcpStream::connect(addr));
+        let (mut server, client) = (server.unwrap().0, client.unwrap());
+        // the kernel rounds this up to its minimum, which is still far smaller than the frames below
+        socket2::SockRef::from(&client).set_send_buffer_size(1).unwrap();
+        let send_buffer = socket2::SockRef::from(&client).send_buffer_size().unwrap();
+
+        let large = (0..4 * 1024 * 1024).map(|idx| idx as u8).collect::<Vec<u8>>();
+        assert!(large.len() > 8 * send_buffer);
+
+        // a raw socket writes the whole buffer, though it cannot mark where it ends
+        let (sent, received) = tokio::join!(client.send_to_peer(&large), async {
+            let mut received = vec![0u8; large.len()];
+            server.read_exact(&mut received).await.map(|_| received)
+        });
+        sent.unwrap();
+        assert!(received.unwrap() == large);
+
+        let (server, client) = (TcpConn::new(server), TcpConn::new(client));
+        let (sent, received) = tokio::join!(async {
+            client.send_to_peer(&large).await?;
+            client.send_to_peer(b"next").await
+        }, async {
+            Ok::<_, std::io::Error>((server.recv().await?, server.recv().await?))
+        });
+
+        sent.unwrap();
+        let (received, next) = received.unwrap();
+        assert!(received[..] == large[..]);
+        assert_eq!(&next[..], b"next");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tapped_frames() {
+        let (server_conn, client_conn) = channel_pair();
+        let server_conn = TappedConn::new(server_conn);
+        let log = server_conn.log();
+        let (server, client) = tokio::join!(
+            NetworkApplication::register(RelativeNodeType::Receiver, server_conn),
+            NetworkApplication::register(RelativeNodeType::Initiator, client_conn)
+        );
+
+        let (server, client) = (server.unwrap(), client.unwrap());
+        let _ = tokio::join!(server.peer_capabilities(), client.peer_capabilities());
+        // `Greeter`, then `Hello`, which is recorded once its write returns
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert_eq!(log.sent().len(), 2);
+
+        // the variant index of `Greeter`, as a little-endian u32
+        assert_eq!(&log.sent()[0][..], &[3, 0, 0, 0][..]);
+
+        let mut held = drain_prereserved(&server);
+        held.extend(drain_prereserved(&client));
+
+        log.clear();
+        let (server_sub, _client_sub) = open_pair(&server, &client).await;
+
+        // the variant index of `PreCreate`, then the id as a little-endian u64. The Initiator echoes the same frame
+        let mut pre_create = vec![2, 0, 0, 0];
+        pre_create.extend_from_slice(&u64::from(server_sub.id()).to_le_bytes());
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        let frames = log.frames();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].0, Direction::Sent);
+        assert_eq!(frames[1].0, Direction::Received);
+        assert!(frames.iter().all(|(_, frame)| frame[..] == pre_create[..]));
+    }
+}
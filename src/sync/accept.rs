@@ -0,0 +1,580 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use futures::future::BoxFuture;
+use futures::Stream;
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::{UnboundedSender, UnboundedReceiver, unbounded_channel};
+use tokio::sync::oneshot;
+use tokio::time::{Duration, Instant};
+
+use crate::multiplex::{MultiplexedConn, MultiplexedConnKey, MultiplexedPacket, OwnedMultiplexedSubscription};
+use crate::reliable_conn::ReliableOrderedStreamToTargetExt;
+use crate::sync::subscription::{Subscribable, SubscriptionBiStream};
+use crate::sync::channel::typed_channel::TypedSubscription;
+use crate::sync::primitives::NetObject;
+
+/// Bookkeeping for opens that require the adjacent node's admission, in both directions
+pub(crate) struct OpenRegistry {
+    inbound_tx: UnboundedSender<InboundOpen>,
+    inbound_rx: Mutex<UnboundedReceiver<InboundOpen>>,
+    outbound: parking_lot::Mutex<HashMap<u64, oneshot::Sender<Result<(), String>>>>,
+    nonce: AtomicU64,
+    rate_limit: Option<parking_lot::Mutex<TokenBucket>>,
+    rejected: AtomicU64,
+    recent_rejections: parking_lot::Mutex<RejectionWindow>,
+    // Initiator: open signals already admitted as open requests, which are not counted against the rate again
+    expected_signals: AtomicU64
+}
+
+/// Admits up to `rate` events per second on average, with bursts of up to `rate`
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant
+}
+
+impl TokenBucket {
+    fn new(rate: u32) -> Self {
+        Self { rate: rate as f64, tokens: rate as f64, last_refill: Instant::now() }
+    }
+
+    fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        self.tokens = f64::min(self.rate, self.tokens + now.duration_since(self.last_refill).as_secs_f64() * self.rate);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Estimates the number of rejections over the last second from counts over two consecutive one-second windows, weighting
+/// the previous window by how much of it still overlaps the last second
+struct RejectionWindow {
+    start: Instant,
+    current: u64,
+    previous: u64
+}
+
+impl RejectionWindow {
+    const WINDOW: Duration = Duration::from_secs(1);
+
+    fn new() -> Self {
+        Self { start: Instant::now(), current: 0, previous: 0 }
+    }
+
+    fn roll(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.start);
+        if elapsed >= 2 * Self::WINDOW {
+            *self = Self { start: now, current: 0, previous: 0 };
+        } else if elapsed >= Self::WINDOW {
+            self.previous = std::mem::take(&mut self.current);
+            self.start += Self::WINDOW;
+        }
+    }
+
+    fn record(&mut self) {
+        self.roll(Instant::now());
+        self.current += 1;
+    }
+
+    fn per_sec(&mut self) -> f64 {
+        let now = Instant::now();
+        self.roll(now);
+        let overlap = 1.0 - now.duration_since(self.start).as_secs_f64() / Self::WINDOW.as_secs_f64();
+        self.previous as f64 * overlap + self.current as f64
+    }
+}
+
+struct InboundOpen {
+    nonce: u64,
+    label: Option<String>
+}
+
+impl OpenRegistry {
+    pub(crate) fn new(max_opens_per_sec: Option<u32>) -> Self {
+        let (inbound_tx, inbound_rx) = unbounded_channel();
+        let rate_limit = max_opens_per_sec.map(|rate| parking_lot::Mutex::new(TokenBucket::new(rate)));
+        Self { inbound_tx, inbound_rx: Mutex::new(inbound_rx), outbound: parking_lot::Mutex::new(HashMap::new()), nonce: AtomicU64::new(0), rate_limit, rejected: AtomicU64::new(0), recent_rejections: parking_lot::Mutex::new(RejectionWindow::new()), expected_signals: AtomicU64::new(0) }
+    }
+
+    /// Returns false, and counts the rejection, if an inbound open would exceed the configured rate
+    fn admit(&self) -> bool {
+        let admitted = self.rate_limit.as_ref().map(|bucket| bucket.lock().try_take()).unwrap_or(true);
+        if !admitted {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            self.recent_rejections.lock().record();
+        }
+
+        admitted
+    }
+
+    /// Initiator: admits an open signal, which is free if it follows an open request admitted by either node
+    pub(crate) fn admit_signal(&self) -> bool {
+        let expected = self.expected_signals.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |expected| expected.checked_sub(1)).is_ok();
+        expected || self.admit()
+    }
+
+    pub(crate) fn expect_signal(&self) {
+        self.expected_signals.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn rejected_per_sec(&self) -> f64 {
+        self.recent_rejections.lock().per_sec()
+    }
+
+    fn register_outbound(&self) -> (u64, oneshot::Receiver<Result<(), String>>) {
+        let nonce = self.nonce.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.outbound.lock().insert(nonce, tx);
+        (nonce, rx)
+    }
+
+    pub(crate) fn on_request(&self, nonce: u64, label: Option<String>) -> Result<(), anyhow::Error> {
+        self.inbound_tx.send(InboundOpen { nonce, label }).map_err(|_| anyhow::Error::msg("Inbound open queue died"))
+    }
+
+    pub(crate) fn on_response(&self, nonce: u64, response: Result<(), String>) -> Result<(), anyhow::Error> {
+        let tx = self.outbound.lock().remove(&nonce).ok_or_else(|| anyhow::Error::msg("Unexpected open response"))?;
+        let _ = tx.send(response);
+        Ok(())
+    }
+}
+
+/// Invoked with each accepted inbound stream whose label it was registered for (see [`crate::config::MultiplexConfig::on_stream`])
+pub type StreamHandler<K> = Arc<dyn Fn(OwnedMultiplexedSubscription<K>) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Stream handlers keyed by label. The config is not generic over the id type, so each handler is stored type-erased
+#[derive(Clone, Default)]
+pub(crate) struct StreamHandlers(HashMap<String, Arc<dyn Any + Send + Sync>>);
+
+impl StreamHandlers {
+    pub(crate) fn insert<K: MultiplexedConnKey + 'static>(&mut self, label: String, handler: StreamHandler<K>) {
+        self.0.insert(label, Arc::new(handler));
+    }
+
+    fn get<K: MultiplexedConnKey + 'static>(&self, label: &str) -> Option<StreamHandler<K>> {
+        self.0.get(label)?.downcast_ref::<StreamHandler<K>>().cloned()
+    }
+}
+
+impl std::fmt::Debug for StreamHandlers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// What happens to an inbound stream when no handler is registered for its label
+#[derive(Clone, Debug, PartialEq, Default)]
+pub enum UnroutedPolicy {
+    /// The stream is queued for [`MultiplexedConn::accept_inbound`]
+    #[default]
+    Accept,
+    /// The stream is rejected with the given reason
+    Reject(String)
+}
+
+/// Which node opened a stream (see [`MultiplexedConn::origin`])
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Origin {
+    Local,
+    Remote
+}
+
+/// A stream the adjacent node asked to open, awaiting a decision. Dropping it without deciding rejects it
+pub struct PendingInbound<K: MultiplexedConnKey + 'static> {
+    conn: MultiplexedConn<K>,
+    nonce: u64,
+    label: Option<String>,
+    decided: bool
+}
+
+impl<K: MultiplexedConnKey + 'static> PendingInbound<K> {
+    /// The label the adjacent node opened the stream with
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Admits the stream, completing the open on both nodes
+    pub async fn accept(mut self) -> Result<OwnedMultiplexedSubscription<K>, anyhow::Error> {
+        self.decided = true;
+        if self.conn.node_type().is_initiator() {
+            self.conn.opens.expect_signal();
+        }
+
+        self.conn.conn.send_serialized(MultiplexedPacket::<K>::OpenAccepted { nonce: self.nonce }).await?;
+        let subscription: OwnedMultiplexedSubscription<K> = self.conn.initiate_subscription().await?;
+        self.conn.origins.lock().insert(subscription.id(), Origin::Remote);
+        Ok(subscription)
+    }
+
+    /// Refuses the stream before any subscriber is created. The adjacent node's open fails with `reason`
+    pub async fn reject<R: Into<String>>(mut self, reason: R) -> Result<(), anyhow::Error> {
+        self.decided = true;
+        Ok(self.conn.conn.send_serialized(MultiplexedPacket::<K>::Rejected { nonce: self.nonce, reason: reason.into() }).await?)
+    }
+}
+
+impl<K: MultiplexedConnKey + 'static> Drop for PendingInbound<K> {
+    fn drop(&mut self) {
+        if self.decided {
+            return
+        }
+
+        // the runtime may not exist while dropping
+        if let Some(rt) = self.conn.config().runtime() {
+            let (conn, nonce) = (self.conn.clone(), self.nonce);
+            rt.spawn(async move {
+                let _ = conn.conn.send_serialized(MultiplexedPacket::<K>::Rejected { nonce, reason: "Not accepted".to_string() }).await;
+            });
+        }
+    }
+}
+
+impl<K: MultiplexedConnKey + 'static> MultiplexedConn<K> {
+    /// Asks the adjacent node to open a stream labelled `label`. The adjacent node admits or rejects it via
+    /// [`MultiplexedConn::accept_inbound`]; on rejection, this returns an error carrying the adjacent node's reason
+    pub async fn open_named<L: Into<String>>(&self, label: L) -> Result<OwnedMultiplexedSubscription<K>, anyhow::Error> {
+        let (nonce, rx) = self.opens.register_outbound();
+        if let Err(err) = self.conn.send_serialized(MultiplexedPacket::<K>::OpenRequest { nonce, label: Some(label.into()) }).await {
+            let _ = self.opens.outbound.lock().remove(&nonce);
+            return Err(err.into())
+        }
+
+        match rx.await {
+            Ok(Ok(())) => {
+                let subscription: OwnedMultiplexedSubscription<K> = self.initiate_subscription().await?;
+                self.origins.lock().insert(subscription.id(), Origin::Local);
+                Ok(subscription)
+            }
+
+            Ok(Err(reason)) => Err(anyhow::Error::msg(format!("Stream open rejected by the adjacent node: {}", reason))),
+            Err(_) => Err(anyhow::Error::msg("Open request dropped"))
+        }
+    }
+
+    /// Returns which node opened the stream `id`, or None if it is not open. A stream opened through [`Self::open_named`]
+    /// originates with the node that called it. Streams that both nodes open through `initiate_subscription`, including
+    /// pre-reserved streams, originate with the Receiver, whose open signal the Initiator accepts
+    pub fn origin(&self, id: K) -> Option<Origin> {
+        if !self.is_open(id) {
+            return None
+        }
+
+        let role_default = if self.node_type().is_initiator() { Origin::Remote } else { Origin::Local };
+        Some(self.origins.lock().get(&id).copied().unwrap_or(role_default))
+    }
+
+    /// The number of inbound opens rejected for exceeding the configured open rate (see
+    /// [`crate::config::MultiplexConfig::with_max_opens_per_sec`]). See [`Self::opens_rejected_rate`] for the current rate
+    pub fn opens_rejected(&self) -> u64 {
+        self.opens.rejected()
+    }
+
+    /// The number of inbound opens rejected for exceeding the configured open rate over roughly the last second
+    pub fn opens_rejected_rate(&self) -> f64 {
+        self.opens.rejected_per_sec()
+    }
+
+    /// Rejects inbound opens beyond the configured rate, then dispatches the rest to their registered handler, if any,
+    /// otherwise applies the [`UnroutedPolicy`]
+    pub(crate) fn route_inbound(&self, nonce: u64, label: Option<String>) -> Result<(), anyhow::Error> {
+        let (handler, reject_reason) = if self.opens.admit() {
+            let handler = label.as_deref().and_then(|label| self.config().stream_handlers.get::<K>(label));
+            match (handler, self.config().unrouted_policy()) {
+                (None, UnroutedPolicy::Accept) => return self.opens.on_request(nonce, label),
+                (None, UnroutedPolicy::Reject(reason)) => (None, Some(reason.clone())),
+                (handler, _) => (handler, None)
+            }
+        } else {
+            (None, Some("Open rate limit exceeded".to_string()))
+        };
+
+        let rt = self.config().runtime().ok_or_else(|| anyhow::Error::msg("No runtime available to route the inbound stream"))?;
+        let pending = PendingInbound { conn: self.clone(), nonce, label, decided: false };
+
+        rt.spawn(async move {
+            if let Some(handler) = handler {
+                match pending.accept().await {
+                    Ok(subscription) => (handler)(subscription).await,
+                    Err(err) => log::warn!("Unable to accept routed stream: {:?}", err)
+                }
+            } else if let Err(err) = pending.reject(reject_reason.unwrap_or_default()).await {
+                log::warn!("Unable to reject unrouted stream: {:?}", err)
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Waits for the adjacent node to request a stream via [`MultiplexedConn::open_named`]
+    pub async fn accept_inbound(&self) -> Result<PendingInbound<K>, anyhow::Error> {
+        let InboundOpen { nonce, label } = self.opens.inbound_rx.lock().await.recv().await.ok_or_else(|| anyhow::Error::msg("Inbound open queue died"))?;
+        Ok(PendingInbound { conn: self.clone(), nonce, label, decided: false })
+    }
+
+    /// Accepts every stream the adjacent node opens via [`MultiplexedConn::open_named`], yielding each with its label
+    /// and carrying `M`s (see [`TypedSubscription::into_typed`] for streams whose label implies another type). Streams
+    /// opened without a label are yielded with an empty one. The returned stream ends once the connection does
+    pub fn incoming_typed<M: NetObject>(&self) -> impl Stream<Item=(String, TypedSubscription<M, Self>)> + Send + 'static {
+        let conn = self.clone();
+        async_stream::stream! {
+            let demux_ended = conn.demux_result();
+            tokio::pin!(demux_ended);
+
+            loop {
+                // the stream holds the connection open, so its inbound queue alone would never end
+                let pending = tokio::select! {
+                    res = conn.accept_inbound() => match res {
+                        Ok(pending) => pending,
+                        Err(_) => break
+                    },
+                    _ = &mut demux_ended => break
+                };
+
+                let label = pending.label().unwrap_or_default().to_string();
+                match pending.accept().await {
+                    Ok(subscription) => yield (label, TypedSubscription::new(subscription)),
+                    Err(err) => log::warn!("Unable to accept inbound stream {:?}: {:?}", label, err)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::test_utils::{create_streams, create_streams_with_config, create_channel_streams_with_config, open_pair, drain_prereserved, channel_pair};
+    use crate::reliable_conn::ReliableOrderedStreamToTargetExt;
+    use crate::config::MultiplexConfig;
+    use crate::multiplex::{MultiplexedPacket, OwnedMultiplexedSubscription};
+    use crate::negotiation::Capabilities;
+    use crate::sync::accept::{UnroutedPolicy, Origin};
+    use crate::sync::network_application::{NetworkApplication, INITIAL_CAPACITY};
+    use crate::sync::{RelativeNodeType, SymmetricConvID};
+    use crate::sync::subscription::{Subscribable, SubscriptionBiStream};
+    use futures::{FutureExt, StreamExt};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn reject_inbound() {
+        let (server, client) = create_streams().await;
+
+        let acceptor = tokio::spawn(async move {
+            for _ in 0..2 {
+                let pending = server.accept_inbound().await.unwrap();
+                match pending.label() {
+                    Some("rpc") => {
+                        let stream = pending.accept().await.unwrap();
+                        stream.send_serialized(1u64).await.unwrap();
+                        assert_eq!(stream.recv_serialized::<u64>().await.unwrap(), 2);
+                    }
+
+                    _ => pending.reject("unknown service").await.unwrap()
+                }
+            }
+        });
+
+        match client.open_named("metrics").await {
+            Err(err) => assert!(err.to_string().contains("unknown service")),
+            Ok(_) => panic!("Open should have been rejected")
+        }
+
+        let stream = client.open_named("rpc").await.unwrap();
+        assert_eq!(stream.recv_serialized::<u64>().await.unwrap(), 1);
+        stream.send_serialized(2u64).await.unwrap();
+        acceptor.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stream_origin() {
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default()).await;
+        let accept = |conn: crate::sync::network_application::NetworkApplication| async move {
+            conn.accept_inbound().await.unwrap().accept().await.unwrap()
+        };
+
+        let (from_server, accepted_by_client) = tokio::join!(server.open_named("a"), accept(client.clone()));
+        let (from_client, accepted_by_server) = tokio::join!(client.open_named("b"), accept(server.clone()));
+        let (from_server, from_client) = (from_server.unwrap(), from_client.unwrap());
+        let (a, b) = (from_server.id(), from_client.id());
+        assert_eq!((accepted_by_client.id(), accepted_by_server.id()), (a, b));
+
+        assert_eq!((server.origin(a), server.origin(b)), (Some(Origin::Local), Some(Origin::Remote)));
+        assert_eq!((client.origin(a), client.origin(b)), (Some(Origin::Remote), Some(Origin::Local)));
+
+        // a symmetric open originates with the Receiver
+        let (server_sub, _client_sub) = open_pair(&server, &client).await;
+        assert_eq!((server.origin(server_sub.id()), client.origin(server_sub.id())), (Some(Origin::Local), Some(Origin::Remote)));
+
+        // closed streams have no origin
+        drop((from_server, accepted_by_client));
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert_eq!((server.origin(a), client.origin(a)), (None, None));
+    }
+
+    #[tokio::test]
+    async fn route_by_label() {
+        let config = MultiplexConfig::new()
+            .on_stream("rpc", |stream: OwnedMultiplexedSubscription| async move {
+                let request = stream.recv_serialized::<u64>().await.unwrap();
+                stream.send_serialized(format!("rpc: {}", request + 1)).await.unwrap();
+            })
+            .on_stream("metrics", |stream: OwnedMultiplexedSubscription| async move {
+                let request = stream.recv_serialized::<u64>().await.unwrap();
+                stream.send_serialized(format!("metrics: {}", request)).await.unwrap();
+            })
+            .with_unrouted_policy(UnroutedPolicy::Reject("no route".to_string()));
+
+        let (_server, client) = create_streams_with_config(config).await;
+
+        let rpc = client.open_named("rpc").await.unwrap();
+        rpc.send_serialized(10u64).await.unwrap();
+        assert_eq!(rpc.recv_serialized::<String>().await.unwrap(), "rpc: 11");
+
+        let metrics = client.open_named("metrics").await.unwrap();
+        metrics.send_serialized(10u64).await.unwrap();
+        assert_eq!(metrics.recv_serialized::<String>().await.unwrap(), "metrics: 10");
+
+        match client.open_named("unknown").await {
+            Err(err) => assert!(err.to_string().contains("no route")),
+            Ok(_) => panic!("Open should have been rejected")
+        }
+    }
+
+    #[tokio::test]
+    async fn incoming_typed() {
+        let (server, client) = create_streams().await;
+        let service = tokio::spawn(async move {
+            let incoming = server.incoming_typed::<u64>();
+            futures::pin_mut!(incoming);
+            for _ in 0..2 {
+                let (name, stream) = incoming.next().await.unwrap();
+                match name.as_str() {
+                    "double" => {
+                        let value = stream.recv().await.unwrap();
+                        stream.send(value * 2).await.unwrap();
+                    }
+
+                    "shout" => {
+                        let stream = stream.into_typed::<String>();
+                        let text = stream.recv().await.unwrap();
+                        stream.send(text.to_uppercase()).await.unwrap();
+                    }
+
+                    name => panic!("Unexpected stream {}", name)
+                }
+            }
+        });
+
+        let double = client.open_named("double").await.unwrap();
+        double.send_serialized(21u64).await.unwrap();
+        assert_eq!(double.recv_serialized::<u64>().await.unwrap(), 42);
+
+        let shout = client.open_named("shout").await.unwrap();
+        shout.send_serialized("hello".to_string()).await.unwrap();
+        assert_eq!(shout.recv_serialized::<String>().await.unwrap(), "HELLO");
+        service.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn open_rate_limit() {
+        let config = MultiplexConfig::new()
+            .on_stream("svc", |_stream: OwnedMultiplexedSubscription| async move {})
+            .with_max_opens_per_sec(5);
+
+        let (server, client) = create_streams_with_config(config).await;
+        let mut admitted = 0;
+
+        for _ in 0..10 {
+            if client.open_named("svc").await.is_ok() {
+                admitted += 1;
+            }
+        }
+
+        // the clock is paused, so only the initial burst is admitted
+        assert_eq!(admitted, 5);
+        assert_eq!(server.opens_rejected(), 5);
+        assert!((server.opens_rejected_rate() - 5.0).abs() < 0.1);
+
+        // the steady-state rate is admitted once the bucket refills
+        tokio::time::advance(Duration::from_millis(400)).await;
+        assert!(client.open_named("svc").await.is_ok());
+        assert!(client.open_named("svc").await.is_ok());
+        assert!(client.open_named("svc").await.is_err());
+        assert_eq!(server.opens_rejected(), 6);
+        assert!((server.opens_rejected_rate() - 6.0).abs() < 0.1);
+
+        // the rate falls back to zero once a rejection-free second has passed, while the total is kept
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(server.opens_rejected_rate(), 0.0);
+        assert_eq!(server.opens_rejected(), 6);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn open_signal_rate_limit() {
+        let (server, client) = create_streams_with_config(MultiplexConfig::new().with_max_opens_per_sec(2)).await;
+        // drain the pre-reserved streams so that each open sends an open signal to the Initiator
+        let mut held = drain_prereserved(&server);
+        held.extend(drain_prereserved(&client));
+
+        for _ in 0..2 {
+            let (server_stream, client_stream) = open_pair(&server, &client).await;
+            held.push(server_stream);
+            held.push(client_stream);
+        }
+
+        // the server is the Receiver, so the client rejects its open signal, failing the open on both nodes
+        let (server_stream, client_stream) = tokio::join!(server.initiate_subscription(), client.initiate_subscription());
+        assert!(server_stream.err().unwrap().to_string().contains("Open rate limit exceeded"));
+        assert!(client_stream.is_err());
+        assert_eq!(client.opens_rejected(), 1);
+
+        // the rejected id is consumed on both nodes, so the next open agrees on its id
+        tokio::time::advance(Duration::from_millis(500)).await;
+        let (server_stream, client_stream) = open_pair(&server, &client).await;
+        assert_eq!(server_stream.id(), client_stream.id());
+        server_stream.send_serialized(7u64).await.unwrap();
+        assert_eq!(client_stream.recv_serialized::<u64>().await.unwrap(), 7);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn open_signal_rate_limit_without_open_reject() {
+        let (conn, peer) = channel_pair();
+        peer.send_serialized(MultiplexedPacket::<SymmetricConvID>::Greeter).await.unwrap();
+        let conn = NetworkApplication::register_with_config(RelativeNodeType::Initiator, conn, MultiplexConfig::new().with_max_opens_per_sec(2)).await.unwrap();
+        // the adjacent node advertises no capabilities, so it cannot be told of a rejection
+        let capabilities = Capabilities { features: Vec::new(), ..Capabilities::local() };
+        peer.send_serialized(MultiplexedPacket::<SymmetricConvID>::Hello { capabilities }).await.unwrap();
+        let _ = conn.peer_capabilities().await.unwrap();
+        let mut held = drain_prereserved(&conn);
+
+        let ids = (1..=3).map(|offset| SymmetricConvID::from(INITIAL_CAPACITY as u64 + offset)).collect::<Vec<_>>();
+        for id in &ids {
+            peer.send_serialized(MultiplexedPacket::PreCreate { id: *id }).await.unwrap();
+        }
+
+        // the signals within the rate are answered, while the one beyond it is dropped and counted
+        for id in &ids[..2] {
+            let stream = conn.initiate_subscription().await.unwrap();
+            assert_eq!(stream.id(), *id);
+            held.push(stream);
+        }
+
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert_eq!(conn.opens_rejected(), 1);
+        assert!(conn.initiate_subscription().now_or_never().is_none());
+        while let Some(packet) = peer.recv_serialized::<MultiplexedPacket<SymmetricConvID>>().now_or_never() {
+            assert!(!matches!(packet.unwrap(), MultiplexedPacket::PreCreateRejected { .. }));
+        }
+    }
+}
@@ -1 +1,2 @@
-pub mod bi_channel;
\ No newline at end of file
+pub mod bi_channel;
+pub mod typed_channel;
\ No newline at end of file
@@ -0,0 +1,111 @@
+use crate::sync::primitives::NetObject;
+use crate::sync::subscription::Subscribable;
+use crate::sync::channel::bi_channel::InnerChannel;
+use crate::reliable_conn::ReliableOrderedStreamToTargetExt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// The sending half of a typed channel (see [`crate::multiplex::MultiplexedConn::typed_channel`]). The stream closes
+/// once both halves drop
+pub struct TypedSender<T: NetObject, S: Subscribable + 'static> {
+    chan: Arc<InnerChannel<S>>,
+    _pd: PhantomData<fn(T)>
+}
+
+/// The receiving half of a typed channel (see [`crate::multiplex::MultiplexedConn::typed_channel`]). The stream closes
+/// once both halves drop
+pub struct TypedReceiver<T: NetObject, S: Subscribable + 'static> {
+    chan: Arc<InnerChannel<S>>,
+    _pd: PhantomData<fn() -> T>
+}
+
+impl<T: NetObject, S: Subscribable + 'static> TypedSender<T, S> {
+    pub async fn send(&self, t: T) -> Result<(), anyhow::Error> {
+        Ok(self.chan.send_serialized(t).await?)
+    }
+}
+
+impl<T: NetObject, S: Subscribable + 'static> TypedReceiver<T, S> {
+    pub async fn recv(&self) -> Result<T, anyhow::Error> {
+        Ok(self.chan.recv_serialized::<T>().await?)
+    }
+}
+
+/// A stream carrying `T`s in both directions, such as those yielded by [`crate::multiplex::MultiplexedConn::incoming_typed`].
+/// The stream closes once it drops
+pub struct TypedSubscription<T: NetObject, S: Subscribable + 'static> {
+    chan: InnerChannel<S>,
+    _pd: PhantomData<fn(T) -> T>
+}
+
+impl<T: NetObject, S: Subscribable + 'static> TypedSubscription<T, S> {
+    pub(crate) fn new(chan: InnerChannel<S>) -> Self {
+        Self { chan, _pd: PhantomData }
+    }
+
+    pub async fn send(&self, t: T) -> Result<(), anyhow::Error> {
+        Ok(self.chan.send_serialized(t).await?)
+    }
+
+    pub async fn recv(&self) -> Result<T, anyhow::Error> {
+        Ok(self.chan.recv_serialized::<T>().await?)
+    }
+
+    /// Reinterprets the stream as carrying `U`s, as when the stream's name determines what it carries
+    pub fn into_typed<U: NetObject>(self) -> TypedSubscription<U, S> {
+        TypedSubscription::new(self.chan)
+    }
+
+    /// Returns the untyped stream
+    pub fn into_inner(self) -> InnerChannel<S> {
+        self.chan
+    }
+}
+
+/// Splits an opened stream into a sender of `Out`s and a receiver of `In`s
+pub(crate) fn typed_pair<Out: NetObject, In: NetObject, S: Subscribable + 'static>(chan: InnerChannel<S>) -> (TypedSender<Out, S>, TypedReceiver<In, S>) {
+    let chan = Arc::new(chan);
+    (TypedSender { chan: chan.clone(), _pd: PhantomData }, TypedReceiver { chan, _pd: PhantomData })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::test_utils::create_streams;
+    use serde::{Serialize, Deserialize};
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    enum Request {
+        Add(u32, u32),
+        Echo(String)
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    enum Response {
+        Sum(u32),
+        Echoed(String)
+    }
+
+    #[tokio::test]
+    async fn typed_channel() {
+        let (server, client) = create_streams().await;
+        let (server_pair, client_pair) = tokio::join!(server.typed_channel::<Response, Request>(), client.typed_channel::<Request, Response>());
+        let ((responses, requests), (client_tx, client_rx)) = (server_pair.unwrap(), client_pair.unwrap());
+
+        let server = tokio::spawn(async move {
+            for _ in 0..2 {
+                let response = match requests.recv().await.unwrap() {
+                    Request::Add(a, b) => Response::Sum(a + b),
+                    Request::Echo(text) => Response::Echoed(text)
+                };
+
+                responses.send(response).await.unwrap();
+            }
+        });
+
+        client_tx.send(Request::Add(2, 3)).await.unwrap();
+        client_tx.send(Request::Echo("hello".to_string())).await.unwrap();
+        assert_eq!(client_rx.recv().await.unwrap(), Response::Sum(5));
+        assert_eq!(client_rx.recv().await.unwrap(), Response::Echoed("hello".to_string()));
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use async_trait::async_trait;
+use bytes::Bytes;
+use tokio::sync::Mutex;
+
+use crate::multiplex::{MultiplexedConnKey, MultiplexedPacket};
+use crate::reliable_conn::{ReliableOrderedStreamToTarget, SerializedBuffer, serialize_to_buffer};
+use crate::sync::priority::Priority;
+
+/// Accumulates outbound packets for up to the coalesce window, then writes them to the transport as one `Batch` frame,
+/// highest priority first. Each send returns with the outcome of writing its batch
+pub(crate) struct CoalescingConn {
+    inner: Arc<dyn ReliableOrderedStreamToTarget>,
+    encode_batch: fn(Vec<Vec<u8>>) -> std::io::Result<SerializedBuffer>,
+    window: std::time::Duration,
+    runtime: Option<tokio::runtime::Handle>,
+    peer_max_frame: Arc<AtomicUsize>,
+    pending: Arc<parking_lot::Mutex<PendingBatch>>,
+    // notified each time a batch is taken for writing
+    taken: Arc<tokio::sync::Notify>,
+    // held from taking a batch until it is written, so that batches reach the transport in the order they were taken
+    write_lock: Arc<Mutex<()>>
+}
+
+/// The encoded size a batch reaches before it is written without waiting out the coalesce window
+pub(crate) const MAX_COALESCED_BYTES: usize = 64 * 1024;
+/// The bytes a `Batch` frame holds besides its frames: the variant index and the count of frames
+const BATCH_HEADER_BYTES: usize = 12;
+/// The bytes a `Batch` frame adds to each frame it holds: the frame's length
+const BATCH_FRAME_PREFIX_BYTES: usize = 8;
+
+/// The outcome of writing a batch, as handed to each send in it. io::Error is not Clone, so the kind and message are kept
+type BatchOutcome = Result<(), (std::io::ErrorKind, String)>;
+
+#[derive(Default)]
+struct PendingBatch {
+    frames: Vec<(Priority, Vec<u8>)>,
+    // the size of the batch once encoded
+    bytes: usize,
+    // set once the batch is being written early, after which no more frames join it
+    full: bool,
+    // one per frame, resolved once the batch is written
+    waiters: Vec<tokio::sync::oneshot::Sender<BatchOutcome>>
+}
+
+impl CoalescingConn {
+    pub(crate) fn new<K: MultiplexedConnKey>(inner: Arc<dyn ReliableOrderedStreamToTarget>, window: std::time::Duration, runtime: Option<tokio::runtime::Handle>, peer_max_frame: Arc<AtomicUsize>) -> Self {
+        // the batch packet is encoded through a plain fn so that this type need not be generic over (or outlive) K
+        fn encode_batch<K: MultiplexedConnKey>(frames: Vec<Vec<u8>>) -> std::io::Result<SerializedBuffer> {
+            serialize_to_buffer(&MultiplexedPacket::<K>::Batch { frames })
+        }
+
+        Self { inner, encode_batch: encode_batch::<K>, window, runtime, peer_max_frame, pending: Arc::new(parking_lot::Mutex::new(PendingBatch::default())), taken: Arc::new(tokio::sync::Notify::new()), write_lock: Arc::new(Mutex::new(())) }
+    }
+
+    /// Writes the pending batch, if any, and hands the outcome to each send in it
+    async fn flush(inner: Arc<dyn ReliableOrderedStreamToTarget>, encode_batch: fn(Vec<Vec<u8>>) -> std::io::Result<SerializedBuffer>, pending: Arc<parking_lot::Mutex<PendingBatch>>, taken: Arc<tokio::sync::Notify>, write_lock: Arc<Mutex<()>>) -> std::io::Result<()> {
+        let _guard = write_lock.lock().await;
+        let PendingBatch { mut frames, waiters, .. } = std::mem::take(&mut *pending.lock());
+        taken.notify_waiters();
+        // the sort is stable, so each stream's frames keep their order
+        frames.sort_by_key(|(priority, _)| std::cmp::Reverse(*priority));
+        let mut frames = frames.into_iter().map(|(_, frame)| frame).collect::<Vec<_>>();
+
+        let res = match frames.len() {
+            0 => return Ok(()),
+            1 => inner.send_to_peer(&frames.remove(0)).await,
+            _ => match (encode_batch)(frames) {
+                Ok(batch) => inner.send_to_peer(&batch).await,
+                Err(err) => Err(err)
+            }
+        };
+
+        let outcome = res.as_ref().map(|_| ()).map_err(|err| (err.kind(), err.to_string()));
+        for waiter in waiters {
+            // the send was cancelled
+            let _ = waiter.send(outcome.clone());
+        }
+
+        res
+    }
+
+    fn spawn_flush(&self, rt: &tokio::runtime::Handle, delay: Option<std::time::Duration>) {
+        let (inner, encode_batch, pending, taken, write_lock) = (self.inner.clone(), self.encode_batch, self.pending.clone(), self.taken.clone(), self.write_lock.clone());
+        rt.spawn(async move {
+            if let Some(delay) = delay {
+                tokio::time::sleep(delay).await;
+            }
+
+            let _ = Self::flush(inner, encode_batch, pending, taken, write_lock).await;
+        });
+    }
+}
+
+#[async_trait]
+impl ReliableOrderedStreamToTarget for CoalescingConn {
+    async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+        self.send_to_peer_with_priority(input, Priority::Normal).await
+    }
+
+    async fn send_to_peer_with_priority(&self, input: &[u8], priority: Priority) -> std::io::Result<()> {
+        let rt = self.runtime.clone().or_else(|| tokio::runtime::Handle::try_current().ok())
+            .ok_or_else(|| std::io::Error::other("No runtime available to flush coalesced packets"))?;
+
+        let limit = std::cmp::min(MAX_COALESCED_BYTES, self.peer_max_frame.load(Ordering::Relaxed));
+        let (written, first_in_batch, full) = loop {
+            // created before checking the batch, so that a batch taken in between is not missed
+            let taken = self.taken.notified();
+            let write_early = {
+                let mut pending = self.pending.lock();
+                let bytes = std::cmp::max(pending.bytes, BATCH_HEADER_BYTES) + BATCH_FRAME_PREFIX_BYTES + input.len();
+                // a frame too large to share a batch within the limit is written alone, unwrapped
+                if !pending.full && (pending.frames.is_empty() || bytes <= limit) {
+                    let (tx, rx) = tokio::sync::oneshot::channel();
+                    pending.frames.push((priority, input.to_vec()));
+                    pending.bytes = bytes;
+                    pending.full = bytes >= limit;
+                    pending.waiters.push(tx);
+                    break (rx, pending.frames.len() == 1, pending.full)
+                }
+
+                // the frame does not fit, so the batch is written without waiting out the window
+                !std::mem::replace(&mut pending.full, true)
+            };
+
+            if write_early {
+                self.spawn_flush(&rt, None)
+            }
+
+            taken.await
+        };
+
+        if full {
+            self.spawn_flush(&rt, None)
+        } else if first_in_batch {
+            self.spawn_flush(&rt, Some(self.window))
+        }
+
+        match written.await {
+            Ok(outcome) => outcome.map_err(|(kind, message)| std::io::Error::new(kind, message)),
+            Err(_) => Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "The batch was dropped before being written"))
+        }
+    }
+
+    async fn recv(&self) -> std::io::Result<Bytes> {
+        self.inner.recv().await
+    }
+
+    fn peer_alive(&self) -> Option<bool> {
+        self.inner.peer_alive()
+    }
+
+    /// Writes out the pending batch first
+    async fn shutdown(&self) -> std::io::Result<()> {
+        Self::flush(self.inner.clone(), self.encode_batch, self.pending.clone(), self.taken.clone(), self.write_lock.clone()).await?;
+        self.inner.shutdown().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::coalesce::{CoalescingConn, MAX_COALESCED_BYTES, BATCH_HEADER_BYTES, BATCH_FRAME_PREFIX_BYTES};
+    use crate::multiplex::MultiplexedPacket;
+    use crate::reliable_conn::ReliableOrderedStreamToTarget;
+    use crate::sync::SymmetricConvID;
+    use crate::sync::priority::Priority;
+    use bytes::Bytes;
+    use std::time::Duration;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use tokio::time::Instant;
+    use futures::FutureExt;
+
+    /// Records the frames written to it, failing writes while `fail` is set
+    #[derive(Default)]
+    struct RecordingConn {
+        writes: parking_lot::Mutex<Vec<Vec<u8>>>,
+        fail: AtomicBool
+    }
+
+    #[async_trait::async_trait]
+    impl ReliableOrderedStreamToTarget for RecordingConn {
+        async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Write failed"))
+            }
+
+            self.writes.lock().push(input.to_vec());
+            Ok(())
+        }
+
+        async fn recv(&self) -> std::io::Result<Bytes> {
+            futures::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn coalesced_send_outcomes() {
+        let inner = Arc::new(RecordingConn::default());
+        let conn = CoalescingConn::new::<SymmetricConvID>(inner.clone(), Duration::from_millis(50), None, Arc::new(AtomicUsize::new(usize::MAX)));
+
+        // the sends in a batch return once it is written, ordered by priority
+        let sends = [(Priority::Low, 0u8), (Priority::High, 1), (Priority::Normal, 2), (Priority::High, 3)];
+        futures::future::try_join_all(sends.iter().map(|(priority, frame)| conn.send_to_peer_with_priority(std::slice::from_ref(frame), *priority))).await.unwrap();
+        let batch = inner.writes.lock().remove(0);
+        match bincode2::deserialize::<MultiplexedPacket<SymmetricConvID>>(&batch).unwrap() {
+            MultiplexedPacket::Batch { frames } => assert_eq!(frames, vec![vec![1], vec![3], vec![2], vec![0]]),
+            _ => panic!("Expected a batch")
+        }
+
+        // a failed write is returned to the sends in its batch, and to no later send
+        inner.fail.store(true, Ordering::SeqCst);
+        let (first, second) = tokio::join!(conn.send_to_peer(&[4]), conn.send_to_peer(&[5]));
+        assert_eq!(first.unwrap_err().kind(), std::io::ErrorKind::BrokenPipe);
+        assert_eq!(second.unwrap_err().kind(), std::io::ErrorKind::BrokenPipe);
+        inner.fail.store(false, Ordering::SeqCst);
+        conn.send_to_peer(&[6]).await.unwrap();
+        assert_eq!(inner.writes.lock().drain(..).collect::<Vec<_>>(), vec![vec![6]]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn coalesced_batch_bounded() {
+        let inner = Arc::new(RecordingConn::default());
+        let window = Duration::from_secs(60);
+        let conn = CoalescingConn::new::<SymmetricConvID>(inner.clone(), window, None, Arc::new(AtomicUsize::new(usize::MAX)));
+
+        // a full batch is written without waiting out the window
+        let start = Instant::now();
+        let half = vec![0; (MAX_COALESCED_BYTES - BATCH_HEADER_BYTES) / 2 - BATCH_FRAME_PREFIX_BYTES];
+        let (first, second) = tokio::join!(conn.send_to_peer(&half), conn.send_to_peer(&half));
+        first.unwrap();
+        second.unwrap();
+        assert!(start.elapsed() < window);
+        assert_eq!(inner.writes.lock().drain(..).map(|batch| batch.len()).collect::<Vec<_>>(), vec![MAX_COALESCED_BYTES]);
+
+        // a frame that would take the batch past the adjacent node's limit is left to the next, and one that cannot share
+        // a batch at all is written unwrapped
+        conn.peer_max_frame.store(1024, Ordering::Relaxed);
+        let (first, second, third) = tokio::join!(conn.send_to_peer(&[0; 400]), conn.send_to_peer(&[1; 400]), conn.send_to_peer(&[2; 1000]));
+        first.unwrap();
+        second.unwrap();
+        third.unwrap();
+        let writes = inner.writes.lock().drain(..).collect::<Vec<_>>();
+        assert_eq!(writes.len(), 2);
+        assert!(writes.iter().all(|frame| frame.len() <= 1024));
+        assert_eq!(writes[1], vec![2; 1000]);
+        conn.peer_max_frame.store(usize::MAX, Ordering::Relaxed);
+
+        // sends wait while a full batch awaits writing, and the frames they carry join the next batch
+        conn.pending.lock().full = true;
+        let mut waiting = Box::pin(conn.send_to_peer(&[2]));
+        assert!((&mut waiting).now_or_never().is_none());
+        assert_eq!(conn.pending.lock().frames.len(), 0);
+        CoalescingConn::flush(inner.clone(), conn.encode_batch, conn.pending.clone(), conn.taken.clone(), conn.write_lock.clone()).await.unwrap();
+        waiting.await.unwrap();
+        assert_eq!(inner.writes.lock().last().unwrap(), &vec![2]);
+    }
+}
@@ -0,0 +1,48 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::reliable_conn::ReliableOrderedStreamToTarget;
+use crate::sync::priority::Priority;
+
+/// Refuses to send frames larger than the adjacent node advertised it accepts
+pub(crate) struct FrameLimitedConn {
+    inner: Arc<dyn ReliableOrderedStreamToTarget>,
+    peer_max_frame: Arc<AtomicUsize>
+}
+
+impl FrameLimitedConn {
+    pub(crate) fn new(inner: Arc<dyn ReliableOrderedStreamToTarget>, peer_max_frame: Arc<AtomicUsize>) -> Self {
+        Self { inner, peer_max_frame }
+    }
+}
+
+#[async_trait]
+impl ReliableOrderedStreamToTarget for FrameLimitedConn {
+    async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+        self.send_to_peer_with_priority(input, Priority::Normal).await
+    }
+
+    /// The priority is passed on to a coalescing layer beneath, which orders its batches by it
+    async fn send_to_peer_with_priority(&self, input: &[u8], priority: Priority) -> std::io::Result<()> {
+        let max = self.peer_max_frame.load(Ordering::Relaxed);
+        if input.len() > max {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Frame of {} bytes exceeds the adjacent node's limit of {} bytes", input.len(), max)))
+        }
+
+        self.inner.send_to_peer_with_priority(input, priority).await
+    }
+
+    async fn recv(&self) -> std::io::Result<Bytes> {
+        self.inner.recv().await
+    }
+
+    fn peer_alive(&self) -> Option<bool> {
+        self.inner.peer_alive()
+    }
+
+    async fn shutdown(&self) -> std::io::Result<()> {
+        self.inner.shutdown().await
+    }
+}
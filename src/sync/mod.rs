@@ -5,6 +5,15 @@ pub mod primitives;
 pub mod collections;
 
 pub mod subscription;
+pub mod accept;
+pub mod pool;
+pub mod priority;
+pub mod reassembly;
+pub mod processing;
+pub mod swap;
+pub mod coalesce;
+pub mod frame_limit;
+pub mod partition;
 
 pub mod network_application;
 pub mod network_endpoint;
@@ -15,7 +24,7 @@ pub mod channel;
 pub mod callback_channel;
 pub mod tracked_callback_channel;
 
-#[derive(Serialize, Deserialize, Eq, PartialEq, Hash, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Copy, Clone)]
 /// Used to keep track between two symmetric actions across two nodes
 pub struct SymmetricConvID(u64);
 
@@ -25,6 +34,12 @@ impl From<u64> for SymmetricConvID {
     }
 }
 
+impl From<SymmetricConvID> for u64 {
+    fn from(item: SymmetricConvID) -> Self {
+        item.0
+    }
+}
+
 pub mod test_utils {
     use async_trait::async_trait;
     use bytes::Bytes;
@@ -34,11 +49,14 @@ pub mod test_utils {
     use tokio::sync::Mutex;
     use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
+    use crate::multiplex::OwnedMultiplexedSubscription;
     use crate::reliable_conn::{ReliableOrderedStreamToTarget, ConnAddr};
     use crate::reliable_conn::simulator::NetworkConnSimulator;
     use crate::sync::network_application::NetworkApplication;
     use crate::sync::RelativeNodeType;
     use crate::sync::network_endpoint::NetworkEndpoint;
+    use crate::sync::subscription::Subscribable;
+    use crate::config::MultiplexConfig;
     use std::net::SocketAddr;
 
     #[cfg(test)]
@@ -68,6 +86,10 @@ pub mod test_utils {
         async fn recv(&self) -> std::io::Result<Bytes> {
             Ok(self.stream.lock().await.next().await.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Stream died"))??.freeze())
         }
+
+        async fn shutdown(&self) -> std::io::Result<()> {
+            self.sink.lock().await.close().await
+        }
     }
 
     impl ConnAddr for TcpCodecFramed {
@@ -88,21 +110,56 @@ pub mod test_utils {
     }
 
     pub async fn create_streams() -> (NetworkApplication, NetworkApplication) {
+        create_streams_with_config(MultiplexConfig::default()).await
+    }
+
+    pub async fn create_streams_with_config(config: MultiplexConfig) -> (NetworkApplication, NetworkApplication) {
         let (tx, rx) = tokio::sync::oneshot::channel();
+        let server_config = config.clone();
         let server = async move {
             let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
             tx.send(listener.local_addr().unwrap()).unwrap();
-            NetworkApplication::register(RelativeNodeType::Receiver, NetworkConnSimulator::new(0, codec(listener.accept().await.unwrap().0))).await.unwrap()
+            NetworkApplication::register_with_config(RelativeNodeType::Receiver, NetworkConnSimulator::new(0, codec(listener.accept().await.unwrap().0)), server_config).await.unwrap()
         };
 
         let client = async move {
             let addr = rx.await.unwrap();
-            NetworkApplication::register(RelativeNodeType::Initiator, NetworkConnSimulator::new(0,codec(TcpStream::connect(addr).await.unwrap()))).await.unwrap()
+            NetworkApplication::register_with_config(RelativeNodeType::Initiator, NetworkConnSimulator::new(0,codec(TcpStream::connect(addr).await.unwrap())), config).await.unwrap()
         };
 
         tokio::join!(server, client)
     }
 
+    /// Returns two ends of a framed TCP transport, without any multiplexing on top
+    pub async fn create_framed_pair() -> (TcpCodecFramed, TcpCodecFramed) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (server, client) = tokio::join!(listener.accept(), TcpStream::connect(addr));
+        (codec(server.unwrap().0), codec(client.unwrap()))
+    }
+
+    /// Registers both nodes over an in-memory transport (see [`channel_pair`]), so that a paused clock governs all of their timing
+    pub async fn create_channel_streams_with_config(config: MultiplexConfig) -> (NetworkApplication, NetworkApplication) {
+        let (server_conn, client_conn) = channel_pair();
+        let (server, client) = tokio::join!(
+            NetworkApplication::register_with_config(RelativeNodeType::Receiver, server_conn, config.clone()),
+            NetworkApplication::register_with_config(RelativeNodeType::Initiator, client_conn, config)
+        );
+
+        (server.unwrap(), client.unwrap())
+    }
+
+    /// Opens a substream on both nodes at once, returning the ends in the order the nodes were given
+    pub async fn open_pair(a: &NetworkApplication, b: &NetworkApplication) -> (OwnedMultiplexedSubscription, OwnedMultiplexedSubscription) {
+        let (a_sub, b_sub) = tokio::join!(a.initiate_subscription(), b.initiate_subscription());
+        (a_sub.unwrap(), b_sub.unwrap())
+    }
+
+    /// Takes every stream pre-reserved on `conn`, so that its next open requires a handshake with the adjacent node
+    pub fn drain_prereserved(conn: &NetworkApplication) -> Vec<OwnedMultiplexedSubscription> {
+        std::iter::from_fn(|| conn.get_next_prereserved()).collect()
+    }
+
     pub async fn create_streams_with_addrs_and_lag(min: usize) -> (NetworkEndpoint, NetworkEndpoint) {
         let (tx, rx) = tokio::sync::oneshot::channel();
         let server = async move {
@@ -123,6 +180,66 @@ pub mod test_utils {
         create_streams_with_addrs_and_lag(0).await
     }
 
+    /// One end of an in-memory transport, so that a paused clock governs all of its timing
+    pub struct ChannelConn {
+        tx: tokio::sync::mpsc::UnboundedSender<Bytes>,
+        rx: Mutex<tokio::sync::mpsc::UnboundedReceiver<Bytes>>
+    }
+
+    /// Returns two ends of an in-memory transport, without any multiplexing on top
+    pub fn channel_pair() -> (ChannelConn, ChannelConn) {
+        let ((a_tx, a_rx), (b_tx, b_rx)) = (tokio::sync::mpsc::unbounded_channel(), tokio::sync::mpsc::unbounded_channel());
+        (ChannelConn { tx: a_tx, rx: Mutex::new(b_rx) }, ChannelConn { tx: b_tx, rx: Mutex::new(a_rx) })
+    }
+
+    #[async_trait]
+    impl ReliableOrderedStreamToTarget for ChannelConn {
+        async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+            self.tx.send(Bytes::copy_from_slice(input)).map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Peer dropped"))
+        }
+
+        async fn recv(&self) -> std::io::Result<Bytes> {
+            Ok(self.rx.lock().await.recv().await.unwrap_or_default())
+        }
+    }
+
+    /// A transport that can be severed, after which every frame sent on it is lost and reads fail
+    #[cfg(test)]
+    pub(crate) struct SeverableConn<T> {
+        pub(crate) inner: T,
+        pub(crate) severed: tokio::sync::watch::Receiver<bool>
+    }
+
+    #[cfg(test)]
+    #[async_trait]
+    impl<T: ReliableOrderedStreamToTarget> ReliableOrderedStreamToTarget for SeverableConn<T> {
+        async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+            if *self.severed.borrow() {
+                return Ok(())
+            }
+
+            self.inner.send_to_peer(input).await
+        }
+
+        async fn recv(&self) -> std::io::Result<Bytes> {
+            let mut severed = self.severed.clone();
+            let severed = async move {
+                while !*severed.borrow() {
+                    if severed.changed().await.is_err() {
+                        futures::future::pending::<()>().await
+                    }
+                }
+            };
+
+            // a severed conn yields nothing more, even packets that arrived before it was severed
+            tokio::select! {
+                biased;
+                _ = severed => Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "Severed")),
+                res = self.inner.recv() => res
+            }
+        }
+    }
+
     pub fn deadlock_detector() {
         log::info!("Deadlock function called ...");
         use std::thread;
@@ -158,6 +275,11 @@ pub enum RelativeNodeType {
 }
 
 impl RelativeNodeType {
+    /// The canonical tie-breaker for symmetric protocols: whenever both nodes contend for the same action, the Initiator wins
+    pub fn is_initiator(&self) -> bool {
+        *self == RelativeNodeType::Initiator
+    }
+
     pub fn into_byte(self) -> u8 {
         match self {
             RelativeNodeType::Initiator => 10,
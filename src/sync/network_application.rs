@@ -1,15 +1,22 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use futures::FutureExt;
+use bytes::Bytes;
 
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify, Semaphore, watch};
+use tokio::time::Instant;
+use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
-use crate::multiplex::{MultiplexedConn, MultiplexedConnKey, MultiplexedPacket};
+use crate::multiplex::{MultiplexedConn, MultiplexedConnKey, MultiplexedPacket, SubscriberMap, OwnedMultiplexedSubscription, StreamEvent, SharedPayloadEncoder, DemuxOutcome, DecodeError};
 use crate::reliable_conn::{ReliableOrderedStreamToTarget, ReliableOrderedStreamToTargetExt};
 use crate::sync::{RelativeNodeType, SymmetricConvID};
+use crate::sync::priority::Priority;
 use crate::sync::operations::net_join::NetJoin;
 use crate::sync::operations::net_select::NetSelect;
 use crate::sync::operations::net_select_ok::NetSelectOk;
@@ -20,41 +27,79 @@ use crate::sync::subscription::Subscribable;
 use crate::sync::sync_start::NetSyncStart;
 use crate::sync::primitives::net_rwlock::{NetRwLockLoader, NetRwLock};
 use crate::sync::channel::bi_channel;
+use crate::sync::channel::typed_channel::{self, TypedSender, TypedReceiver};
+use crate::config::{MultiplexConfig, DecodeErrorPolicy, KeepalivePolicy};
+use crate::codec::{PayloadCodec, CodecSubscription};
+use crate::exactly_once::ExactlyOnceStream;
+use crate::negotiation::Capabilities;
 
 pub type NetworkApplication = MultiplexedConn<SymmetricConvID>;
 
 pub(crate) const INITIAL_CAPACITY: usize = 32;
+/// The most transport frames the demultiplexer takes in at once to route by priority
+const MAX_READ_AHEAD: usize = 64;
 
 pub struct PreActionChannel<K: MultiplexedConnKey = SymmetricConvID> {
     tx: tokio::sync::mpsc::Sender<K>,
-    rx: Mutex<tokio::sync::mpsc::Receiver<K>>
+    rx: Mutex<tokio::sync::mpsc::Receiver<K>>,
+    cancel: Notify,
+    permits: Option<Semaphore>,
+    pending: AtomicUsize,
+    // Receiver: ids whose opens ended before the Initiator's echo arrived. Their echoes are discarded on arrival
+    abandoned: parking_lot::Mutex<HashSet<K>>
 }
 
 impl<K: MultiplexedConnKey> PreActionChannel<K> {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(max_concurrent_opens: Option<usize>) -> Self {
         let (tx, rx) = tokio::sync::mpsc::channel(1);
-        Self { tx, rx: Mutex::new(rx) }
+        Self { tx, rx: Mutex::new(rx), cancel: Notify::new(), permits: max_concurrent_opens.map(Semaphore::new), pending: AtomicUsize::new(0), abandoned: parking_lot::Mutex::new(HashSet::new()) }
+    }
+
+    /// The number of opens admitted past the [`MultiplexConfig::with_max_concurrent_opens`] limit and not yet finished
+    pub fn pending_opens(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    /// Wakes every in-flight local open with a cancellation error. Open signals already received from the adjacent node
+    /// stay queued, since the adjacent node is still waiting on them, while the echoes owed to the local node's cancelled
+    /// opens are discarded as they arrive
+    pub(crate) fn cancel_all(&self) {
+        self.cancel.notify_waiters();
+    }
+
+    /// Returns true if the open of `id` ended before its echo arrived, in which case the echo is to be discarded
+    pub(crate) fn take_abandoned(&self, id: K) -> bool {
+        self.abandoned.lock().remove(&id)
     }
 }
 
 pub struct PostActionChannel<K: MultiplexedConnKey = SymmetricConvID> {
-    tx: Mutex<HashMap<K, tokio::sync::oneshot::Sender<()>>>,
-    rx: Mutex<HashMap<K, tokio::sync::oneshot::Receiver<()>>>,
+    tx: parking_lot::Mutex<HashMap<K, tokio::sync::oneshot::Sender<()>>>,
+    rx: parking_lot::Mutex<HashMap<K, tokio::sync::oneshot::Receiver<()>>>,
 }
 
 impl<K: MultiplexedConnKey> PostActionChannel<K> {
-    pub(crate) async fn send(&self, id: K) -> Result<(), anyhow::Error> {
-        Ok(self.tx.lock().await.remove(&id).ok_or_else(|| anyhow::Error::msg("TX Channel does not exist (x0)"))?.send(()).map_err(|_| anyhow::Error::msg("Post-action channel for symmetric conv died"))?)
+    pub(crate) fn send(&self, id: K) -> Result<(), anyhow::Error> {
+        let tx = self.tx.lock().remove(&id).ok_or_else(|| anyhow::Error::msg("TX Channel does not exist (x0)"))?;
+        Ok(tx.send(()).map_err(|_| anyhow::Error::msg("Post-action channel for symmetric conv died"))?)
     }
 
     pub(crate) async fn recv(&self, id: K) -> Result<(), anyhow::Error> {
-        Ok(self.rx.lock().await.remove(&id).ok_or_else(|| anyhow::Error::msg("RX Channel does not exist (x0)"))?.await?)
+        // taken out first, so that the lock is not held while awaiting the signal
+        let rx = self.rx.lock().remove(&id).ok_or_else(|| anyhow::Error::msg("RX Channel does not exist (x0)"))?;
+        Ok(rx.await?)
     }
 
-    pub(crate) async fn setup_channel(&self, id: K) {
+    pub(crate) fn setup_channel(&self, id: K) {
         let (tx, rx) = tokio::sync::oneshot::channel();
-        self.tx.lock().await.insert(id, tx);
-        self.rx.lock().await.insert(id, rx);
+        self.tx.lock().insert(id, tx);
+        self.rx.lock().insert(id, rx);
+    }
+
+    /// Removes the channel set up for `id`, if any, for an open that did not complete
+    pub(crate) fn discard_channel(&self, id: K) {
+        let _ = self.tx.lock().remove(&id);
+        let _ = self.rx.lock().remove(&id);
     }
 }
 
@@ -67,12 +112,233 @@ impl<K: MultiplexedConnKey> PostActionChannel<K> {
             rx.insert(*id, rx_s);
         }
 
-        Self { tx: Mutex::new(tx), rx: Mutex::new(rx) }
+        Self { tx: parking_lot::Mutex::new(tx), rx: parking_lot::Mutex::new(rx) }
     }
 }
 
 impl<K: MultiplexedConnKey + 'static> MultiplexedConn<K> {
     pub async fn register<T: ReliableOrderedStreamToTarget + 'static>(relative_node_type: RelativeNodeType, t: T) -> Result<Self, anyhow::Error> {
+        Self::register_with_config(relative_node_type, t, MultiplexConfig::default()).await
+    }
+
+    pub async fn register_with_config<T: ReliableOrderedStreamToTarget + 'static>(relative_node_type: RelativeNodeType, t: T, config: MultiplexConfig) -> Result<Self, anyhow::Error> {
+        Self::greet(relative_node_type, &t).await?;
+
+        let rt = config.runtime().ok_or_else(|| anyhow::Error::msg("No runtime available to spawn the demultiplexer"))?;
+        let this = Self::new_with_config(relative_node_type, t, config);
+        let hello_conn = this.clone();
+        // the demultiplexer holds no handle of its own while idle, so that it cannot keep the connection alive by itself
+        let (demux, transport, demux_status, mut handles_alive) = (this.downgrade(), this.conn.clone(), this.demux_status.clone(), this.handles_alive());
+
+        // sent independently of the demultiplexer so that a transport that cannot yet accept writes does not stall inbound processing
+        rt.spawn(async move {
+            if let Err(err) = hello_conn.conn.send_serialized(MultiplexedPacket::<K>::Hello { capabilities: hello_conn.local_capabilities() }).await {
+                log::warn!("Unable to advertise capabilities: {:?}", err);
+            }
+        });
+
+        let (keepalive_transport, mut keepalive, demux_ended, backoff) = (this.conn.clone(), this.keepalive_watch(), this.demux_result(), *this.config().backoff());
+        rt.spawn(async move {
+            tokio::pin!(demux_ended);
+            // the number of consecutive failed sends. Each failure is retried after the backoff rather than the interval
+            let mut failures = 0;
+            loop {
+                let delay = match failures {
+                    0 => keepalive.borrow().map(|policy| policy.interval),
+                    failures => Some(backoff.delay(failures - 1))
+                };
+
+                let tick = async move {
+                    match delay {
+                        Some(delay) => tokio::time::sleep(delay).await,
+                        None => futures::future::pending().await
+                    }
+                };
+
+                tokio::select! {
+                    _ = tick => match keepalive_transport.send_serialized(MultiplexedPacket::<K>::Keepalive).await {
+                        Ok(()) => failures = 0,
+                        Err(err) => {
+                            log::warn!("Unable to send keepalive: {:?}", err);
+                            failures += 1
+                        }
+                    },
+                    // the interval was renegotiated
+                    res = keepalive.changed() => if res.is_err() { break },
+                    _ = &mut demux_ended => break
+                }
+            }
+        });
+
+        // partial messages are swept on a timer, so that they are discarded even if no further fragments arrive. The timer
+        // runs only while any are held
+        let (sweeper, sweep_interval, sweep_pending, sweep_demux_ended) = (this.downgrade(), this.reassembly.timeout(), this.reassembly.pending(), this.demux_result());
+        rt.spawn(async move {
+            tokio::pin!(sweep_demux_ended);
+            loop {
+                tokio::select! {
+                    _ = sweep_pending.notified() => {},
+                    _ = &mut sweep_demux_ended => break
+                }
+
+                let mut remaining = true;
+                while remaining {
+                    tokio::select! {
+                        _ = tokio::time::sleep(sweep_interval) => match Self::upgrade(&sweeper) {
+                            Some(conn) => remaining = conn.reassembly.sweep(),
+                            None => return
+                        },
+                        _ = &mut sweep_demux_ended => return
+                    }
+                }
+            }
+        });
+
+        // warm streams are evicted on a timer once they outlive the TTL, even if no later open draws on the pool. The
+        // timer runs only while any are warm
+        let (pool_sweeper, pool_pending, pool_demux_ended) = (this.downgrade(), this.pool.pending(), this.demux_result());
+        rt.spawn(async move {
+            tokio::pin!(pool_demux_ended);
+            loop {
+                tokio::select! {
+                    _ = pool_pending.notified() => {},
+                    _ = &mut pool_demux_ended => break
+                }
+
+                loop {
+                    let next_expiry = match Self::upgrade(&pool_sweeper) {
+                        Some(conn) => conn.expire_pooled().await,
+                        None => return
+                    };
+
+                    let next_expiry = match next_expiry {
+                        Some(next_expiry) => next_expiry,
+                        None => break
+                    };
+
+                    tokio::select! {
+                        _ = tokio::time::sleep_until(next_expiry) => {},
+                        _ = &mut pool_demux_ended => return
+                    }
+                }
+            }
+        });
+
+        let (mut idle, peer_capabilities) = (this.keepalive_watch(), this.capabilities_watch());
+        rt.spawn(async move {
+            let mut ended = None;
+            // set once either node closes the connection cleanly
+            let mut goodbye = false;
+
+            let outcome = loop {
+                if let Some(outcome) = ended.take() {
+                    break outcome
+                }
+
+                let packet = tokio::select! {
+                    packet = transport.recv() => packet,
+                    // every handle, including those held by subscriptions, has been dropped
+                    _ = handles_alive.changed() => {
+                        goodbye = true;
+                        break Self::say_goodbye(&transport, &peer_capabilities).await
+                    },
+                    // measured from when the demultiplexer began waiting, so that time spent routing is not counted against the adjacent node
+                    timeout = idle_timeout(Instant::now(), &mut idle) => break Err((std::io::ErrorKind::TimedOut, format!("Nothing arrived from the adjacent node within the keepalive timeout of {:?}", timeout)))
+                };
+
+                let mut packets = match packet {
+                    // every valid packet is non-empty, so an empty read signals EOF
+                    Ok(packet) if packet.is_empty() => break Ok(()),
+                    Ok(packet) => vec![packet],
+                    Err(err) => break Err((err.kind(), err.to_string()))
+                };
+
+                // packets that have already arrived are read ahead, so that they can be routed in order of priority
+                ended = read_ahead::<K, _>(&*transport, &mut packets);
+
+                let conn_task = match Self::upgrade(&demux) {
+                    Some(conn_task) => conn_task,
+                    None => {
+                        goodbye = true;
+                        break Self::say_goodbye(&transport, &peer_capabilities).await
+                    }
+                };
+
+                if let Some(outcome) = conn_task.route_packets(&packets).await {
+                    // only a goodbye ends the level cleanly
+                    goodbye = outcome.is_ok();
+                    ended = Some(outcome);
+                }
+            };
+
+            log::info!("Demultiplexer ending: {:?}", outcome);
+            if !goodbye {
+                if let Some(conn) = Self::upgrade(&demux) {
+                    conn.on_peer_died()
+                }
+            }
+
+            let _ = demux_status.send(Some(outcome));
+        });
+
+        Ok(this)
+    }
+
+    /// Registers a connection whose inbound frames are routed only by [`Self::poll_once`], rather than by a demultiplexer
+    /// task. Nothing is spawned, so there are no keepalives, and neither timers sweeping partial fragmented messages and
+    /// expired pooled streams nor a goodbye once the last handle drops. Intended for synchronous loops that poll the network once per tick
+    pub async fn register_polled<T: ReliableOrderedStreamToTarget + 'static>(relative_node_type: RelativeNodeType, t: T, config: MultiplexConfig) -> Result<Self, anyhow::Error> {
+        Self::greet(relative_node_type, &t).await?;
+        let this = Self::new_with_config(relative_node_type, t, config);
+        this.conn.send_serialized(MultiplexedPacket::<K>::Hello { capabilities: this.local_capabilities() }).await?;
+        Ok(this)
+    }
+
+    /// For a connection registered through [`Self::register_polled`], routes every frame the transport has ready without
+    /// waiting for more, and returns how many were routed. Streams open, close and receive only as this is called, so the
+    /// opens and closes awaited in between must run concurrently with it. Fails once the transport fails or ends, or the
+    /// adjacent level says goodbye, after which [`Self::demux_result`] resolves. The transport's `recv` must be cancel-safe
+    pub async fn poll_once(&self) -> std::io::Result<usize> {
+        let ended = |outcome: DemuxOutcome| match outcome {
+            Ok(()) => std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "The connection ended"),
+            Err((kind, reason)) => std::io::Error::new(kind, reason)
+        };
+
+        if let Some(outcome) = self.demux_status_rx.borrow().clone() {
+            return Err(ended(outcome))
+        }
+
+        let mut routed = 0;
+        loop {
+            let mut packets = Vec::new();
+            let mut outcome = read_ahead::<K, _>(&*self.conn, &mut packets);
+            if packets.is_empty() && outcome.is_none() {
+                break
+            }
+
+            let mut goodbye = false;
+            if let Some(routed_outcome) = self.route_packets(&packets).await {
+                goodbye = routed_outcome.is_ok();
+                outcome = Some(routed_outcome);
+            }
+
+            routed += packets.len();
+            if let Some(outcome) = outcome {
+                log::info!("Polled connection ending: {:?}", outcome);
+                if !goodbye {
+                    self.on_peer_died()
+                }
+
+                let _ = self.demux_status.send(Some(outcome.clone()));
+                return Err(ended(outcome))
+            }
+        }
+
+        Ok(routed)
+    }
+
+    /// The Receiver invites the Initiator, after which the connection can be assembled on both nodes
+    async fn greet<T: ReliableOrderedStreamToTarget>(relative_node_type: RelativeNodeType, t: &T) -> Result<(), anyhow::Error> {
         match relative_node_type {
             RelativeNodeType::Receiver => {
                 t.send_serialized(MultiplexedPacket::<K>::Greeter).await?;
@@ -84,43 +350,458 @@ impl<K: MultiplexedConnKey + 'static> MultiplexedConn<K> {
             }
         }
 
-        let this = Self::new(relative_node_type, t);
-        let conn_task = this.clone();
+        Ok(())
+    }
+
+    fn local_capabilities(&self) -> Capabilities {
+        Capabilities { max_recv_frame: self.config().max_recv_frame.map(|max| max as u64), keepalive: self.config().keepalive_policy(), ..Capabilities::local() }
+    }
 
-        tokio::task::spawn(async move {
-            while let Ok(ref packet) = conn_task.conn.recv().await {
-                if let Err(err) = conn_task.forward_packet(packet).await {
-                    log::warn!("Unable to forward packet: {:?}", err);
+    /// Decodes transport frames and routes them, those of higher-priority streams first. Returns the outcome the level ends
+    /// with if a frame ends it, which is Ok only for the adjacent level's goodbye
+    async fn route_packets(&self, packets: &[Bytes]) -> Option<DemuxOutcome> {
+        let received = Instant::now();
+        let mut frames = Vec::new();
+        let mut ended = None;
+        for packet in packets {
+            match self.decode_packet(packet) {
+                Ok(decoded) => frames.extend(decoded),
+                Err(err) if err.is::<DecodeError>() && self.config().decode_error_policy() == DecodeErrorPolicy::Abort => {
+                    log::error!("Unable to decode packet. Aborting: {:?}", err);
+                    // the frames decoded ahead of it are still routed
+                    ended = Some(Err((std::io::ErrorKind::InvalidData, err.to_string())));
+                    break
                 }
+                Err(err) => log::warn!("Unable to forward packet: {:?}", err)
             }
-        });
+        }
 
-        Ok(this)
+        // priorities are looked up before any frame is routed, and the sort is stable, so each stream stays in order
+        let mut frames = frames.into_iter().map(|frame| (self.inbound_priority(&frame), frame)).collect::<Vec<_>>();
+        frames.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+        for (_, frame) in frames {
+            if let MultiplexedPacket::Goodbye = frame {
+                log::info!("Adjacent level closed");
+                return Some(Ok(()))
+            }
+
+            if let Err(err) = self.forward_routed(frame).await {
+                log::warn!("Unable to forward packet: {:?}", err);
+            }
+
+            self.record_demux_lag(received.elapsed());
+        }
+
+        ended
+    }
+
+    /// The priority an inbound frame is routed with: that of the local stream it concerns, if opened
+    fn inbound_priority(&self, packet: &MultiplexedPacket<K>) -> Priority {
+        let stream_priority = |id: &K| {
+            let priority_in = |subscribers: &SubscriberMap<K>| subscribers.shard(id).read().get(id).map(|sender| sender.priority).unwrap_or_default();
+            match self.partitions.get(id) {
+                Some(partition) => priority_in(partition.subscriptions()),
+                None => priority_in(self.subscriptions())
+            }
+        };
+
+        match packet {
+            // both are the last packet sent on a transport, so each waits for everything read before it
+            MultiplexedPacket::TransportSwap | MultiplexedPacket::Goodbye => Priority::Low,
+            MultiplexedPacket::CloseNotice { id } | MultiplexedPacket::PreCreateRejected { id, .. } => stream_priority(id),
+            // frames that concern no stream keep their place among those of streams with the default priority
+            packet => packet.stream_id().map(stream_priority).unwrap_or_default()
+        }
+    }
+
+    /// Tells the adjacent node's level that this level is closing, if it advertised support for the goodbye, then shuts down
+    /// the transport. Its substream, if any, closes once the demultiplexer drops it
+    async fn say_goodbye(transport: &Arc<dyn ReliableOrderedStreamToTarget>, peer_capabilities: &watch::Receiver<Option<Capabilities>>) -> DemuxOutcome {
+        if crate::negotiation::supports(peer_capabilities, "goodbye") {
+            if let Err(err) = transport.send_serialized(MultiplexedPacket::<K>::Goodbye).await {
+                log::warn!("Unable to say goodbye to the adjacent level: {:?}", err);
+            }
+        }
+
+        if let Err(err) = transport.shutdown().await {
+            log::warn!("Unable to shut down the transport: {:?}", err);
+        }
+
+        Ok(())
     }
 
     pub async fn forward_packet(&self, packet: &[u8]) -> Result<(), anyhow::Error> {
-        let deserialized = bincode2::deserialize::<MultiplexedPacket<K>>(packet)?;
-        match deserialized {
+        for frame in self.decode_packet(packet)? {
+            self.forward_routed(frame).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Deserializes a transport frame, unpacking the frames of a batch
+    fn decode_packet(&self, packet: &[u8]) -> Result<Vec<MultiplexedPacket<K>>, anyhow::Error> {
+        let check_frame_len = |len: usize| match self.config().max_recv_frame {
+            Some(max) if len > max => Err(anyhow::Error::msg(format!("Discarding frame of {} bytes, which exceeds the limit of {} bytes", len, max))),
+            _ => Ok(())
+        };
+
+        // checked before decoding, so that an oversized frame is never allocated for. The adjacent node keeps its batches
+        // within the limit too, and each frame in a batch is checked again as it is unpacked
+        check_frame_len(packet.len())?;
+
+        match MultiplexedPacket::<K>::decode(packet)? {
+            MultiplexedPacket::Batch { frames } => frames.iter().map(|frame| {
+                check_frame_len(frame.len())?;
+                match MultiplexedPacket::<K>::decode(frame)? {
+                    MultiplexedPacket::Batch { .. } => Err(anyhow::Error::msg("Nested packet batch")),
+                    packet => Ok(packet)
+                }
+            }).collect(),
+
+            deserialized => Ok(vec![deserialized])
+        }
+    }
+
+    /// Hands packets scoped to a partitioned id to the partition responsible for it (see [`MultiplexedConn::partition`])
+    async fn forward_routed(&self, packet: MultiplexedPacket<K>) -> Result<(), anyhow::Error> {
+        match packet.stream_id().and_then(|id| self.partitions.get(id)) {
+            Some(partition) => partition.forward_deserialized(packet).await,
+            None => self.forward_deserialized(packet).await
+        }
+    }
+
+    async fn deliver_application(&self, id: K, payload: Vec<u8>) -> Result<(), anyhow::Error> {
+        if K::is_reserved(&id) {
+            return Err(anyhow::Error::msg(format!("Discarding application data for {:?}, which is reserved for control use", id)))
+        }
+
+        // a payload handed to a handler, or buffered ahead of the open, is never queued, so nothing is evicted to make room for it
+        let (queued, full) = self.subscriptions().shard(&id).read().get(&id).map(|channel_tx| (channel_tx.queues(), channel_tx.is_full(self.config().max_buffered_messages()))).unwrap_or((false, false));
+
+        // a full queue fails the stream on both nodes, rather than silently skipping the payload
+        if full {
+            self.evict(id).await;
+            return Err(anyhow::Error::msg(format!("Inbound queue for {:?} is full. Evicted it and discarded the payload", id)))
+        }
+
+        // evicting takes the shard locks, so this happens before the payload is delivered under one
+        if queued {
+            self.make_room(id, payload.len()).await?;
+        }
+
+        let lock = self.subscriptions().shard(&id).read();
+        match lock.get(&id) {
+            Some(channel_tx) => {
+                channel_tx.deliver(payload)?;
+                self.on_delivered(id);
+                if let Some(fill_ratio) = self.config().backpressure().and_then(|config| channel_tx.crossed_high_water(config)) {
+                    self.emit_event(StreamEvent::Backpressure { id, fill_ratio })
+                }
+
+                Ok(())
+            }
+
+            // the shard lock is held across buffering so that the local open cannot slip in between
+            None => self.buffer_early_frame(id, payload)
+        }
+    }
+
+    async fn on_pre_create(&self, id: K) -> Result<(), anyhow::Error> {
+        if self.node_type().is_initiator() {
+            // a replayed signal for a stream already opened here means that the echo was lost with the old transport
+            if self.is_open(id) {
+                return self.send_pre_open_signal(id).await
+            }
+
+            // a replayed signal for a stream rejected here means that the rejection was lost with the old transport
+            let rejected = self.handshakes.rejected_opens.lock().get(&id).cloned();
+            if let Some(reason) = rejected {
+                return Ok(self.conn.send_serialized(MultiplexedPacket::PreCreateRejected { id, reason }).await?)
+            }
+
+            // a replayed signal for a stream that is still queued here needs no action
+            if !self.handshakes.queued_opens.lock().insert(id) {
+                return Ok(())
+            }
+
+            // a reopened pooled stream must be routable before any data the Receiver sends right behind this signal. It
+            // was admitted when first opened
+            if !self.reclaim_pooled(id) && !self.opens.admit_signal() {
+                // an adjacent node that cannot be told of the rejection is not answered at all, so its open never completes
+                if !self.peer_supports("open-reject") {
+                    log::warn!("Dropping the open signal for {:?}, which exceeds the open rate", id);
+                    self.handshakes.queued_opens.lock().remove(&id);
+                    return Ok(())
+                }
+
+                let reason = "Open rate limit exceeded".to_string();
+                // queued like any other open, so that the Initiator's own open of `id` fails in turn
+                self.handshakes.rejected_opens.lock().insert(id, reason.clone());
+                self.pre_action_container().tx.send(id).await?;
+                return Ok(self.conn.send_serialized(MultiplexedPacket::PreCreateRejected { id, reason }).await?)
+            }
+        } else if !self.handshakes.unechoed_opens.lock().remove(&id) {
+            log::warn!("Discarding duplicate open echo for {:?}", id);
+            return Ok(())
+        } else if self.pre_action_container().take_abandoned(id) {
+            log::trace!("Discarding the open echo for {:?}, whose open was cancelled", id);
+            return Ok(())
+        }
+
+        Ok(self.pre_action_container().tx.send(id).await?)
+    }
+
+    async fn forward_deserialized(&self, packet: MultiplexedPacket<K>) -> Result<(), anyhow::Error> {
+        match packet {
             MultiplexedPacket::ApplicationLayer { id, payload } => {
-                let lock = self.subscriptions().read();
-                let channel_tx = lock.get(&id).ok_or_else(|| anyhow::Error::msg("Channel ID does not exist"))?;
-                Ok(channel_tx.send(payload)?)
+                self.deliver_application(id, payload).await
+            }
+
+            MultiplexedPacket::Fragment { id, message, last, chunk } => {
+                match self.reassembly.push(id, message, last, chunk)? {
+                    Some(payload) => self.deliver_application(id, payload).await,
+                    None => Ok(())
+                }
+            }
+
+            MultiplexedPacket::Reset { id } => {
+                self.on_reset(id);
+                Ok(())
             }
 
             MultiplexedPacket::PreCreate{ id } => {
+                self.on_pre_create(id).await
+            }
+
+            MultiplexedPacket::PreCreateTraced { id, trace_context } => {
+                #[cfg(feature = "otel")]
+                self.traces.on_remote_context(id, trace_context);
+                #[cfg(not(feature = "otel"))]
+                let _ = trace_context;
+                self.on_pre_create(id).await
+            }
+
+            MultiplexedPacket::PreCreateRejected { id, reason } => {
+                if !self.handshakes.unechoed_opens.lock().remove(&id) {
+                    log::warn!("Discarding duplicate open rejection for {:?}", id);
+                    return Ok(())
+                }
+
+                if self.pre_action_container().take_abandoned(id) {
+                    return Ok(())
+                }
+
+                self.handshakes.rejected_opens.lock().insert(id, reason);
                 Ok(self.pre_action_container().tx.send(id).await?)
             }
 
+            MultiplexedPacket::PoolEvict { id } => {
+                let _ = self.pool.reclaim(id);
+                Ok(())
+            }
+
             MultiplexedPacket::PostDrop { id } => {
-                self.post_close_container().send(id).await
+                if self.node_type().is_initiator() {
+                    self.on_peer_closed(id);
+                    let res = self.post_close_container().send(id);
+                    // a replayed signal for a stream already closed here means that the answer was lost with the old transport
+                    if res.is_err() && !self.subscriptions().shard(&id).read().contains_key(&id) {
+                        return Ok(self.conn.send_serialized(MultiplexedPacket::PostDrop { id }).await?)
+                    }
+
+                    res
+                } else if self.handshakes.unanswered_closes.lock().remove(&id) {
+                    self.post_close_container().send(id)
+                } else {
+                    log::warn!("Discarding duplicate close answer for {:?}", id);
+                    Ok(())
+                }
+            }
+
+            MultiplexedPacket::StreamProbe { id, nonce } => {
+                let routable = self.is_routable(id);
+                Ok(self.conn.send_serialized(MultiplexedPacket::StreamProbeAck { id, nonce, routable }).await?)
+            }
+
+            MultiplexedPacket::StreamProbeAck { nonce, routable, .. } => {
+                // the probe may have timed out or been cancelled in the meantime
+                match self.pending_probes.lock().remove(&nonce) {
+                    Some(tx) => {
+                        let _ = tx.send(routable);
+                    }
+
+                    None => log::trace!("Discarding the ack of abandoned probe {}", nonce)
+                }
+
+                Ok(())
+            }
+
+            MultiplexedPacket::OpenRequest { nonce, label } => {
+                self.route_inbound(nonce, label)
+            }
+
+            MultiplexedPacket::OpenAccepted { nonce } => {
+                // the open signal that follows was already admitted by the adjacent node
+                if self.node_type().is_initiator() {
+                    self.opens.expect_signal();
+                }
+
+                self.opens.on_response(nonce, Ok(()))
+            }
+
+            MultiplexedPacket::Rejected { nonce, reason } => {
+                self.opens.on_response(nonce, Err(reason))
+            }
+
+            MultiplexedPacket::Hello { capabilities } => {
+                self.on_hello(capabilities);
+                Ok(())
+            }
+
+            // the demultiplexer's idle timer was already reset by its arrival
+            MultiplexedPacket::Keepalive => Ok(()),
+
+            MultiplexedPacket::LastWill { name, payload } => {
+                self.on_last_will(name, payload);
+                Ok(())
+            }
+
+            MultiplexedPacket::CloseNotice { id } => {
+                self.on_peer_closed(id);
+                Ok(())
+            }
+
+            MultiplexedPacket::TransportSwap => {
+                // nothing further arrives on the old transport. Stalls until the local side supplies its end of the new one
+                self.transport.switch_inbound().await;
+                Ok(())
             }
 
+            // stops the demultiplexer, which handles it before routing
+            MultiplexedPacket::Goodbye => Ok(()),
+
             _ => {
                 Err(anyhow::Error::msg("Unexpected packet type"))
             }
         }
     }
 
+    /// Verifies that the stream `id` is routable end-to-end by sending a control-level echo scoped to it, returning the
+    /// round-trip time. Unlike a connection-wide check, this fails if either side's subscriber for `id` is gone or wedged.
+    /// Fails with `TimedOut` if the adjacent node does not answer within the configured timeout (see
+    /// [`MultiplexConfig::with_probe_timeout`])
+    pub async fn probe_stream(&self, id: K) -> std::io::Result<Duration> {
+        if !self.is_routable(id) {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("Stream {:?} is not open locally", id)))
+        }
+
+        let nonce = self.probe_nonce.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.pending_probes.lock().insert(nonce, tx);
+        // removes the entry however the probe ends, including when the caller drops it
+        let _pending = PendingProbe { probes: &self.pending_probes, nonce };
+
+        let start = Instant::now();
+        let timeout = self.config().probe_timeout();
+        let answer = tokio::time::timeout(timeout, async {
+            self.conn.send_serialized(MultiplexedPacket::StreamProbe { id, nonce }).await?;
+            Ok::<_, std::io::Error>(rx.await)
+        }).await.map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, format!("Probe of {:?} went unanswered for {:?}", id, timeout)))??;
+
+        match answer {
+            Ok(true) => {
+                let rtt = start.elapsed();
+                self.peer.record_rtt(rtt);
+                Ok(rtt)
+            }
+
+            Ok(false) => Err(std::io::Error::new(std::io::ErrorKind::NotConnected, format!("Stream {:?} is not routable on the adjacent node", id))),
+            Err(_) => Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "Probe dropped"))
+        }
+    }
+
+    /// Migrates this connection onto `new_conn` without disturbing any open streams or the id generator. Both nodes must
+    /// call this with their respective ends of the new transport; inbound processing stalls from the moment the peer
+    /// switches until the local node does.
+    ///
+    /// Sends already in progress on the old transport finish first, then a swap marker is sent as the final frame on the
+    /// old transport. Every frame after it goes over `new_conn`, so ordering is preserved across the migration
+    pub async fn replace_transport<T: ReliableOrderedStreamToTarget + 'static>(&self, new_conn: T) -> std::io::Result<()> {
+        let new_conn: Arc<dyn ReliableOrderedStreamToTarget> = Arc::new(new_conn);
+        self.transport.stage_inbound(new_conn.clone())?;
+
+        let mut outbound = self.transport.outbound().await;
+        outbound.send_serialized(MultiplexedPacket::<K>::TransportSwap).await?;
+        *outbound = new_conn;
+        Ok(())
+    }
+
+    /// Resumes this connection on `new_conn` after its transport was lost, as opposed to [`Self::replace_transport`], which
+    /// migrates off a transport that still works. Both nodes must call this with their respective ends of the new transport,
+    /// and must have been configured with [`MultiplexConfig::with_transport_restore_grace`] so that their demultiplexers
+    /// wait for it.
+    ///
+    /// Opens and closes that were mid-handshake resume: the Receiver replays every handshake signal still awaiting a
+    /// reply (see [`Self::pending_handshakes`]), and the Initiator answers any it had already answered. Application frames
+    /// in flight on the lost transport are not recovered (see [`ExactlyOnceStream`]). A replay that fails is retried after
+    /// the configured backoff (see [`MultiplexConfig::with_backoff`])
+    pub async fn restore_transport<T: ReliableOrderedStreamToTarget + 'static>(&self, new_conn: T) -> std::io::Result<()> {
+        self.transport.restore(Arc::new(new_conn)).await;
+        self.replay_handshakes().await
+    }
+
+    /// Sends `payload` to each of `ids` that is currently open locally, skipping the rest, and returns the number of
+    /// streams it was sent on. The payload is serialized once and the frame buffer is reused across the sends
+    pub async fn multicast(&self, ids: &[K], payload: &[u8]) -> std::io::Result<usize> {
+        let mut encoder = SharedPayloadEncoder::new(payload)?;
+        let mut sent = 0;
+
+        for id in ids {
+            if !self.is_routable(*id) {
+                continue
+            }
+
+            self.conn.send_to_peer(encoder.frame_for(*id)?).await?;
+            sent += 1;
+        }
+
+        Ok(sent)
+    }
+
+    /// Opens a new substream whose payloads are encoded with `codec` rather than the connection-wide default.
+    /// As with `initiate_subscription`, the stream id is agreed upon with the adjacent node
+    pub async fn subscribe_with_codec<C: PayloadCodec>(&self, codec: C) -> Result<CodecSubscription<OwnedMultiplexedSubscription<K>, C>, anyhow::Error> {
+        let subscription = self.initiate_subscription().await?;
+        Ok(CodecSubscription::new(subscription, codec))
+    }
+
+    /// Opens `count` substreams concurrently, returned in the order their ids were agreed upon. The adjacent node must
+    /// open the same number of substreams. Bursts are paced by [`MultiplexConfig::with_max_concurrent_opens`]
+    pub async fn initiate_many(&self, count: usize) -> Result<Vec<OwnedMultiplexedSubscription<K>>, anyhow::Error> {
+        // the pre-action lock is fair and each open is first polled in order, so ids are agreed upon in call order
+        futures::future::try_join_all((0..count).map(|_| self.initiate_subscription())).await
+    }
+
+    /// The number of opens currently in progress on this node (see [`MultiplexConfig::with_max_concurrent_opens`])
+    pub fn pending_opens(&self) -> usize {
+        self.pre_action_container().pending_opens()
+    }
+
+    /// Opens a new substream with exactly-once delivery across reconnects (see [`ExactlyOnceStream`]). The adjacent node
+    /// must open its end in the same way
+    pub async fn subscribe_exactly_once(&self) -> Result<ExactlyOnceStream<OwnedMultiplexedSubscription<K>>, anyhow::Error> {
+        let subscription = self.initiate_subscription().await?;
+        Ok(ExactlyOnceStream::new(subscription))
+    }
+
+    /// Causes every outstanding `initiate_subscription` future to resolve with an `Interrupted` [`std::io::Error`]. Useful
+    /// during teardown when the peer is known to be gone but opens are still awaiting an ack. Should the adjacent node
+    /// answer a cancelled open after all, the answer is discarded rather than taken by a later open
+    pub fn cancel_pending_opens(&self) {
+        self.pre_action_container().cancel_all()
+    }
+
     /// Both nodes execute a function, returning once one of the functions gets evaluated
     pub fn net_select<'a, F: Send + 'a, R: Send + 'a>(&'a self, future: F) -> NetSelect<'a, R>
         where
@@ -186,6 +867,54 @@ impl<K: MultiplexedConnKey + 'static> MultiplexedConn<K> {
     pub fn bi_channel<R: NetObject>(&self) -> bi_channel::ChannelLoader<R, Self> {
         bi_channel::Channel::new(self)
     }
+
+    /// Opens a stream and splits it into a sender of `Out`s and a receiver of `In`s, returning once both nodes have opened
+    /// it. The adjacent node opens its end the same way, with the two types swapped
+    pub async fn typed_channel<Out: NetObject, In: NetObject>(&self) -> Result<(TypedSender<Out, Self>, TypedReceiver<In, Self>), anyhow::Error> {
+        let chan = self.initiate_subscription().await?;
+        Ok(typed_channel::typed_pair(chan))
+    }
+}
+
+/// Reads the packets that have already arrived on `transport` into `packets` without waiting, until `MAX_READ_AHEAD` are
+/// held or a swap marker is. Returns the outcome the transport ended with, if it ended while reading
+fn read_ahead<K: MultiplexedConnKey, T: ReliableOrderedStreamToTarget + ?Sized>(transport: &T, packets: &mut Vec<Bytes>) -> Option<DemuxOutcome> {
+    while packets.len() < MAX_READ_AHEAD {
+        // whatever follows the marker arrives on the new transport, which is read from only once the marker is routed
+        if packets.last().is_some_and(|packet| MultiplexedPacket::<K>::is_transport_swap(packet)) {
+            break
+        }
+
+        match transport.recv().now_or_never()? {
+            Ok(packet) if packet.is_empty() => return Some(Ok(())),
+            Ok(packet) => packets.push(packet),
+            Err(err) => return Some(Err((err.kind(), err.to_string())))
+        }
+    }
+
+    None
+}
+
+/// Resolves once the timeout of the keepalive in force has passed since `idle_since`, returning the timeout. Never
+/// resolves while no keepalive is in force
+async fn idle_timeout(idle_since: Instant, keepalive: &mut tokio::sync::watch::Receiver<Option<KeepalivePolicy>>) -> Duration {
+    loop {
+        let policy = *keepalive.borrow();
+        let changed = async {
+            // the policy can no longer change
+            if keepalive.changed().await.is_err() {
+                futures::future::pending::<()>().await
+            }
+        };
+
+        match policy {
+            Some(policy) => tokio::select! {
+                _ = tokio::time::sleep_until(idle_since + policy.timeout) => return policy.timeout,
+                _ = changed => {}
+            },
+            None => changed.await
+        }
+    }
 }
 
 /// Ensures that the symmetric conversation ID exists between both endpoints when starting
@@ -208,6 +937,74 @@ impl<'a, S: Subscribable<UnderlyingConn=T> + 'a, T: ReliableOrderedStreamToTarge
 }
 
 async fn preaction_sync<'a, S: Subscribable<UnderlyingConn=T, ID = K> + 'a, T: ReliableOrderedStreamToTarget + 'static, K: MultiplexedConnKey>(ptr: &'a S) -> Result<<S as Subscribable>::BorrowedSubscriptionType, anyhow::Error> {
+    // register for cancellation before waiting on anything so that a concurrent cancel is never missed
+    let cancelled = ptr.pre_action_container().cancel.notified();
+
+    let admitted = async {
+        let container = ptr.pre_action_container();
+        let _permit = match container.permits.as_ref() {
+            Some(permits) => Some(permits.acquire().await?),
+            None => None
+        };
+
+        let _pending = PendingOpen::new(&container.pending);
+        match ptr.deadline() {
+            Some(deadline) => tokio::time::timeout_at(deadline, preaction_sync_inner(ptr)).await
+                .unwrap_or_else(|_| Err(anyhow::Error::msg("The open did not complete by the connection's deadline"))),
+            None => preaction_sync_inner(ptr).await
+        }
+    };
+
+    tokio::select! {
+        res = admitted => res,
+        _ = cancelled => Err(anyhow::Error::new(std::io::Error::new(std::io::ErrorKind::Interrupted, "Pending open cancelled")))
+    }
+}
+
+/// Counts an open as pending for as long as it lives
+struct PendingOpen<'a>(&'a AtomicUsize);
+
+impl<'a> PendingOpen<'a> {
+    fn new(pending: &'a AtomicUsize) -> Self {
+        pending.fetch_add(1, Ordering::Relaxed);
+        Self(pending)
+    }
+}
+
+impl Drop for PendingOpen<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Receiver: an open whose signal went out. If it ends before the Initiator's echo is taken, the echo is abandoned
+struct AwaitingEcho<'a, K: MultiplexedConnKey> {
+    abandoned: &'a parking_lot::Mutex<HashSet<K>>,
+    id: K,
+    echoed: bool
+}
+
+impl<K: MultiplexedConnKey> Drop for AwaitingEcho<'_, K> {
+    fn drop(&mut self) {
+        if !self.echoed {
+            let _ = self.abandoned.lock().insert(self.id);
+        }
+    }
+}
+
+/// Keeps a probe's entry in the pending probes for as long as it lives
+struct PendingProbe<'a> {
+    probes: &'a parking_lot::Mutex<HashMap<u64, tokio::sync::oneshot::Sender<bool>>>,
+    nonce: u64
+}
+
+impl Drop for PendingProbe<'_> {
+    fn drop(&mut self) {
+        let _ = self.probes.lock().remove(&self.nonce);
+    }
+}
+
+async fn preaction_sync_inner<'a, S: Subscribable<UnderlyingConn=T, ID = K> + 'a, T: ReliableOrderedStreamToTarget + 'static, K: MultiplexedConnKey>(ptr: &'a S) -> Result<<S as Subscribable>::BorrowedSubscriptionType, anyhow::Error> {
     let mut recv_lock = ptr.pre_action_container().rx.lock().await;
 
     if let Some(subscription) = ptr.get_next_prereserved() {
@@ -216,26 +1013,58 @@ async fn preaction_sync<'a, S: Subscribable<UnderlyingConn=T, ID = K> + 'a, T: R
 
     match ptr.node_type() {
         RelativeNodeType::Receiver => {
+            if let Some(subscription) = ptr.reopen_pooled().await? {
+                return Ok(subscription)
+            }
+
             // generate the subscription to ensure local can begin receiving packet
             let next_id = ptr.get_next_id();
-            let subscription = ptr.subscribe(next_id);
-            ptr.post_close_container().setup_channel(next_id).await;
+            let subscription = ptr.subscribe(next_id)?;
+            ptr.post_close_container().setup_channel(next_id);
 
             ptr.send_pre_open_signal(next_id).await?;
-            let recvd_id = recv_lock.recv().await.ok_or_else(|| anyhow::Error::msg("rx dead"))?;
+            let mut awaiting = AwaitingEcho { abandoned: &ptr.pre_action_container().abandoned, id: next_id, echoed: false };
+            let recvd_id = loop {
+                let recvd_id = recv_lock.recv().await.ok_or_else(|| anyhow::Error::msg("rx dead"))?;
+                // the answer to an earlier open that was cancelled while awaiting it
+                if recvd_id != next_id && ptr.pre_action_container().take_abandoned(recvd_id) {
+                    let _ = ptr.take_rejected_open(recvd_id);
+                    continue
+                }
+
+                break recvd_id
+            };
+
+            awaiting.echoed = true;
+            if let Some(reason) = ptr.take_rejected_open(recvd_id) {
+                // dropping the subscription closes it, which the Initiator answers as it would a replayed close
+                return Err(anyhow::Error::msg(format!("Stream open rejected by the adjacent node: {}", reason)))
+            }
 
             if recvd_id != next_id {
                 log::error!("Invalid sync ID received. {:?} != {:?}", recvd_id, next_id);
             }
 
+            ptr.on_open_complete(next_id);
             Ok(subscription)
         }
 
         RelativeNodeType::Initiator => {
             let next_id = recv_lock.recv().await.ok_or_else(|| anyhow::Error::msg("rx dead"))?;
-            let subscription = ptr.subscribe(next_id);
-            ptr.post_close_container().setup_channel(next_id).await;
+            if let Some(reason) = ptr.take_rejected_open(next_id) {
+                return Err(anyhow::Error::msg(format!("Stream open rejected: {}", reason)))
+            }
+
+            if let Some(subscription) = ptr.take_reopened(next_id) {
+                ptr.post_close_container().setup_channel(next_id);
+                // the Receiver does not await an echo for a pooled reopen
+                return Ok(subscription)
+            }
+
+            let subscription = ptr.subscribe(next_id)?;
+            ptr.post_close_container().setup_channel(next_id);
             ptr.send_pre_open_signal(next_id).await?;
+            ptr.on_open_complete(next_id);
             // we can safely return, knowing the adjacent node will still have the conv open to receive messages
             Ok(subscription)
         }
@@ -262,6 +1091,7 @@ impl<'a> Future for PostActionSync<'a> {
 
 async fn postaction_sync<'a, S: Subscribable<ID=K> + 'a, K: MultiplexedConnKey>(subscribable: &'a S, close_id: K) -> Result<(), anyhow::Error> {
     log::info!("[Postaction] on {:?}", subscribable.node_type());
+    subscribable.on_close_begin(close_id).await;
     match subscribable.node_type() {
         RelativeNodeType::Receiver => {
             subscribable.send_post_close_signal(close_id).await?;
@@ -276,4 +1106,707 @@ async fn postaction_sync<'a, S: Subscribable<ID=K> + 'a, K: MultiplexedConnKey>(
             Ok(())
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::test_utils::{create_streams, create_streams_with_config, create_channel_streams_with_config, create_framed_pair, channel_pair, SeverableConn, open_pair, drain_prereserved};
+    use crate::sync::subscription::{Subscribable, SubscriptionBiStream};
+    use crate::multiplex::{OwnedMultiplexedSubscription, MultiplexedPacket, StreamEvent};
+    use crate::config::{MultiplexConfig, EarlyDataPolicy, BackpressureConfig, KeepalivePolicy, BackoffConfig};
+    use crate::reliable_conn::ReliableOrderedStreamToTargetExt;
+    use crate::sync::network_application::INITIAL_CAPACITY;
+    use crate::negotiation::Capabilities;
+    use crate::sync::SymmetricConvID;
+    use crate::codec::{PayloadCodec, BincodeCodec, SchemaVersioner};
+    use crate::reliable_conn::ReliableOrderedStreamToTarget;
+    use serde::Serialize;
+    use serde::de::DeserializeOwned;
+    use std::time::Duration;
+    use std::sync::Arc;
+    use crate::sync::network_application::NetworkApplication;
+    use crate::sync::RelativeNodeType;
+    use bytes::Bytes;
+    use futures::FutureExt;
+
+    #[tokio::test(start_paused = true)]
+    async fn cancel_pending_opens() {
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default()).await;
+
+        // drain the pre-reserved streams so that the next open requires the peer's participation
+        let mut held = drain_prereserved(&server);
+
+        let server_stuck = server.clone();
+        let stuck = tokio::spawn(async move {
+            server_stuck.initiate_subscription().await.map(|_: OwnedMultiplexedSubscription| ())
+        });
+
+        // the client never participates, so the open can only end via cancellation once its signal is out
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        let stale_id = server.pending_handshakes().opens[0];
+        server.cancel_pending_opens();
+
+        let err = stuck.await.unwrap().unwrap_err();
+        assert_eq!(err.downcast_ref::<std::io::Error>().unwrap().kind(), std::io::ErrorKind::Interrupted);
+        assert!(server.pre_action_container().abandoned.lock().contains(&stale_id));
+
+        // the client answers the cancelled open once it opens a stream of its own past the pre-reserved ones
+        held.extend(drain_prereserved(&client));
+
+        let stale: OwnedMultiplexedSubscription = client.initiate_subscription().await.unwrap();
+        assert_eq!(stale.id(), stale_id);
+        held.push(stale);
+
+        // which the server discards on arrival rather than queueing it for its next open to take
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert!(server.pending_handshakes().opens.is_empty());
+        assert!(!server.pre_action_container().abandoned.lock().contains(&stale_id));
+        assert!(server.pre_action_container().rx.try_lock().unwrap().try_recv().is_err());
+
+        let (server_sub, client_sub) = tokio::join!(server.initiate_subscription(), client.initiate_subscription());
+        let (server_sub, client_sub): (OwnedMultiplexedSubscription, OwnedMultiplexedSubscription) = (server_sub.unwrap(), client_sub.unwrap());
+        assert_eq!(server_sub.id(), client_sub.id());
+        assert_ne!(server_sub.id(), stale_id);
+        assert!(!server.pre_action_container().take_abandoned(server_sub.id()));
+    }
+
+    /// Tags every payload with a leading marker byte so it is distinguishable from plain bincode on the wire
+    struct TaggedCodec;
+
+    impl PayloadCodec for TaggedCodec {
+        fn encode<T: Serialize>(&self, t: &T) -> std::io::Result<Vec<u8>> {
+            let mut packet = vec![0xAB];
+            packet.extend(BincodeCodec.encode(t)?);
+            Ok(packet)
+        }
+
+        fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> std::io::Result<T> {
+            match bytes.split_first() {
+                Some((0xAB, rest)) => BincodeCodec.decode(rest),
+                _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Missing tag"))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn per_stream_codec() {
+        let (server, client) = create_streams().await;
+
+        let (server_tagged, client_tagged) = tokio::join!(server.subscribe_with_codec(TaggedCodec), client.subscribe_with_codec(TaggedCodec));
+        let (server_plain, client_plain) = tokio::join!(server.subscribe_with_codec(BincodeCodec), client.subscribe_with_codec(BincodeCodec));
+        let (server_tagged, client_tagged, server_plain, client_plain) = (server_tagged.unwrap(), client_tagged.unwrap(), server_plain.unwrap(), client_plain.unwrap());
+
+        server_tagged.send_serialized(&String::from("tagged")).await.unwrap();
+        server_plain.send_serialized(&String::from("plain")).await.unwrap();
+        assert_eq!(client_tagged.recv_serialized::<String>().await.unwrap(), "tagged");
+        assert_eq!(client_plain.recv_serialized::<String>().await.unwrap(), "plain");
+
+        // the raw bytes on the tagged stream carry the tag, while the plain stream is untouched
+        server_tagged.send_serialized(&1u8).await.unwrap();
+        server_plain.send_serialized(&1u8).await.unwrap();
+        assert_eq!(client_tagged.recv().await.unwrap()[0], 0xAB);
+        assert_eq!(client_plain.recv().await.unwrap()[0], 1);
+    }
+
+    #[derive(serde::Deserialize, Serialize, Debug, PartialEq)]
+    struct ProfileV1 {
+        name: String
+    }
+
+    #[derive(serde::Deserialize, Serialize, Debug, PartialEq)]
+    struct ProfileV2 {
+        name: String,
+        age: Option<u8>
+    }
+
+    struct ProfileVersioner(u8);
+
+    impl SchemaVersioner<ProfileV1> for ProfileVersioner {
+        fn version(&self) -> u8 {
+            self.0
+        }
+
+        fn decode<C: PayloadCodec>(&self, _version: u8, bytes: &[u8], codec: &C) -> std::io::Result<ProfileV1> {
+            codec.decode(bytes)
+        }
+    }
+
+    impl SchemaVersioner<ProfileV2> for ProfileVersioner {
+        fn version(&self) -> u8 {
+            self.0
+        }
+
+        fn decode<C: PayloadCodec>(&self, version: u8, bytes: &[u8], codec: &C) -> std::io::Result<ProfileV2> {
+            match version {
+                1 => codec.decode::<ProfileV1>(bytes).map(|v1| ProfileV2 { name: v1.name, age: None }),
+                2 => codec.decode(bytes),
+                _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Unknown schema version"))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn schema_versioning() {
+        let (server, client) = create_streams().await;
+        let (server_sub, client_sub) = tokio::join!(server.subscribe_with_codec(BincodeCodec), client.subscribe_with_codec(BincodeCodec));
+        let (server_sub, client_sub) = (server_sub.unwrap(), client_sub.unwrap());
+
+        // an older build sends v1, while the upgraded build migrates it on arrival
+        server_sub.send_versioned(&ProfileVersioner(1), &ProfileV1 { name: "old".into() }).await.unwrap();
+        server_sub.send_versioned(&ProfileVersioner(2), &ProfileV2 { name: "new".into(), age: Some(7) }).await.unwrap();
+        assert_eq!(client_sub.recv_versioned::<ProfileV2, _>(&ProfileVersioner(2)).await.unwrap(), ProfileV2 { name: "old".into(), age: None });
+        assert_eq!(client_sub.recv_versioned::<ProfileV2, _>(&ProfileVersioner(2)).await.unwrap(), ProfileV2 { name: "new".into(), age: Some(7) });
+
+        server_sub.send_versioned(&ProfileVersioner(3), &ProfileV2 { name: "future".into(), age: None }).await.unwrap();
+        assert!(client_sub.recv_versioned::<ProfileV2, _>(&ProfileVersioner(2)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn max_buffered_messages() {
+        let (server, client) = create_streams_with_config(MultiplexConfig::new().with_max_buffered_messages(100)).await;
+        let _ = tokio::join!(server.peer_capabilities(), client.peer_capabilities());
+
+        // returns true if the receiving end was evicted once `count` payloads were routed to it
+        async fn flood(server: &NetworkApplication, client: &NetworkApplication, max: Option<usize>, count: usize) -> bool {
+            let mut events = server.events();
+            let (server_sub, client_sub) = open_pair(server, client).await;
+            if let Some(max) = max {
+                server_sub.set_max_buffered_messages(max);
+            }
+
+            for _ in 0..count {
+                client_sub.send_to_peer(&[1]).await.unwrap();
+            }
+
+            // answered only once every payload ahead of it has been routed
+            let _ = client.probe_stream(client_sub.id()).await;
+            match events.try_recv() {
+                Ok(StreamEvent::Evicted { id }) => {
+                    assert_eq!(id, server_sub.id());
+                    assert_eq!(server_sub.recv().await.unwrap_err().kind(), std::io::ErrorKind::ConnectionReset);
+                    true
+                }
+
+                _ => {
+                    for _ in 0..count {
+                        assert_eq!(&server_sub.recv().await.unwrap()[..], &[1]);
+                    }
+
+                    false
+                }
+            }
+        }
+
+        // single-byte payloads are capped by count alone
+        assert!(!flood(&server, &client, None, 100).await);
+        assert!(flood(&server, &client, None, 101).await);
+        assert!(!flood(&server, &client, Some(10), 10).await);
+        assert!(flood(&server, &client, Some(10), 11).await);
+        assert!(!flood(&server, &client, Some(usize::MAX), 1000).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn probe_stream() {
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default()).await;
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let id = server_sub.id();
+
+        server.probe_stream(id).await.unwrap();
+        client.probe_stream(id).await.unwrap();
+
+        // once the client side's close reaches the server, probes from the server must fail
+        std::mem::drop(client_sub);
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert!(server.probe_stream(id).await.is_err());
+        std::mem::drop(server_sub);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn probe_stream_timeout() {
+        let (server_conn, client_conn) = channel_pair();
+        let (sever, severed) = tokio::sync::watch::channel(false);
+        let (_keep, kept) = tokio::sync::watch::channel(false);
+        let config = MultiplexConfig::new().with_probe_timeout(Duration::from_millis(200));
+        let (server, client) = tokio::join!(
+            NetworkApplication::register_with_config(RelativeNodeType::Receiver, SeverableConn { inner: server_conn, severed }, config.clone()),
+            NetworkApplication::register_with_config(RelativeNodeType::Initiator, SeverableConn { inner: client_conn, severed: kept }, config)
+        );
+
+        let (server, client) = (server.unwrap(), client.unwrap());
+        let (server_sub, _client_sub) = open_pair(&server, &client).await;
+        let id = server_sub.id();
+
+        // a probe the caller abandons leaves nothing behind
+        sever.send(true).unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(50), server.probe_stream(id)).await.is_err());
+        assert!(server.pending_probes.lock().is_empty());
+
+        // nor does one that goes unanswered
+        assert_eq!(server.probe_stream(id).await.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        assert!(server.pending_probes.lock().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn early_data_buffered_until_open() {
+        let config = MultiplexConfig::new().with_early_data_policy(EarlyDataPolicy::Buffer { max_frames_per_id: 4, max_pending_ids: 4, timeout: Duration::from_secs(5) });
+        let (server, client) = create_channel_streams_with_config(config).await;
+        let mut held = drain_prereserved(&server);
+        held.extend(drain_prereserved(&client));
+
+        // the application frame for the next id reaches the server before either side has opened it
+        let next_id = SymmetricConvID::from(INITIAL_CAPACITY as u64 + 1);
+        client.conn.send_serialized(MultiplexedPacket::ApplicationLayer { id: next_id, payload: bincode2::serialize(&1u64).unwrap() }).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1)).await;
+
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        assert_eq!(server_sub.id(), next_id);
+
+        client_sub.send_serialized(2u64).await.unwrap();
+        assert_eq!(server_sub.recv_serialized::<u64>().await.unwrap(), 1);
+        assert_eq!(server_sub.recv_serialized::<u64>().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn backpressure_event() {
+        let config = MultiplexConfig::new().with_backpressure_events(BackpressureConfig::new(10, 0.5));
+        let (server, client) = create_streams_with_config(config).await;
+        let mut events = client.events();
+
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+
+        for idx in 0..8u64 {
+            server_sub.send_serialized(idx).await.unwrap();
+        }
+
+        assert_eq!(events.recv().await.unwrap(), StreamEvent::Backpressure { id: client_sub.id(), fill_ratio: 0.5 });
+
+        for idx in 0..8u64 {
+            assert_eq!(client_sub.recv_serialized::<u64>().await.unwrap(), idx);
+        }
+
+        // the queue stayed above the threshold for the remaining sends, so no further warnings were emitted
+        assert!(events.try_recv().is_err());
+
+        // once drained, the warning is re-armed
+        for idx in 0..5u64 {
+            server_sub.send_serialized(idx).await.unwrap();
+        }
+
+        assert_eq!(events.recv().await.unwrap(), StreamEvent::Backpressure { id: client_sub.id(), fill_ratio: 0.5 });
+    }
+
+    #[tokio::test]
+    async fn max_concurrent_opens() {
+        let (server, client) = create_streams_with_config(MultiplexConfig::new().with_max_concurrent_opens(50)).await;
+        let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        let sampler = {
+            let (server, client, done) = (server.clone(), client.clone(), done.clone());
+            tokio::spawn(async move {
+                let mut max_pending = 0;
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    max_pending = std::cmp::max(max_pending, std::cmp::max(server.pending_opens(), client.pending_opens()));
+                    tokio::task::yield_now().await;
+                }
+
+                max_pending
+            })
+        };
+
+        let (server_subs, client_subs) = tokio::join!(server.initiate_many(1000), client.initiate_many(1000));
+        done.store(true, std::sync::atomic::Ordering::Relaxed);
+        let (server_subs, client_subs) = (server_subs.unwrap(), client_subs.unwrap());
+
+        assert_eq!(server_subs.iter().map(|sub| sub.id()).collect::<Vec<_>>(), client_subs.iter().map(|sub| sub.id()).collect::<Vec<_>>());
+        let max_pending = sampler.await.unwrap();
+        assert!(max_pending > 0 && max_pending <= 50, "{} opens were pending at once", max_pending);
+        assert_eq!(server.pending_opens(), 0);
+    }
+
+    /// A transport whose sends each take `delay` once `slow` is set
+    struct SlowConn<T> {
+        inner: T,
+        slow: Arc<std::sync::atomic::AtomicBool>,
+        delay: Duration
+    }
+
+    #[async_trait::async_trait]
+    impl<T: ReliableOrderedStreamToTarget> ReliableOrderedStreamToTarget for SlowConn<T> {
+        async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+            if self.slow.load(std::sync::atomic::Ordering::SeqCst) {
+                tokio::time::sleep(self.delay).await;
+            }
+
+            self.inner.send_to_peer(input).await
+        }
+
+        async fn recv(&self) -> std::io::Result<Bytes> {
+            self.inner.recv().await
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn demux_lag() {
+        let threshold = Duration::from_millis(20);
+        let config = MultiplexConfig::new().with_demux_lag_threshold(threshold);
+        let (server_conn, client_conn) = channel_pair();
+        let slow = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let (server, client) = tokio::join!(
+            NetworkApplication::register_with_config(RelativeNodeType::Receiver, server_conn, config.clone()),
+            NetworkApplication::register_with_config(RelativeNodeType::Initiator, SlowConn { inner: client_conn, slow: slow.clone(), delay: Duration::from_millis(30) }, config)
+        );
+
+        let (server, client) = (server.unwrap(), client.unwrap());
+        let mut events = client.events();
+        let (server_sub, _client_sub) = open_pair(&server, &client).await;
+        assert!(client.demux_lag() < threshold);
+
+        // the client's demultiplexer answers the probe itself, so the slow send stalls it
+        slow.store(true, std::sync::atomic::Ordering::SeqCst);
+        server.probe_stream(server_sub.id()).await.unwrap();
+
+        match events.recv().await.unwrap() {
+            StreamEvent::DemuxLag { lag } => assert!(lag >= Duration::from_millis(30)),
+            event => panic!("Unexpected event {:?}", event)
+        }
+
+        assert!(client.demux_lag() >= Duration::from_millis(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn multicast() {
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default()).await;
+        let mut events = server.events();
+        let mut server_subs = Vec::new();
+        let mut client_subs = Vec::new();
+
+        for _ in 0..4 {
+            let (server_sub, client_sub) = open_pair(&server, &client).await;
+            server_subs.push(server_sub);
+            client_subs.push(client_sub);
+        }
+
+        // close the third stream on both sides
+        let closed_id = server_subs[2].id();
+        drop((server_subs.remove(2), client_subs.remove(2)));
+        match events.recv().await.unwrap() {
+            StreamEvent::Closed { summary } => assert_eq!(summary.id, closed_id),
+            event => panic!("Unexpected event {:?}", event)
+        }
+
+        let ids = [server_subs[0].id(), closed_id, server_subs[2].id()];
+        assert_eq!(server.multicast(&ids, b"group").await.unwrap(), 2);
+        // terminate each stream so that the absence of the multicast payload is observable
+        for server_sub in &server_subs {
+            server_sub.send_to_peer(b"end").await.unwrap();
+        }
+
+        assert_eq!(&client_subs[0].recv().await.unwrap()[..], b"group");
+        assert_eq!(&client_subs[0].recv().await.unwrap()[..], b"end");
+        assert_eq!(&client_subs[1].recv().await.unwrap()[..], b"end");
+        assert_eq!(&client_subs[2].recv().await.unwrap()[..], b"group");
+        assert_eq!(&client_subs[2].recv().await.unwrap()[..], b"end");
+    }
+
+    #[tokio::test]
+    async fn split_batched_frame() {
+        let (_server, client) = create_streams().await;
+        let client_sub = client.get_next_prereserved().unwrap();
+
+        let frames = (0..3u64).map(|idx| bincode2::serialize(&MultiplexedPacket::ApplicationLayer { id: client_sub.id(), payload: bincode2::serialize(&idx).unwrap() }).unwrap()).collect();
+        client.forward_packet(&bincode2::serialize(&MultiplexedPacket::<SymmetricConvID>::Batch { frames }).unwrap()).await.unwrap();
+
+        for idx in 0..3u64 {
+            assert_eq!(client_sub.recv_serialized::<u64>().await.unwrap(), idx);
+        }
+
+        // batches may not nest
+        let nested = bincode2::serialize(&MultiplexedPacket::<SymmetricConvID>::Batch { frames: vec![] }).unwrap();
+        assert!(client.forward_packet(&bincode2::serialize(&MultiplexedPacket::<SymmetricConvID>::Batch { frames: vec![nested] }).unwrap()).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bounded_reassembly() {
+        let config = MultiplexConfig::new().with_fragmentation(16).with_max_reassembly_bytes(64, 96).with_reassembly_timeout(Duration::from_millis(200));
+        let (server, client) = create_channel_streams_with_config(config).await;
+        let _ = tokio::join!(server.peer_capabilities(), client.peer_capabilities());
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        assert_eq!(server_sub.fragment_size(), Some(16));
+
+        let message = (0..50u8).collect::<Vec<u8>>();
+        server_sub.send_to_peer(&message).await.unwrap();
+        assert_eq!(&client_sub.recv().await.unwrap()[..], &message[..]);
+        assert_eq!(client.reassembly_bytes(), 0);
+
+        let fragment = |message: u64, last: bool, len: usize| bincode2::serialize(&MultiplexedPacket::Fragment { id: client_sub.id(), message, last, chunk: vec![1u8; len] }).unwrap();
+        for message in 0..2u64 {
+            client.forward_packet(&fragment(message, false, 32)).await.unwrap();
+        }
+
+        assert_eq!(client.reassembly_bytes(), 64);
+        // the stream's limit is reached, so the message is discarded along with its later fragments
+        assert!(client.forward_packet(&fragment(1, false, 1)).await.is_err());
+        assert_eq!(client.reassembly_bytes(), 32);
+        client.forward_packet(&fragment(1, true, 1)).await.unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(50), client_sub.recv()).await.is_err());
+
+        // swept by the timer within two timeouts, without any further fragments arriving
+        tokio::time::sleep(Duration::from_millis(450)).await;
+        assert_eq!(client.reassembly_bytes(), 0);
+
+        // a last fragment that would take its message past the stream's limit is discarded too, rather than joined
+        client.forward_packet(&fragment(2, false, 32)).await.unwrap();
+        assert!(client.forward_packet(&fragment(2, true, 33)).await.is_err());
+        assert_eq!(client.reassembly_bytes(), 0);
+        assert!(client_sub.recv().now_or_never().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stream_pool() {
+        let ttl = Duration::from_millis(500);
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default().with_stream_pool(4, ttl)).await;
+        // the pool is used only once each node has advertised support for it
+        let _ = tokio::join!(server.peer_capabilities(), client.peer_capabilities());
+
+        // the pool is only drawn upon once the pre-reserved streams are used up
+        let mut held = drain_prereserved(&server);
+        held.extend(drain_prereserved(&client));
+
+        // the paused clock only advances once the close handshakes have run as far as they can
+        let assert_pooled = |count: usize| {
+            let (server, client) = (server.clone(), client.clone());
+            async move {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                assert_eq!((server.debug_state().pooled_ids, client.debug_state().pooled_ids), (count, count));
+            }
+        };
+
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let pooled_id = server_sub.id();
+        drop((server_sub, client_sub));
+        assert_pooled(1).await;
+
+        // within the TTL, the Receiver reopens without waiting on the Initiator, and may send right away
+        let server_sub: OwnedMultiplexedSubscription = tokio::time::timeout(Duration::from_millis(100), server.initiate_subscription()).await.unwrap().unwrap();
+        assert_eq!(server_sub.id(), pooled_id);
+        server_sub.send_serialized(7u64).await.unwrap();
+
+        let client_sub: OwnedMultiplexedSubscription = client.initiate_subscription().await.unwrap();
+        assert_eq!(client_sub.id(), pooled_id);
+        assert_eq!(client_sub.recv_serialized::<u64>().await.unwrap(), 7);
+        assert_eq!(server.debug_state().pooled_ids, 0);
+
+        drop((server_sub, client_sub));
+        assert_pooled(1).await;
+        tokio::time::sleep(ttl).await;
+
+        // past the TTL, the slot is released and the open requires the Initiator's participation
+        let server_open = server.clone();
+        let mut server_open = tokio::spawn(async move { server_open.initiate_subscription().await.map(|sub: OwnedMultiplexedSubscription| sub.id()) });
+        assert!(tokio::time::timeout(Duration::from_millis(100), &mut server_open).await.is_err());
+
+        let client_sub: OwnedMultiplexedSubscription = client.initiate_subscription().await.unwrap();
+        let server_id = server_open.await.unwrap().unwrap();
+        assert_eq!(server_id, client_sub.id());
+        assert_ne!(server_id, pooled_id);
+        assert_pooled(0).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stream_pool_expiry() {
+        let ttl = Duration::from_millis(500);
+        let (server, client) = create_channel_streams_with_config(MultiplexConfig::default().with_stream_pool(4, ttl)).await;
+        let _ = tokio::join!(server.peer_capabilities(), client.peer_capabilities());
+        let pooled_ids = || (server.debug_state().pooled_ids, client.debug_state().pooled_ids);
+
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        drop((server_sub, client_sub));
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert_eq!(pooled_ids(), (1, 1));
+
+        // evicted from both nodes once the TTL passes, though no further open draws on the pool
+        tokio::time::sleep(ttl - Duration::from_millis(2)).await;
+        assert_eq!(pooled_ids(), (1, 1));
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        assert_eq!(pooled_ids(), (0, 0));
+    }
+
+    /// A transport whose first `failures` sends fail
+    struct FlakyConn<T> {
+        inner: T,
+        failures: std::sync::atomic::AtomicUsize
+    }
+
+    #[async_trait::async_trait]
+    impl<T: ReliableOrderedStreamToTarget> ReliableOrderedStreamToTarget for FlakyConn<T> {
+        async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+            if self.failures.fetch_update(std::sync::atomic::Ordering::Relaxed, std::sync::atomic::Ordering::Relaxed, |failures| failures.checked_sub(1)).is_ok() {
+                return Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Flaky send"))
+            }
+
+            self.inner.send_to_peer(input).await
+        }
+
+        async fn recv(&self) -> std::io::Result<Bytes> {
+            self.inner.recv().await
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restore_transport_mid_open() {
+        let (server_conn, client_conn) = channel_pair();
+        let (sever, severed) = tokio::sync::watch::channel(false);
+        let backoff = BackoffConfig { base: Duration::from_millis(10), max: Duration::from_millis(50), jitter: 0.0 };
+        let config = MultiplexConfig::new().with_transport_restore_grace(Duration::from_secs(5)).with_backoff(backoff);
+        let (server, client) = tokio::join!(
+            NetworkApplication::register_with_config(RelativeNodeType::Receiver, SeverableConn { inner: server_conn, severed: severed.clone() }, config.clone()),
+            NetworkApplication::register_with_config(RelativeNodeType::Initiator, SeverableConn { inner: client_conn, severed }, config)
+        );
+
+        let (server, client) = (server.unwrap(), client.unwrap());
+
+        // the next open requires a handshake
+        let mut held = drain_prereserved(&server);
+        held.extend(drain_prereserved(&client));
+
+        sever.send(true).unwrap();
+        let (server_open, client_open) = (server.clone(), client.clone());
+        let server_open = tokio::spawn(async move { server_open.initiate_subscription().await.map(|sub: OwnedMultiplexedSubscription| sub) });
+        let client_open = tokio::spawn(async move { client_open.initiate_subscription().await.map(|sub: OwnedMultiplexedSubscription| sub) });
+
+        // the open signal was lost with the transport
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert!(!server.pending_handshakes().opens.is_empty());
+
+        // the replay is retried after the backoff
+        let (server_conn, client_conn) = channel_pair();
+        let server_conn = FlakyConn { inner: server_conn, failures: std::sync::atomic::AtomicUsize::new(2) };
+        let (restored_server, restored_client) = tokio::join!(server.restore_transport(server_conn), client.restore_transport(client_conn));
+        restored_server.unwrap();
+        restored_client.unwrap();
+
+        let server_sub = tokio::time::timeout(Duration::from_secs(5), server_open).await.unwrap().unwrap().unwrap();
+        let client_sub = tokio::time::timeout(Duration::from_secs(5), client_open).await.unwrap().unwrap().unwrap();
+        assert_eq!(server_sub.id(), client_sub.id());
+        assert!(server.pending_handshakes().opens.is_empty());
+
+        server_sub.send_serialized(1u64).await.unwrap();
+        assert_eq!(client_sub.recv_serialized::<u64>().await.unwrap(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn recv_resilient() {
+        let (server_conn, client_conn) = channel_pair();
+        let (sever, severed) = tokio::sync::watch::channel(false);
+        let config = MultiplexConfig::new().with_transport_restore_grace(Duration::from_millis(500));
+        let (server, client) = tokio::join!(
+            NetworkApplication::register_with_config(RelativeNodeType::Receiver, SeverableConn { inner: server_conn, severed: severed.clone() }, config.clone()),
+            NetworkApplication::register_with_config(RelativeNodeType::Initiator, SeverableConn { inner: client_conn, severed }, config)
+        );
+
+        let (server, client) = (server.unwrap(), client.unwrap());
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let client_sub = std::sync::Arc::new(client_sub);
+
+        let pending = client_sub.clone();
+        let pending = tokio::spawn(async move { pending.recv_resilient(Duration::from_secs(5)).await });
+
+        sever.send(true).unwrap();
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        let (server_conn, client_conn) = channel_pair();
+        let (restored_server, restored_client) = tokio::join!(server.restore_transport(server_conn), client.restore_transport(client_conn));
+        restored_server.unwrap();
+        restored_client.unwrap();
+
+        server_sub.send_to_peer(b"after").await.unwrap();
+        assert_eq!(&pending.await.unwrap().unwrap()[..], b"after");
+
+        let err = client_sub.recv_resilient(Duration::from_millis(50)).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn negotiated_keepalive() {
+        let (server_conn, client_conn) = channel_pair();
+        let (sever, severed) = tokio::sync::watch::channel(false);
+        let (_never, never_severed) = tokio::sync::watch::channel(false);
+        let (server, client) = tokio::join!(
+            NetworkApplication::register_with_config(RelativeNodeType::Receiver, SeverableConn { inner: server_conn, severed: never_severed }, MultiplexConfig::new().with_keepalive_policy(Duration::from_secs(1), Duration::from_secs(3))),
+            NetworkApplication::register_with_config(RelativeNodeType::Initiator, SeverableConn { inner: client_conn, severed }, MultiplexConfig::new().with_keepalive_policy(Duration::from_secs(2), Duration::from_secs(10)))
+        );
+
+        let (server, client) = (server.unwrap(), client.unwrap());
+        let _ = tokio::join!(server.peer_capabilities(), client.peer_capabilities());
+        let negotiated = Some(KeepalivePolicy { interval: Duration::from_secs(1), timeout: Duration::from_secs(3) });
+        assert_eq!((server.keepalive_policy(), client.keepalive_policy()), (negotiated, negotiated));
+
+        // keepalive frames hold an otherwise idle connection open long past the timeout
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        assert!(server.demux_result().now_or_never().is_none());
+        assert!(client.demux_result().now_or_never().is_none());
+
+        // the client goes silent
+        sever.send(true).unwrap();
+        let start = tokio::time::Instant::now();
+        let err = server.demux_result().await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() <= Duration::from_secs(3));
+
+        // an adjacent node that does not advertise keepalives is neither sent any nor held to the timeout
+        let (conn, peer) = channel_pair();
+        let conn = NetworkApplication::register_with_config(RelativeNodeType::Receiver, conn, MultiplexConfig::new().with_keepalive_policy(Duration::from_secs(1), Duration::from_secs(3))).await.unwrap();
+        let capabilities = Capabilities { features: Vec::new(), ..Capabilities::local() };
+        peer.send_serialized(MultiplexedPacket::<SymmetricConvID>::Hello { capabilities }).await.unwrap();
+        let _ = conn.peer_capabilities().await.unwrap();
+        assert_eq!(conn.keepalive_policy(), None);
+
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        assert!(conn.demux_result().now_or_never().is_none());
+        while let Some(packet) = peer.recv_serialized::<MultiplexedPacket<SymmetricConvID>>().now_or_never() {
+            assert!(!matches!(packet.unwrap(), MultiplexedPacket::Keepalive));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn last_will() {
+        let (server_conn, client_conn) = channel_pair();
+        let (sever, severed) = tokio::sync::watch::channel(false);
+        let (_never, never_severed) = tokio::sync::watch::channel(false);
+        let config = MultiplexConfig::new().with_keepalive_policy(Duration::from_secs(1), Duration::from_secs(3));
+        let (server, client) = tokio::join!(
+            NetworkApplication::register_with_config(RelativeNodeType::Receiver, SeverableConn { inner: server_conn, severed: never_severed }, config.clone()),
+            NetworkApplication::register_with_config(RelativeNodeType::Initiator, SeverableConn { inner: client_conn, severed }, config)
+        );
+
+        let (server, client) = (server.unwrap(), client.unwrap());
+        let mut events = server.events();
+        client.set_last_will("presence", b"client went away".to_vec()).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // the client's transport dies without a goodbye
+        sever.send(true).unwrap();
+        let start = tokio::time::Instant::now();
+        assert_eq!(server.demux_result().await.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() <= Duration::from_secs(3));
+
+        match events.recv().await.unwrap() {
+            StreamEvent::LastWill { name, payload } => assert_eq!((name.as_str(), &payload[..]), ("presence", &b"client went away"[..])),
+            event => panic!("Unexpected event {:?}", event)
+        }
+
+        // a clean close leaves no last will behind
+        let (server, client) = create_streams().await;
+        let mut events = server.events();
+        client.set_last_will("presence", b"client went away".to_vec()).await.unwrap();
+        drop(client);
+        server.demux_result().await.unwrap();
+        assert!(events.try_recv().is_err());
+
+        // an adjacent node that does not advertise last wills is not sent one
+        let (conn, peer) = create_framed_pair().await;
+        let conn = NetworkApplication::register(RelativeNodeType::Receiver, conn).await.unwrap();
+        let capabilities = Capabilities { features: Vec::new(), ..Capabilities::local() };
+        peer.send_serialized(MultiplexedPacket::<SymmetricConvID>::Hello { capabilities }).await.unwrap();
+        assert!(conn.set_last_will("presence", Vec::new()).await.unwrap_err().to_string().contains("does not support"));
+    }
+}
@@ -34,11 +34,13 @@ impl NetworkEndpoint {
     pub async fn register<T: ReliableOrderedConnectionToTarget + 'static>(relative_node_type: RelativeNodeType, conn: T) -> Result<Self, anyhow::Error> {
         let (local_addr, peer_addr) = (conn.local_addr()?, conn.peer_addr()?);
         let endpoint = NetworkApplication::register(relative_node_type, conn).await?;
+        // inherited by the levels nested over its substreams, and by substreams promoted to connections
+        endpoint.set_addrs(local_addr, peer_addr);
         Ok(Self { endpoint, local_addr, peer_addr })
     }
 
     pub fn is_initiator(&self) -> bool {
-        self.node_type() == RelativeNodeType::Initiator
+        self.node_type().is_initiator()
     }
 }
 
@@ -90,7 +90,7 @@ async fn resolve<S: Subscribable<ID=K, UnderlyingConn=Conn>, K: MultiplexedConnK
     let local_state = LocalState { local_state: State::Pending, ret_value: None };
     let local_state_ref = &Mutex::new(local_state);
 
-    let has_preference = local_node_type == RelativeNodeType::Initiator;
+    let has_preference = local_node_type.is_initiator();
 
     // the evaluator finishes before the "completer" if this goes successfully
     let evaluator = async move {
@@ -0,0 +1,101 @@
+use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::multiplex::{AssembleParts, IDGen, MultiplexedConn, MultiplexedConnInner, MultiplexedConnKey};
+use crate::sync::SymmetricConvID;
+use crate::sync::network_application::INITIAL_CAPACITY;
+
+type SkipPast<K> = Box<dyn Fn(&<K as IDGen<K>>::Container) + Send + Sync>;
+
+/// A view created by [`MultiplexedConn::partition`], which receives every packet scoped to an id within `range`
+struct Partition<K: MultiplexedConnKey> {
+    range: std::ops::Range<u64>,
+    contains: Box<dyn Fn(&K) -> bool + Send + Sync>,
+    // advances a generator of the parent's past `range`
+    skip_past: SkipPast<K>,
+    conn: Weak<MultiplexedConnInner<K>>
+}
+
+/// The partitions carved out of a connection
+pub(crate) struct Partitions<K: MultiplexedConnKey> {
+    partitions: parking_lot::RwLock<Vec<Partition<K>>>
+}
+
+impl<K: MultiplexedConnKey> Partitions<K> {
+    pub(crate) fn new() -> Self {
+        Self { partitions: parking_lot::RwLock::new(Vec::new()) }
+    }
+
+    /// Returns the partition responsible for `id`, if any. Partitions that have since been dropped are ignored
+    pub(crate) fn get(&self, id: &K) -> Option<MultiplexedConn<K>> {
+        let partitions = self.partitions.read();
+        let partition = partitions.iter().find(|partition| (partition.contains)(id))?;
+        partition.conn.upgrade().map(|inner| MultiplexedConn { inner })
+    }
+
+    /// Advances `container` past the live partition containing `id`, returning false if there is none
+    pub(crate) fn skip_past(&self, id: &K, container: &K::Container) -> bool {
+        let partitions = self.partitions.read();
+        match partitions.iter().find(|partition| partition.conn.strong_count() != 0 && (partition.contains)(id)) {
+            Some(partition) => {
+                (partition.skip_past)(container);
+                true
+            }
+
+            None => false
+        }
+    }
+
+    /// Returns every partition still alive
+    pub(crate) fn live(&self) -> Vec<MultiplexedConn<K>> {
+        self.partitions.read().iter().filter_map(|partition| partition.conn.upgrade()).map(|inner| MultiplexedConn { inner }).collect()
+    }
+}
+
+impl MultiplexedConn<SymmetricConvID> {
+    /// Carves the ids in `id_range` out of this connection into a separate view over the same transport, with its own
+    /// subscriber map and its own opens and closes. Inbound packets for those ids are routed to the partition, so
+    /// independent components can each multiplex over one socket without stepping on each other's ids.
+    ///
+    /// The adjacent node must create the same partition. `id_range` must not overlap another partition, must lie above
+    /// every id this connection has issued so far. While the partition is alive, the ids this connection goes on to issue
+    /// skip over `id_range`. Opening more streams on the partition than fit in `id_range` is a logic error
+    pub fn partition(&self, id_range: std::ops::Range<u64>) -> Result<Self, anyhow::Error> {
+        if id_range.end.saturating_sub(id_range.start) <= INITIAL_CAPACITY as u64 {
+            return Err(anyhow::Error::msg(format!("A partition must span more than {} ids", INITIAL_CAPACITY)))
+        }
+
+        if id_range.start <= self.id_gen.load(Ordering::Relaxed) {
+            return Err(anyhow::Error::msg("Partition overlaps ids already issued by this connection"))
+        }
+
+        let mut partitions = self.partitions.partitions.write();
+        partitions.retain(|partition| partition.conn.strong_count() != 0);
+
+        if partitions.iter().any(|partition| partition.range.start < id_range.end && id_range.start < partition.range.end) {
+            return Err(anyhow::Error::msg("Partition overlaps an existing partition"))
+        }
+
+        let parts = AssembleParts {
+            conn: self.conn.clone(),
+            peer_max_frame: self.peer_max_frame.clone(),
+            transport: self.transport.clone(),
+            // the generators yield one past their current value
+            id_gen: Arc::new(AtomicU64::new(id_range.start - 1)),
+            current_latest_subscribed: Arc::new(AtomicU64::new(id_range.start - 1)),
+            // the partition has no demultiplexer of its own, so it reports the parent's
+            demux_status: self.demux_status.clone(),
+            demux_status_rx: self.demux_status_rx.clone(),
+            parent: Some(self.clone())
+        };
+
+        let partition = Self::assemble(self.node_type, self.config().clone(), parts);
+
+        let range = id_range.clone();
+        let end = id_range.end;
+        // the generators yield one past their current value, so the next id issued is `end`
+        let skip_past = Box::new(move |container: &Arc<AtomicU64>| { let _ = container.fetch_max(end - 1, Ordering::Relaxed); });
+        partitions.push(Partition { range: id_range, contains: Box::new(move |id| range.contains(&u64::from(*id))), skip_past, conn: Arc::downgrade(&partition.inner) });
+        Ok(partition)
+    }
+}
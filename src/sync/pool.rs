@@ -0,0 +1,104 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+use tokio::time::Instant;
+
+use crate::config::StreamPoolConfig;
+use crate::multiplex::MultiplexedConnKey;
+
+/// Recently-closed stream ids whose slot both nodes keep warm (see [`crate::config::MultiplexConfig::with_stream_pool`]).
+///
+/// The Receiver owns the pool: it decides which ids stay warm, enforces the size and TTL, and reopens an id by sending
+/// the open signal without awaiting the Initiator's echo. Ids are evicted on a timer once they outlive the TTL. The Initiator mirrors the warm ids so that it can recognize such
+/// a reopen, and forgets one only once the Receiver reopens or evicts it
+pub(crate) struct StreamPool<K: MultiplexedConnKey> {
+    config: Option<StreamPoolConfig>,
+    // ordered by when each id was closed, oldest first
+    warm: parking_lot::Mutex<VecDeque<(K, Instant)>>,
+    // Initiator: ids warmed while sending their half of the close handshake, whose close has not yet completed
+    closing: parking_lot::Mutex<HashSet<K>>,
+    // Receiver: notified whenever an id is kept warm, so that a sweep evicts it once it outlives the TTL
+    pending: Arc<tokio::sync::Notify>
+}
+
+impl<K: MultiplexedConnKey> StreamPool<K> {
+    pub(crate) fn new(config: Option<StreamPoolConfig>) -> Self {
+        Self { config, warm: parking_lot::Mutex::new(VecDeque::new()), closing: parking_lot::Mutex::new(HashSet::new()), pending: Arc::new(tokio::sync::Notify::new()) }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.config.is_some()
+    }
+
+    /// Receiver: keeps `id` warm, returning the ids that had to be evicted to stay within the pool size
+    pub(crate) fn release(&self, id: K) -> Vec<K> {
+        let size = match self.config {
+            Some(config) => config.size,
+            None => return vec![id]
+        };
+
+        let mut warm = self.warm.lock();
+        warm.push_back((id, Instant::now()));
+        self.pending.notify_one();
+        let overflow = warm.len().saturating_sub(size);
+        warm.drain(..overflow).map(|(id, _)| id).collect()
+    }
+
+    /// Receiver: takes the most recently closed id that has not outlived the TTL. The expired ids are evicted and returned
+    pub(crate) fn take(&self) -> (Option<K>, Vec<K>) {
+        let mut warm = self.warm.lock();
+        let expired = self.drain_expired(&mut warm);
+        (warm.pop_back().map(|(id, _)| id), expired)
+    }
+
+    /// Receiver: evicts and returns the ids that have outlived the TTL, along with when the next of those left expires.
+    /// Run by the connection for as long as anything is warm
+    pub(crate) fn expire(&self) -> (Vec<K>, Option<Instant>) {
+        let mut warm = self.warm.lock();
+        let expired = self.drain_expired(&mut warm);
+        let next_expiry = self.config.and_then(|config| warm.front().map(|(_, closed_at)| *closed_at + config.ttl));
+        (expired, next_expiry)
+    }
+
+    fn drain_expired(&self, warm: &mut VecDeque<(K, Instant)>) -> Vec<K> {
+        let ttl = match self.config {
+            Some(config) => config.ttl,
+            None => return Vec::new()
+        };
+
+        let expired = warm.iter().take_while(|(_, closed_at)| closed_at.elapsed() >= ttl).count();
+        warm.drain(..expired).map(|(id, _)| id).collect()
+    }
+
+    /// Notified once an id is kept warm, so that the connection sweeps only while anything is
+    pub(crate) fn pending(&self) -> Arc<tokio::sync::Notify> {
+        self.pending.clone()
+    }
+
+    /// Initiator: mirrors an id that the Receiver is about to keep warm
+    pub(crate) fn keep_warm(&self, id: K) {
+        self.warm.lock().push_back((id, Instant::now()));
+        self.closing.lock().insert(id);
+    }
+
+    /// Initiator: returns true if `id` was warmed by [`Self::keep_warm`] as it closed
+    pub(crate) fn finish_close(&self, id: K) -> bool {
+        self.closing.lock().remove(&id)
+    }
+
+    /// Initiator: forgets `id`, returning true if it was warm
+    pub(crate) fn reclaim(&self, id: K) -> bool {
+        let mut warm = self.warm.lock();
+        match warm.iter().position(|(warm_id, _)| *warm_id == id) {
+            Some(idx) => {
+                let _ = warm.remove(idx);
+                true
+            }
+
+            None => false
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.warm.lock().len()
+    }
+}
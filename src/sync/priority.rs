@@ -0,0 +1,240 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, Waker};
+use async_trait::async_trait;
+use bytes::Bytes;
+use tokio::sync::oneshot;
+
+use crate::reliable_conn::ReliableOrderedStreamToTarget;
+
+/// The scheduling class of a subscription (see [`crate::multiplex::MultiplexedConn::subscribe_with_priority`]). Under
+/// load, a connection writes and routes the frames of higher classes ahead of those of lower classes
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Bulk transfers that tolerate added latency
+    Low,
+    #[default]
+    Normal,
+    /// Latency-sensitive traffic
+    High
+}
+
+impl Priority {
+    const CLASSES: usize = 3;
+
+    fn class(self) -> usize {
+        self as usize
+    }
+}
+
+/// Hands out the right to write to the transport one send at a time, preferring waiting sends of higher priority.
+/// Sends of equal priority are served in the order they began waiting
+pub(crate) struct SendScheduler {
+    state: parking_lot::Mutex<SchedulerState>
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    busy: bool,
+    waiting: [VecDeque<oneshot::Sender<()>>; Priority::CLASSES],
+    // woken once no send holds or awaits the turn
+    idle_wakers: Vec<Waker>
+}
+
+impl SendScheduler {
+    pub(crate) fn new() -> Self {
+        Self { state: parking_lot::Mutex::new(SchedulerState::default()) }
+    }
+
+    /// Waits for the turn to write. The turn passes on once the returned guard drops
+    pub(crate) async fn turn(&self, priority: Priority) -> SendTurn<'_> {
+        let rx = {
+            let mut state = self.state.lock();
+            if !state.busy {
+                state.busy = true;
+                return SendTurn { scheduler: self }
+            }
+
+            let (tx, rx) = oneshot::channel();
+            state.waiting[priority.class()].push_back(tx);
+            rx
+        };
+
+        let mut waiter = Waiter { scheduler: self, rx, granted: false };
+        // the sender is only ever dropped after being sent to
+        let _ = (&mut waiter.rx).await;
+        waiter.granted = true;
+        SendTurn { scheduler: self }
+    }
+
+    /// Returns Ready if a send would be handed the turn without waiting
+    pub(crate) fn poll_idle(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.state.lock();
+        if !state.busy {
+            return Poll::Ready(())
+        }
+
+        if !state.idle_wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            state.idle_wakers.push(cx.waker().clone());
+        }
+
+        Poll::Pending
+    }
+
+    fn pass_turn(&self) {
+        let mut state = self.state.lock();
+        // waiters that gave up are skipped
+        while let Some(tx) = state.waiting.iter_mut().rev().find_map(|waiting| waiting.pop_front()) {
+            if tx.send(()).is_ok() {
+                return
+            }
+        }
+
+        state.busy = false;
+        state.idle_wakers.drain(..).for_each(Waker::wake);
+    }
+}
+
+pub(crate) struct SendTurn<'a> {
+    scheduler: &'a SendScheduler
+}
+
+impl Drop for SendTurn<'_> {
+    fn drop(&mut self) {
+        self.scheduler.pass_turn()
+    }
+}
+
+/// A send still waiting for its turn. If it is cancelled after the turn was passed to it, the turn passes on
+struct Waiter<'a> {
+    scheduler: &'a SendScheduler,
+    rx: oneshot::Receiver<()>,
+    granted: bool
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        if !self.granted && self.rx.try_recv().is_ok() {
+            self.scheduler.pass_turn()
+        }
+    }
+}
+
+/// Orders writes to the inner connection by priority. Sends that do not carry a priority are scheduled as [`Priority::Normal`].
+/// When the inner connection coalesces sends into batches, each send awaits the write of its whole batch, so the turn is
+/// not taken; the priority is passed on instead, and each batch is ordered by it.
+///
+/// As the outermost layer of a multiplexed connection, this is also where reads are checked: the demultiplexer is the
+/// connection's sole reader, and in debug builds a read that overlaps another panics
+pub(crate) struct ScheduledConn {
+    inner: Arc<dyn ReliableOrderedStreamToTarget>,
+    // None over a coalescing connection
+    scheduler: Option<SendScheduler>,
+    reading: AtomicBool
+}
+
+impl ScheduledConn {
+    pub(crate) fn new(inner: Arc<dyn ReliableOrderedStreamToTarget>, coalescing: bool) -> Self {
+        Self { inner, scheduler: (!coalescing).then(SendScheduler::new), reading: AtomicBool::new(false) }
+    }
+}
+
+/// Marks a read from a [`ScheduledConn`] as in progress until dropped
+struct SoleRead<'a> {
+    reading: &'a AtomicBool
+}
+
+impl<'a> SoleRead<'a> {
+    fn begin(reading: &'a AtomicBool) -> Self {
+        let overlapped = reading.swap(true, Ordering::SeqCst);
+        debug_assert!(!overlapped, "Read from a multiplexed connection while another read was in progress. The demultiplexer is the connection's sole reader; receive on a subscription instead");
+        Self { reading }
+    }
+}
+
+impl Drop for SoleRead<'_> {
+    fn drop(&mut self) {
+        self.reading.store(false, Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl ReliableOrderedStreamToTarget for ScheduledConn {
+    async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+        self.send_to_peer_with_priority(input, Priority::Normal).await
+    }
+
+    async fn send_to_peer_with_priority(&self, input: &[u8], priority: Priority) -> std::io::Result<()> {
+        match self.scheduler.as_ref() {
+            Some(scheduler) => {
+                let _turn = scheduler.turn(priority).await;
+                self.inner.send_to_peer(input).await
+            }
+
+            None => self.inner.send_to_peer_with_priority(input, priority).await
+        }
+    }
+
+    async fn recv(&self) -> std::io::Result<Bytes> {
+        let _read = SoleRead::begin(&self.reading);
+        self.inner.recv().await
+    }
+
+    fn peer_alive(&self) -> Option<bool> {
+        self.inner.peer_alive()
+    }
+
+    fn poll_send_ready(&self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.scheduler.as_ref().map_or(Poll::Ready(()), |scheduler| scheduler.poll_idle(cx)) {
+            Poll::Ready(()) => self.inner.poll_send_ready(cx),
+            Poll::Pending => Poll::Pending
+        }
+    }
+
+    /// Waits behind every send already queued
+    async fn shutdown(&self) -> std::io::Result<()> {
+        let _turn = match self.scheduler.as_ref() {
+            Some(scheduler) => Some(scheduler.turn(Priority::Low).await),
+            None => None
+        };
+
+        self.inner.shutdown().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::priority::{SendScheduler, Priority};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn higher_priority_served_first() {
+        let scheduler = Arc::new(SendScheduler::new());
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let first = scheduler.turn(Priority::Normal).await;
+
+        let mut waiting = Vec::new();
+        for priority in [Priority::Low, Priority::Normal, Priority::High, Priority::Low] {
+            let (scheduler, tx) = (scheduler.clone(), tx.clone());
+            waiting.push(tokio::spawn(async move {
+                let _turn = scheduler.turn(priority).await;
+                tx.send(priority).unwrap();
+            }));
+            // fixes the order in which the sends begin waiting
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // a waiter that gives up does not hold up the rest
+        let abandoned = tokio::time::timeout(Duration::from_millis(10), scheduler.turn(Priority::High)).await;
+        assert!(abandoned.is_err());
+
+        drop(first);
+        for expected in [Priority::High, Priority::Normal, Priority::Low, Priority::Low] {
+            assert_eq!(rx.recv().await.unwrap(), expected);
+        }
+
+        futures::future::try_join_all(waiting).await.unwrap();
+    }
+}
@@ -0,0 +1,49 @@
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use bytes::Bytes;
+
+use crate::multiplex::PayloadHandler;
+
+type Job = (PayloadHandler, Bytes);
+
+/// Dedicated threads that run payload handlers off the demultiplexer (see
+/// [`crate::config::MultiplexConfig::with_processing_pool`]). Each stream is bound to one thread when its handler is
+/// registered, so a stream's payloads are handled one at a time in the order they arrived, while different streams are
+/// handled in parallel. A handler that panics loses only the payload it was handling. The threads exit once every
+/// handler bound to them has dropped
+pub(crate) struct ProcessingPool {
+    workers: Vec<UnboundedSender<Job>>,
+    next: AtomicUsize
+}
+
+impl ProcessingPool {
+    pub(crate) fn new(threads: usize) -> std::io::Result<Self> {
+        let workers = (0..std::cmp::max(threads, 1)).map(|idx| {
+            let (tx, mut rx) = unbounded_channel::<Job>();
+            std::thread::Builder::new().name(format!("netbeam-processing-{}", idx)).spawn(move || {
+                while let Some((handler, payload)) = rx.blocking_recv() {
+                    // a panicking handler must not take down the thread, nor the other streams bound to it
+                    if std::panic::catch_unwind(AssertUnwindSafe(|| (handler)(payload))).is_err() {
+                        log::error!("A payload handler panicked. Discarding payload")
+                    }
+                }
+            })?;
+
+            Ok(tx)
+        }).collect::<std::io::Result<Vec<_>>>()?;
+
+        Ok(Self { workers, next: AtomicUsize::new(0) })
+    }
+
+    /// Wraps `handler` so that calling it queues the payload onto a thread of the pool, which is chosen round-robin
+    pub(crate) fn bind(&self, handler: PayloadHandler) -> PayloadHandler {
+        let worker = self.workers[self.next.fetch_add(1, Ordering::Relaxed) % self.workers.len()].clone();
+        Arc::new(move |payload| {
+            if worker.send((handler.clone(), payload)).is_err() {
+                log::warn!("Processing thread died. Discarding payload")
+            }
+        })
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::time::Instant;
+
+use crate::config::ReassemblyConfig;
+use crate::multiplex::MultiplexedConnKey;
+
+/// The most discarded messages remembered at once. Beyond it, the longest-remembered is forgotten, at the risk of its late
+/// fragments beginning a truncated message that is itself discarded once it outlives the timeout
+const MAX_DISCARDED: usize = 1024;
+
+/// Fragmented messages that have partially arrived (see [`crate::config::MultiplexConfig::with_fragmentation`]),
+/// bounded per stream and in total, and discarded once they outlive the timeout
+pub(crate) struct Reassembly<K: MultiplexedConnKey> {
+    config: ReassemblyConfig,
+    state: parking_lot::Mutex<ReassemblyState<K>>,
+    // notified whenever something is left for a sweep to expire
+    pending: Arc<tokio::sync::Notify>
+}
+
+struct ReassemblyState<K: MultiplexedConnKey> {
+    // keyed by stream and by the sender's message number
+    partial: HashMap<(K, u64), (Instant, Vec<u8>)>,
+    // the bytes held by each stream's partial messages
+    id_bytes: HashMap<K, usize>,
+    // messages already discarded, whose remaining fragments are dropped on arrival
+    discarded: HashMap<(K, u64), Instant>,
+    total_bytes: usize
+}
+
+impl<K: MultiplexedConnKey> ReassemblyState<K> {
+    /// Removes a partial message, taking its bytes off the running totals
+    fn take(&mut self, key: (K, u64)) -> Option<Vec<u8>> {
+        let (_, bytes) = self.partial.remove(&key)?;
+        self.total_bytes -= bytes.len();
+        if let Some(id_bytes) = self.id_bytes.get_mut(&key.0) {
+            *id_bytes -= bytes.len();
+            if *id_bytes == 0 {
+                let _ = self.id_bytes.remove(&key.0);
+            }
+        }
+
+        Some(bytes)
+    }
+
+    // remembered for another timeout, so that late fragments do not begin a truncated message
+    fn discard(&mut self, key: (K, u64)) {
+        let _ = self.take(key);
+        if self.discarded.len() >= MAX_DISCARDED {
+            if let Some(oldest) = self.discarded.iter().min_by_key(|(_, discarded_at)| **discarded_at).map(|(key, _)| *key) {
+                let _ = self.discarded.remove(&oldest);
+            }
+        }
+
+        let _ = self.discarded.insert(key, Instant::now());
+    }
+
+    fn discard_expired(&mut self, timeout: std::time::Duration) {
+        let expired = self.partial.iter().filter(|(_, (started, _))| started.elapsed() >= timeout).map(|(key, _)| *key).collect::<Vec<_>>();
+        self.discarded.retain(|_, discarded_at| discarded_at.elapsed() < timeout);
+        for key in expired {
+            log::warn!("Discarding incomplete fragmented message {:?} after {:?}", key, timeout);
+            self.discard(key);
+        }
+    }
+}
+
+impl<K: MultiplexedConnKey> Reassembly<K> {
+    pub(crate) fn new(config: ReassemblyConfig) -> Self {
+        Self { config, state: parking_lot::Mutex::new(ReassemblyState { partial: HashMap::new(), id_bytes: HashMap::new(), discarded: HashMap::new(), total_bytes: 0 }), pending: Arc::new(tokio::sync::Notify::new()) }
+    }
+
+    /// Adds a fragment of `message` on stream `id`, returning the whole message once its last fragment arrives
+    pub(crate) fn push(&self, id: K, message: u64, last: bool, chunk: Vec<u8>) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        let mut state = self.state.lock();
+        let key = (id, message);
+
+        if state.discarded.contains_key(&key) {
+            if last {
+                let _ = state.discarded.remove(&key);
+            }
+
+            return Ok(None)
+        }
+
+        // a message of a single fragment is never held
+        if last && !state.partial.contains_key(&key) {
+            return Ok(Some(chunk))
+        }
+
+        let id_bytes = state.id_bytes.get(&id).copied().unwrap_or(0);
+        if id_bytes + chunk.len() > self.config.max_bytes_per_id || state.total_bytes + chunk.len() > self.config.max_total_bytes {
+            // nothing of the message is left to arrive after its last fragment, so there is nothing to remember
+            if last {
+                let _ = state.take(key);
+            } else {
+                state.discard(key);
+            }
+
+            return Err(anyhow::Error::msg(format!("Discarding fragmented message {} on {:?}, which exceeds the reassembly limits", message, id)))
+        }
+
+        if last {
+            // checked above
+            let mut bytes = state.take(key).unwrap();
+            bytes.extend_from_slice(&chunk);
+            return Ok(Some(bytes))
+        }
+
+        self.pending.notify_one();
+        state.total_bytes += chunk.len();
+        *state.id_bytes.entry(id).or_default() += chunk.len();
+        state.partial.entry(key).or_insert_with(|| (Instant::now(), Vec::new())).1.extend_from_slice(&chunk);
+        Ok(None)
+    }
+
+    /// Discards the messages that have outlived the timeout, returning true if anything is left for a later sweep. Run by
+    /// the connection every `timeout` for as long as something is left
+    pub(crate) fn sweep(&self) -> bool {
+        let mut state = self.state.lock();
+        state.discard_expired(self.config.timeout);
+        !state.partial.is_empty() || !state.discarded.is_empty()
+    }
+
+    /// Notified once something is left for a sweep, so that the connection sweeps only while anything is
+    pub(crate) fn pending(&self) -> Arc<tokio::sync::Notify> {
+        self.pending.clone()
+    }
+
+    /// How often [`Self::sweep`] needs to run
+    pub(crate) fn timeout(&self) -> std::time::Duration {
+        self.config.timeout
+    }
+
+    /// The bytes held by incomplete messages
+    pub(crate) fn buffered_bytes(&self) -> usize {
+        self.state.lock().total_bytes
+    }
+}
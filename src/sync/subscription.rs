@@ -1,13 +1,15 @@
-use crate::reliable_conn::ReliableOrderedStreamToTarget;
-use crate::multiplex::{MultiplexedConnKey, MultiplexedPacket, MultiplexedConn, MemorySender};
+use crate::reliable_conn::{ReliableOrderedStreamToTarget, ReliableOrderedConnectionToTarget, ConnAddr, serialize_to_buffer};
+use crate::multiplex::{MultiplexedConnKey, MultiplexedPacket, ApplicationLayerRef, encode_compact_frame, MultiplexedConn, SubscriberMap, TopologySource, InboundReceiver, OutboundGate};
 use tokio::sync::Mutex;
-use tokio::sync::mpsc::UnboundedReceiver;
-use parking_lot::RwLock;
-use std::collections::HashMap;
 use crate::sync::network_application::{PostActionChannel, PreActionChannel, PreActionSync, PostActionSync};
 use crate::sync::RelativeNodeType;
+use crate::sync::priority::Priority;
+use crate::config::MultiplexConfig;
 use bytes::Bytes;
 use async_trait::async_trait;
+use std::sync::Weak;
+use std::task::{Context, Poll};
+use std::net::SocketAddr;
 
 #[async_trait]
 pub trait SubscriptionBiStream: Send + Sync {
@@ -15,18 +17,105 @@ pub trait SubscriptionBiStream: Send + Sync {
     type ID: MultiplexedConnKey;
 
     fn conn(&self) -> &Self::Conn;
-    fn receiver(&self) -> &Mutex<UnboundedReceiver<Vec<u8>>>;
+    fn receiver(&self) -> &Mutex<InboundReceiver>;
     fn id(&self) -> Self::ID;
     fn node_type(&self) -> RelativeNodeType;
+
+    /// The priority this stream's sends are scheduled with
+    fn priority(&self) -> Priority {
+        Priority::Normal
+    }
+
+    /// Returns true if the adjacent node decodes compact frames, which carry less per-frame overhead
+    fn compact_frames(&self) -> bool {
+        false
+    }
+
+    /// Messages larger than this many bytes are sent as fragments
+    fn fragment_size(&self) -> Option<usize> {
+        None
+    }
+
+    /// The instant by which sends must begin and receives must complete, if any (see [`crate::multiplex::MultiplexedConn::set_deadline`])
+    fn deadline(&self) -> Option<tokio::time::Instant> {
+        None
+    }
+
+    /// Counts a message sent on this stream (see [`crate::multiplex::MultiplexedConn::stats`]), and mirrors it to any taps
+    fn record_sent(&self, _message: &[u8]) {}
+
+    /// Admits sends into this stream's outbound queue, if it tracks one (see [`crate::multiplex::MultiplexedConn::outbound_queue_len`])
+    fn outbound_gate(&self) -> Option<OutboundGate> {
+        None
+    }
+
+    /// Where this stream's send and receive spans are recorded
+    #[cfg(feature = "otel")]
+    fn traces(&self) -> Option<&crate::telemetry::StreamTraces<Self::ID>> {
+        None
+    }
+
+    /// Returns true if the local node is the Initiator, which wins any symmetric contention (see [`RelativeNodeType::is_initiator`])
+    fn is_initiator(&self) -> bool {
+        self.node_type().is_initiator()
+    }
+
+    /// The configuration a multiplexed level created on top of this stream inherits: the payload bounds and inbound channel
+    /// policies of this stream's level (see [`SubscriptionBiStreamExt::multiplex`])
+    fn inherited_config(&self) -> Option<MultiplexConfig> {
+        None
+    }
+
+    /// Returns a callback that records a newly-created multiplexed level on top of this stream within the parent level's topology
+    fn nested_level_attacher(&self) -> Option<NestedLevelAttacher> {
+        None
+    }
+
+    /// The local and peer addrs of the connection beneath this stream's level, if known (see [`crate::sync::network_endpoint::NetworkEndpoint`])
+    fn addrs(&self) -> Option<(SocketAddr, SocketAddr)> {
+        None
+    }
 }
 
+pub type NestedLevelAttacher = Box<dyn FnOnce(Weak<dyn TopologySource>) + Send>;
+
 #[async_trait]
 pub trait SubscriptionBiStreamExt: SubscriptionBiStream {
     /// Creates a new multiplexed level capable of obtaining more subscribers.
-    /// Uses Self as a reliable ordered connection, while using NewId to identify the substreams in the created next level
+    /// Uses Self as a reliable ordered connection, while using NewId to identify the substreams in the created next level.
+    /// The new level inherits the payload bounds and inbound channel policies of the level this stream belongs to (see
+    /// [`SubscriptionBiStream::inherited_config`]), but none of its handlers, runtime, keepalive, processing pool or tracer
     async fn multiplex<NewID: MultiplexedConnKey + 'static>(self) -> Result<MultiplexedConn<NewID>, anyhow::Error>
+        where Self: Sized + 'static, Self::ID: 'static {
+        let config = self.inherited_config().unwrap_or_default();
+        self.multiplex_with(config).await
+    }
+
+    /// Like [`Self::multiplex`], but configures the new level with `config` rather than inheriting the configuration
+    async fn multiplex_with<NewID: MultiplexedConnKey + 'static>(self, config: MultiplexConfig) -> Result<MultiplexedConn<NewID>, anyhow::Error>
+        where Self: Sized + 'static, Self::ID: 'static {
+        // the new level takes ownership of self, so the linkage to the parent level is captured beforehand
+        let (attacher, parent_id, addrs) = (self.nested_level_attacher(), self.id(), self.addrs());
+        let conn = MultiplexedConn::<NewID>::register_with_config(self.node_type(), self, config).await?;
+        conn.set_parent_id(parent_id);
+        if let Some((local_addr, peer_addr)) = addrs {
+            conn.set_addrs(local_addr, peer_addr);
+        }
+
+        if let Some(attacher) = attacher {
+            (attacher)(conn.as_topology_source())
+        }
+
+        Ok(conn)
+    }
+
+    /// Detaches this substream from any knowledge of multiplexing, returning it as an opaque reliable-ordered connection
+    /// that can be handed to another protocol stack. Its addrs are those of the connection beneath this stream's level,
+    /// and fail with AddrNotAvailable if unknown. Dropping the returned connection still runs the close sequence
+    fn into_connection(self) -> impl ReliableOrderedConnectionToTarget
         where Self: Sized + 'static {
-        MultiplexedConn::register(self.node_type(), self).await
+        let addrs = self.addrs();
+        SubstreamConnection { inner: Box::new(self), addrs }
     }
 }
 
@@ -40,8 +129,10 @@ pub trait Subscribable: Send + Sync + Sized {
     type BorrowedSubscriptionType: SubscriptionBiStream<ID=Self::ID, Conn=Self::UnderlyingConn> + Into<Self::SubscriptionType>;
     // TODO on stabalization of GATs: type BorrowedSubscriptionType<'a>: SubscriptionBiStream<ID=Self::ID, Conn=Self::UnderlyingConn> + Into<Self::SubscriptionType>;
 
+    /// The connection the demultiplexer reads from. It may be sent on, but never read from: a read would take frames
+    /// meant for the demultiplexer, and panics in debug builds
     fn underlying_conn(&self) -> &Self::UnderlyingConn;
-    fn subscriptions(&self) -> &RwLock<HashMap<Self::ID, MemorySender>>;
+    fn subscriptions(&self) -> &SubscriberMap<Self::ID>;
     fn post_close_container(&self) -> &PostActionChannel<Self::ID>;
     fn pre_action_container(&self) -> &PreActionChannel<Self::ID>;
 
@@ -51,46 +142,228 @@ pub trait Subscribable: Send + Sync + Sized {
 
     fn node_type(&self) -> RelativeNodeType;
 
+    /// Returns true if the local node is the Initiator, which wins any symmetric contention (see [`RelativeNodeType::is_initiator`])
+    fn is_initiator(&self) -> bool {
+        self.node_type().is_initiator()
+    }
+
+    /// The runtime that background work on behalf of this connection, such as the close sequence, is spawned onto
+    fn runtime(&self) -> Option<tokio::runtime::Handle> {
+        tokio::runtime::Handle::try_current().ok()
+    }
+
     fn initiate_subscription(&self) -> PreActionSync<'_, Self, Self::UnderlyingConn> {
         PreActionSync::new(self)
     }
 
     fn get_next_prereserved(&self) -> Option<Self::BorrowedSubscriptionType>;
-    fn subscribe(&self, id: Self::ID) -> Self::BorrowedSubscriptionType;
-    fn owned_subscription(&self, id: Self::ID) -> Self::SubscriptionType;
+
+    /// Receiver: reopens a recently-closed stream kept warm by the stream pool, if any, announcing it to the adjacent
+    /// node without awaiting a reply
+    async fn reopen_pooled(&self) -> Result<Option<Self::BorrowedSubscriptionType>, anyhow::Error> {
+        Ok(None)
+    }
+
+    /// Initiator: returns the stream for `id` if the Receiver's open signal reopened it from the stream pool
+    fn take_reopened(&self, _id: Self::ID) -> Option<Self::BorrowedSubscriptionType> {
+        None
+    }
+
+    /// Returns the reason the open of `id` was rejected for exceeding the Initiator's open rate, if it was, consuming
+    /// the id on both nodes
+    fn take_rejected_open(&self, _id: Self::ID) -> Option<String> {
+        None
+    }
+
+    /// The instant by which opens must complete, if any
+    fn deadline(&self) -> Option<tokio::time::Instant> {
+        None
+    }
+
+    /// Runs once both nodes have completed the open handshake for `id`
+    fn on_open_complete(&self, _id: Self::ID) {}
+
+    /// Runs as the local node begins closing `id`, before the close handshake
+    async fn on_close_begin(&self, _id: Self::ID) {}
+
+    /// Runs once both nodes have completed the close handshake for `id`
+    async fn on_close_complete(&self, id: Self::ID) {
+        let _ = self.subscriptions().shard(&id).write().remove(&id);
+    }
+
+    /// Creates the local end of stream `id`. Fails if `id` is already subscribed to
+    fn subscribe(&self, id: Self::ID) -> Result<Self::BorrowedSubscriptionType, anyhow::Error>;
+    fn owned_subscription(&self, id: Self::ID) -> Result<Self::SubscriptionType, anyhow::Error>;
     fn get_next_id(&self) -> Self::ID;
 }
 
+fn deadline_elapsed() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::TimedOut, "The stream's deadline has passed")
+}
+
+/// Writes `input` to the connection as one or more frames scoped to the stream
+async fn send_frames<R: SubscriptionBiStream + ?Sized>(this: &R, input: &[u8]) -> std::io::Result<()> {
+    if let Some(size) = this.fragment_size().filter(|size| input.len() > *size) {
+        // distinguishes this message's fragments from those of any message sent concurrently on the same stream
+        let message = rand::random::<u64>();
+        let mut chunks = input.chunks(size).peekable();
+
+        while let Some(chunk) = chunks.next() {
+            let packet = MultiplexedPacket::Fragment { id: this.id(), message, last: chunks.peek().is_none(), chunk: chunk.to_vec() };
+            this.conn().send_to_peer_with_priority(&serialize_to_buffer(&packet)?, this.priority()).await?;
+        }
+
+        return Ok(())
+    }
+
+    if this.compact_frames() {
+        if let Some(frame) = encode_compact_frame(this.id(), input)? {
+            return this.conn().send_to_peer_with_priority(&frame, this.priority()).await
+        }
+    }
+
+    let packet = ApplicationLayerRef::ApplicationLayer { id: this.id(), payload: input };
+    this.conn().send_to_peer_with_priority(&serialize_to_buffer(&packet)?, this.priority()).await
+}
+
 #[async_trait]
 impl<R: SubscriptionBiStream + ?Sized> ReliableOrderedStreamToTarget for R {
     async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
-        let packet = MultiplexedPacket::ApplicationLayer { id: self.id(), payload: input.to_vec() };
-        self.conn().send_to_peer(&bincode2::serialize(&packet).unwrap()).await
+        #[cfg(feature = "otel")]
+        let start = crate::telemetry::SpanStart::now();
+        // a send is not interrupted once it has begun, since abandoning a partly-written frame would corrupt the connection
+        let res = match self.deadline() {
+            Some(deadline) if tokio::time::Instant::now() >= deadline => Err(deadline_elapsed()),
+            _ => {
+                // waits while the stream's outbound queue is full
+                let _slot = match self.outbound_gate() {
+                    Some(gate) => Some(gate.enter().await),
+                    None => None
+                };
+
+                send_frames(self, input).await
+            }
+        };
+
+        if res.is_ok() {
+            self.record_sent(input)
+        }
+
+        #[cfg(feature = "otel")]
+        if let Some(traces) = self.traces() {
+            traces.record(self.id(), crate::telemetry::StreamOperation::Send, start)
+        }
+
+        res
     }
 
     async fn recv(&self) -> std::io::Result<Bytes> {
-        self.receiver().lock().await.recv().await.map(Bytes::from).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::ConnectionReset, "Receiver died"))
+        #[cfg(feature = "otel")]
+        let start = crate::telemetry::SpanStart::now();
+        let recv = async {
+            self.receiver().lock().await.recv().await.map(Bytes::from).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::ConnectionReset, "Receiver died"))
+        };
+
+        let res = match self.deadline() {
+            Some(deadline) => tokio::time::timeout_at(deadline, recv).await.unwrap_or_else(|_| Err(deadline_elapsed())),
+            None => recv.await
+        };
+
+        #[cfg(feature = "otel")]
+        if let Some(traces) = self.traces() {
+            traces.record(self.id(), crate::telemetry::StreamOperation::Recv, start)
+        }
+
+        res
+    }
+
+    fn peer_alive(&self) -> Option<bool> {
+        self.conn().peer_alive()
     }
+
+    /// Substreams have no flow control of their own, so this reflects whether the connection's send queue is idle
+    fn poll_send_ready(&self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.conn().poll_send_ready(cx)
+    }
+}
+
+/// A substream detached from multiplexing by [`SubscriptionBiStreamExt::into_connection`]
+struct SubstreamConnection {
+    inner: Box<dyn ReliableOrderedStreamToTarget>,
+    addrs: Option<(SocketAddr, SocketAddr)>
 }
 
-pub(crate) fn close_sequence_for_multiplexed_bistream<S: Subscribable<ID=K> + 'static, K: MultiplexedConnKey + 'static>(id: K, ptr: S) {
+impl SubstreamConnection {
+    fn addrs(&self) -> std::io::Result<(SocketAddr, SocketAddr)> {
+        self.addrs.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::AddrNotAvailable, "The addrs of the substream's connection are unknown"))
+    }
+}
+
+#[async_trait]
+impl ReliableOrderedStreamToTarget for SubstreamConnection {
+    async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+        self.inner.send_to_peer(input).await
+    }
+
+    async fn send_to_peer_with_priority(&self, input: &[u8], priority: Priority) -> std::io::Result<()> {
+        self.inner.send_to_peer_with_priority(input, priority).await
+    }
+
+    async fn recv(&self) -> std::io::Result<Bytes> {
+        self.inner.recv().await
+    }
+
+    fn peer_alive(&self) -> Option<bool> {
+        self.inner.peer_alive()
+    }
+
+    fn poll_send_ready(&self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.inner.poll_send_ready(cx)
+    }
+
+    async fn shutdown(&self) -> std::io::Result<()> {
+        self.inner.shutdown().await
+    }
+}
+
+impl ConnAddr for SubstreamConnection {
+    fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        Ok(self.addrs()?.0)
+    }
+
+    fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        Ok(self.addrs()?.1)
+    }
+}
+
+/// Closes `id`, then resolves `on_closed`, if any
+pub(crate) fn close_sequence_for_multiplexed_bistream<S: Subscribable<ID=K> + 'static, K: MultiplexedConnKey + 'static>(id: K, ptr: S, on_closed: Option<tokio::sync::oneshot::Sender<()>>) {
     log::info!("Running DROP on {:?}", id);
 
     fn close<S: Subscribable<ID=K>, K: MultiplexedConnKey>(id: K, ptr: &S) {
-        let _ = ptr.subscriptions().write().remove(&id);
+        let _ = ptr.subscriptions().shard(&id).write().remove(&id);
         log::info!("DROPPED id = {:?}", id);
     }
 
     // the runtime may not exist while dropping
-    if let Ok(rt) = tokio::runtime::Handle::try_current() {
+    if let Some(rt) = ptr.runtime() {
         rt.spawn(async move {
-            if let Err(err) = PostActionSync::new(&ptr, id).await {
-                log::warn!("[MetaActionSync/close] error: {:?}", err.to_string())
+            match PostActionSync::new(&ptr, id).await {
+                Ok(()) => ptr.on_close_complete(id).await,
+                Err(err) => {
+                    log::warn!("[MetaActionSync/close] error: {:?}", err.to_string());
+                    close(id, &ptr)
+                }
             }
 
-            close(id, &ptr)
+            if let Some(on_closed) = on_closed {
+                let _ = on_closed.send(());
+            }
         });
     } else {
         close(id, &ptr);
+        if let Some(on_closed) = on_closed {
+            let _ = on_closed.send(());
+        }
     }
 }
\ No newline at end of file
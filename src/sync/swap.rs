@@ -0,0 +1,101 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::reliable_conn::ReliableOrderedStreamToTarget;
+
+/// The transport beneath a [`MultiplexedConn`](crate::multiplex::MultiplexedConn), which may be replaced while the connection is live. Sends hold a read
+/// lock for their duration, so a replacement waits for in-flight sends on the old transport to drain first
+pub(crate) struct SwappableConn {
+    outbound: tokio::sync::RwLock<Arc<dyn ReliableOrderedStreamToTarget>>,
+    inbound: parking_lot::Mutex<Arc<dyn ReliableOrderedStreamToTarget>>,
+    staged_inbound: parking_lot::Mutex<Option<Arc<dyn ReliableOrderedStreamToTarget>>>,
+    staged: tokio::sync::Notify,
+    restore_grace: Option<std::time::Duration>,
+    // bumped by each restore
+    generation: AtomicU64,
+    restored: tokio::sync::Notify
+}
+
+impl SwappableConn {
+    pub(crate) fn new(conn: Arc<dyn ReliableOrderedStreamToTarget>, restore_grace: Option<std::time::Duration>) -> Self {
+        Self { outbound: tokio::sync::RwLock::new(conn.clone()), inbound: parking_lot::Mutex::new(conn), staged_inbound: parking_lot::Mutex::new(None), staged: tokio::sync::Notify::new(), restore_grace, generation: AtomicU64::new(0), restored: tokio::sync::Notify::new() }
+    }
+
+    /// Replaces both directions at once with `conn`, abandoning the old transport along with any frames in flight on it
+    pub(crate) async fn restore(&self, conn: Arc<dyn ReliableOrderedStreamToTarget>) {
+        let mut outbound = self.outbound.write().await;
+        *outbound = conn.clone();
+        *self.inbound.lock() = conn;
+        let _ = self.staged_inbound.lock().take();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.restored.notify_waiters();
+    }
+
+    /// Makes `conn` available to the demultiplexer, which switches over to it once the peer's swap marker arrives
+    pub(crate) fn stage_inbound(&self, conn: Arc<dyn ReliableOrderedStreamToTarget>) -> std::io::Result<()> {
+        let mut staged = self.staged_inbound.lock();
+        if staged.is_some() {
+            return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "A transport replacement is already pending"))
+        }
+
+        *staged = Some(conn);
+        self.staged.notify_one();
+        Ok(())
+    }
+
+    /// Called by the demultiplexer after the peer's swap marker, the last frame the peer sends on the old transport
+    pub(crate) async fn switch_inbound(&self) {
+        loop {
+            if let Some(conn) = self.staged_inbound.lock().take() {
+                *self.inbound.lock() = conn;
+                return
+            }
+
+            self.staged.notified().await
+        }
+    }
+
+    pub(crate) async fn outbound(&self) -> tokio::sync::RwLockWriteGuard<'_, Arc<dyn ReliableOrderedStreamToTarget>> {
+        self.outbound.write().await
+    }
+}
+
+#[async_trait]
+impl ReliableOrderedStreamToTarget for SwappableConn {
+    async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+        self.outbound.read().await.send_to_peer(input).await
+    }
+
+    async fn recv(&self) -> std::io::Result<Bytes> {
+        loop {
+            // created before reading the generation, so that a restore in between is not missed
+            let restored = self.restored.notified();
+            let generation = self.generation.load(Ordering::SeqCst);
+            let conn = self.inbound.lock().clone();
+            let res = conn.recv().await;
+
+            let grace = match (&res, self.restore_grace) {
+                (Ok(packet), Some(grace)) if packet.is_empty() => grace,
+                (Err(_), Some(grace)) => grace,
+                _ => return res
+            };
+
+            if self.generation.load(Ordering::SeqCst) == generation {
+                log::warn!("Transport lost ({:?}). Waiting up to {:?} for it to be restored", res, grace);
+                if tokio::time::timeout(grace, restored).await.is_err() {
+                    return res
+                }
+            }
+        }
+    }
+
+    fn peer_alive(&self) -> Option<bool> {
+        self.outbound.try_read().ok().and_then(|conn| conn.peer_alive())
+    }
+
+    async fn shutdown(&self) -> std::io::Result<()> {
+        self.outbound.read().await.shutdown().await
+    }
+}
@@ -107,11 +107,12 @@ async fn synchronize<S: Subscribable<ID=K, UnderlyingConn=Conn>, K: MultiplexedC
     match relative_node_type {
         RelativeNodeType::Receiver => {
             log::info!("[Sync] Receiver sending SYN ...");
-            let now = tt.get_global_time_ns();
+            // local durations are measured with tokio's clock so that tokio::time::pause() is respected
+            let now = tokio::time::Instant::now();
             conn.send_serialized::<SyncPacket<P>>(SyncPacket::Syn(payload)).await?;
             log::info!("[Sync] Receiver awaiting SYN_ACK ...");
             let payload_recv = conn.recv_until_serialized::<SyncPacket<P>, _>(|p| p.is_syn_ack()).await?.payload()?;
-            let rtt = tt.get_global_time_ns() - now;
+            let rtt = now.elapsed().as_nanos() as i64;
             let sync_time = tt.get_global_time_ns() + rtt;
             log::info!("[Sync] Receiver sending ACK...");
             conn.send_serialized::<SyncPacket<P>>(SyncPacket::<P>::Ack(sync_time)).await?;
@@ -146,7 +147,8 @@ async fn synchronize<S: Subscribable<ID=K, UnderlyingConn=Conn>, K: MultiplexedC
 mod tests {
     use crate::time_tracker::TimeTracker;
     use futures::{FutureExt, StreamExt};
-    use crate::sync::test_utils::create_streams;
+    use crate::sync::test_utils::{create_streams, create_streams_with_addrs_and_lag};
+    use std::time::Duration;
 
     fn setup_log() {
         std::env::set_var("RUST_LOG", "error,warn,info,trace");
@@ -221,6 +223,23 @@ mod tests {
         res1.unwrap();
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn run_getter_paused_clock() {
+        setup_log();
+
+        // a 10-20s simulated one-way lag would make this test take over a minute on the wall clock
+        let (server, client) = create_streams_with_addrs_and_lag(10_000).await;
+        let wall_clock = std::time::Instant::now();
+
+        let server = tokio::spawn(async move { server.sync_exchange_payload(100).await.unwrap() });
+        let client = tokio::spawn(async move { client.sync_exchange_payload(99).await.unwrap() });
+        let (res0, res1) = tokio::join!(server, client);
+
+        assert_eq!(res0.unwrap(), 99);
+        assert_eq!(res1.unwrap(), 100);
+        assert!(wall_clock.elapsed() < Duration::from_secs(10));
+    }
+
     async fn dummy_function(_payload: u64) -> i64 {
         TimeTracker::new().get_global_time_ns()
     }
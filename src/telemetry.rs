@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::multiplex::MultiplexedConnKey;
+
+/// An operation on a stream that is recorded as a span
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StreamOperation {
+    /// From the first open signal, sent or received, until the open handshake completes
+    Open,
+    Send,
+    Recv,
+    /// From dropping the local end until both nodes complete the close handshake
+    Close
+}
+
+/// A completed operation on a stream, to be exported by a [`StreamTracer`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamSpan {
+    pub operation: StreamOperation,
+    /// The stream's id, formatted with `Debug`. Intended as a span attribute
+    pub stream_id: String,
+    /// The trace context the adjacent node sent while opening the stream, if any, which the span should link to
+    pub remote_parent: Option<Vec<u8>>,
+    pub start: SystemTime,
+    pub duration: Duration
+}
+
+/// Bridges stream lifecycles to an OpenTelemetry SDK, or any tracer that can create spans with explicit timestamps.
+/// See [`crate::config::MultiplexConfig::with_tracer`]
+pub trait StreamTracer: Send + Sync + 'static {
+    /// Serializes the current trace context, such as a W3C `traceparent`, to send to the adjacent node with each open signal
+    fn inject(&self) -> Option<Vec<u8>>;
+    /// Exports a completed span
+    fn record(&self, span: StreamSpan);
+}
+
+#[derive(Clone)]
+pub(crate) struct TracerHandle(pub(crate) Arc<dyn StreamTracer>);
+
+impl std::fmt::Debug for TracerHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StreamTracer")
+    }
+}
+
+/// The start of a span, on both the wall clock and the monotonic clock
+#[derive(Copy, Clone)]
+pub(crate) struct SpanStart {
+    at: SystemTime,
+    instant: Instant
+}
+
+impl SpanStart {
+    pub(crate) fn now() -> Self {
+        Self { at: SystemTime::now(), instant: Instant::now() }
+    }
+}
+
+#[derive(Default)]
+struct StreamTrace {
+    opening: Option<SpanStart>,
+    closing: Option<SpanStart>,
+    remote_parent: Option<Vec<u8>>
+}
+
+/// The trace state of each stream that has exchanged trace context or is mid-open or mid-close
+pub struct StreamTraces<K: MultiplexedConnKey> {
+    tracer: Option<Arc<dyn StreamTracer>>,
+    streams: parking_lot::Mutex<HashMap<K, StreamTrace>>
+}
+
+impl<K: MultiplexedConnKey> StreamTraces<K> {
+    pub(crate) fn new(tracer: Option<TracerHandle>) -> Self {
+        Self { tracer: tracer.map(|tracer| tracer.0), streams: parking_lot::Mutex::new(HashMap::new()) }
+    }
+
+    /// Called as a local open signal goes out, returning the trace context to send with it
+    pub(crate) fn on_open_signal(&self, id: K) -> Option<Vec<u8>> {
+        let tracer = self.tracer.as_ref()?;
+        let _ = self.streams.lock().entry(id).or_default().opening.get_or_insert_with(SpanStart::now);
+        tracer.inject()
+    }
+
+    pub(crate) fn on_remote_context(&self, id: K, trace_context: Vec<u8>) {
+        if self.tracer.is_some() {
+            let mut streams = self.streams.lock();
+            let trace = streams.entry(id).or_default();
+            let _ = trace.opening.get_or_insert_with(SpanStart::now);
+            trace.remote_parent = Some(trace_context);
+        }
+    }
+
+    pub(crate) fn on_opened(&self, id: K) {
+        let start = self.streams.lock().get_mut(&id).and_then(|trace| trace.opening.take());
+        if let Some(start) = start {
+            self.record(id, StreamOperation::Open, start)
+        }
+    }
+
+    pub(crate) fn on_close_started(&self, id: K) {
+        if self.tracer.is_some() {
+            self.streams.lock().entry(id).or_default().closing = Some(SpanStart::now());
+        }
+    }
+
+    pub(crate) fn on_closed(&self, id: K) {
+        let trace = self.streams.lock().remove(&id);
+        if let Some(StreamTrace { closing: Some(start), remote_parent, .. }) = trace {
+            self.export(id, StreamOperation::Close, start, remote_parent)
+        }
+    }
+
+    pub(crate) fn record(&self, id: K, operation: StreamOperation, start: SpanStart) {
+        if self.tracer.is_some() {
+            let remote_parent = self.streams.lock().get(&id).and_then(|trace| trace.remote_parent.clone());
+            self.export(id, operation, start, remote_parent)
+        }
+    }
+
+    fn export(&self, id: K, operation: StreamOperation, start: SpanStart, remote_parent: Option<Vec<u8>>) {
+        if let Some(tracer) = self.tracer.as_ref() {
+            tracer.record(StreamSpan { operation, stream_id: format!("{:?}", id), remote_parent, start: start.at, duration: start.instant.elapsed() })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::telemetry::{StreamTracer, StreamSpan, StreamOperation};
+    use crate::sync::test_utils::{channel_pair, open_pair, drain_prereserved};
+    use crate::sync::subscription::SubscriptionBiStream;
+    use crate::sync::network_application::NetworkApplication;
+    use crate::sync::{RelativeNodeType, SymmetricConvID};
+    use crate::multiplex::MultiplexedPacket;
+    use crate::reliable_conn::ReliableOrderedStreamToTarget;
+    use crate::config::MultiplexConfig;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    struct RecordingTracer {
+        context: &'static [u8],
+        spans: parking_lot::Mutex<Vec<StreamSpan>>
+    }
+
+    impl RecordingTracer {
+        fn new(context: &'static [u8]) -> Arc<Self> {
+            Arc::new(Self { context, spans: parking_lot::Mutex::new(Vec::new()) })
+        }
+
+        fn span(&self, operation: StreamOperation, id: SymmetricConvID) -> Option<StreamSpan> {
+            self.spans.lock().iter().find(|span| span.operation == operation && span.stream_id == format!("{:?}", id)).cloned()
+        }
+    }
+
+    impl StreamTracer for RecordingTracer {
+        fn inject(&self) -> Option<Vec<u8>> {
+            Some(self.context.to_vec())
+        }
+
+        fn record(&self, span: StreamSpan) {
+            self.spans.lock().push(span)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn trace_context_propagation() {
+        let (server_tracer, client_tracer) = (RecordingTracer::new(b"server-parent"), RecordingTracer::new(b"client-parent"));
+        let (server_conn, client_conn) = channel_pair();
+        let (server, client) = tokio::join!(
+            NetworkApplication::register_with_config(RelativeNodeType::Receiver, server_conn, MultiplexConfig::new().with_tracer(server_tracer.clone())),
+            NetworkApplication::register_with_config(RelativeNodeType::Initiator, client_conn, MultiplexConfig::new().with_tracer(client_tracer.clone()))
+        );
+
+        let (server, client) = (server.unwrap(), client.unwrap());
+        let _ = tokio::join!(server.peer_capabilities(), client.peer_capabilities());
+
+        // the next open requires a handshake, which carries the trace context
+        let mut held = drain_prereserved(&server);
+        held.extend(drain_prereserved(&client));
+
+        let (server_sub, client_sub) = open_pair(&server, &client).await;
+        let id = server_sub.id();
+
+        assert_eq!(client_tracer.span(StreamOperation::Open, id).unwrap().remote_parent.as_deref(), Some(&b"server-parent"[..]));
+        assert_eq!(server_tracer.span(StreamOperation::Open, id).unwrap().remote_parent.as_deref(), Some(&b"client-parent"[..]));
+
+        // the receiving node's span links to the sending node's context
+        server_sub.send_to_peer(b"traced").await.unwrap();
+        assert_eq!(&client_sub.recv().await.unwrap()[..], b"traced");
+        assert!(server_tracer.span(StreamOperation::Send, id).is_some());
+        assert_eq!(client_tracer.span(StreamOperation::Recv, id).unwrap().remote_parent.as_deref(), Some(&b"server-parent"[..]));
+
+        drop((server_sub, client_sub));
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert!(server_tracer.span(StreamOperation::Close, id).is_some() && client_tracer.span(StreamOperation::Close, id).is_some());
+
+        // every build decodes the extended open signal
+        let packet = bincode2::serialize(&MultiplexedPacket::PreCreateTraced { id, trace_context: b"ctx".to_vec() }).unwrap();
+        assert!(matches!(bincode2::deserialize(&packet).unwrap(), MultiplexedPacket::<SymmetricConvID>::PreCreateTraced { trace_context, .. } if trace_context == b"ctx"));
+    }
+}
@@ -1,5 +1,7 @@
 use std::fmt::Formatter;
 
+/// Provides wall-clock timestamps that are meaningful across both nodes. Purely local delays and durations
+/// should use `tokio::time` instead so that they remain controllable via `tokio::time::pause()` in tests
 #[derive(Copy, Clone)]
 pub struct TimeTracker;
 
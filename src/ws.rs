@@ -0,0 +1,141 @@
+use std::net::SocketAddr;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use futures::stream::{SplitSink, SplitStream};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::{Message, Error as WsError};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+use crate::reliable_conn::{ReliableOrderedStreamToTarget, ConnAddr};
+
+/// A transport over a WebSocket, for paths that only permit WebSocket traffic, such as some proxies. Each message is
+/// sent as one binary WebSocket message. Text messages are rejected, and control messages are handled by the
+/// WebSocket itself. As with [`crate::reliable_conn::TcpConn`], an empty message reads the same as the peer closing
+/// the connection
+pub struct WebSocketConn {
+    sink: Mutex<SplitSink<WebSocketStream<TcpStream>, Message>>,
+    stream: Mutex<SplitStream<WebSocketStream<TcpStream>>>,
+    local_addr: SocketAddr,
+    peer_addr: SocketAddr
+}
+
+impl WebSocketConn {
+    /// Wraps a WebSocket that has already completed its handshake
+    pub fn new(ws: WebSocketStream<TcpStream>) -> std::io::Result<Self> {
+        let (local_addr, peer_addr) = (ws.get_ref().local_addr()?, ws.get_ref().peer_addr()?);
+        let (sink, stream) = ws.split();
+        Ok(Self { sink: Mutex::new(sink), stream: Mutex::new(stream), local_addr, peer_addr })
+    }
+
+    /// Performs the server half of the WebSocket handshake on an accepted connection
+    pub async fn accept(stream: TcpStream) -> std::io::Result<Self> {
+        Self::new(tokio_tungstenite::accept_async(stream).await.map_err(into_io_error)?)
+    }
+
+    /// Performs the client half of the WebSocket handshake for `request`, typically a `ws://` url, over `stream`
+    pub async fn connect<R: IntoClientRequest + Unpin>(request: R, stream: TcpStream) -> std::io::Result<Self> {
+        let (ws, _response) = tokio_tungstenite::client_async(request, stream).await.map_err(into_io_error)?;
+        Self::new(ws)
+    }
+}
+
+fn into_io_error(err: WsError) -> std::io::Error {
+    match err {
+        WsError::Io(err) => err,
+        err => std::io::Error::other(err)
+    }
+}
+
+#[async_trait]
+impl ReliableOrderedStreamToTarget for WebSocketConn {
+    async fn send_to_peer(&self, input: &[u8]) -> std::io::Result<()> {
+        self.sink.lock().await.send(Message::Binary(input.to_vec())).await.map_err(into_io_error)
+    }
+
+    /// Returns an empty message once the peer closes the connection
+    async fn recv(&self) -> std::io::Result<Bytes> {
+        let mut stream = self.stream.lock().await;
+        loop {
+            match stream.next().await {
+                Some(Ok(Message::Binary(payload))) => return Ok(Bytes::from(payload)),
+                Some(Ok(Message::Text(_))) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Received a text message on a binary WebSocket transport")),
+                // pings are answered by the WebSocket itself
+                Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => {}
+                Some(Ok(Message::Close(_))) | Some(Err(WsError::ConnectionClosed)) | None => return Ok(Bytes::new()),
+                Some(Err(err)) => return Err(into_io_error(err))
+            }
+        }
+    }
+
+    /// Sends a WebSocket close message
+    async fn shutdown(&self) -> std::io::Result<()> {
+        self.sink.lock().await.close().await.map_err(into_io_error)
+    }
+}
+
+impl ConnAddr for WebSocketConn {
+    fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        Ok(self.peer_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ws::WebSocketConn;
+    use crate::reliable_conn::{ReliableOrderedStreamToTarget, ReliableOrderedStreamToTargetExt, ConnAddr};
+    use crate::sync::network_application::NetworkApplication;
+    use crate::sync::subscription::SubscriptionBiStreamExt;
+    use crate::sync::{RelativeNodeType, SymmetricConvID};
+    use crate::sync::test_utils::open_pair;
+    use futures::SinkExt;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::tungstenite::Message;
+
+    async fn ws_pair() -> (WebSocketConn, WebSocketConn) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = async { WebSocketConn::accept(listener.accept().await.unwrap().0).await.unwrap() };
+        let client = async { WebSocketConn::connect(format!("ws://{}/", addr), TcpStream::connect(addr).await.unwrap()).await.unwrap() };
+        tokio::join!(server, client)
+    }
+
+    #[tokio::test]
+    async fn websocket_transport() {
+        let (server, client) = ws_pair().await;
+        assert_eq!(server.local_addr().unwrap(), client.peer_addr().unwrap());
+
+        client.send_to_peer(b"binary").await.unwrap();
+        assert_eq!(&server.recv().await.unwrap()[..], b"binary");
+
+        client.sink.lock().await.send(Message::Text("text".to_string())).await.unwrap();
+        assert_eq!(server.recv().await.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+        client.shutdown().await.unwrap();
+        assert!(server.recv().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn nested_multiplex_over_websocket() {
+        let (server, client) = ws_pair().await;
+        let (server, client) = tokio::join!(NetworkApplication::register(RelativeNodeType::Receiver, server), NetworkApplication::register(RelativeNodeType::Initiator, client));
+        let (mut server, mut client) = (server.unwrap(), client.unwrap());
+        // every level stays alive while a level nested on top of it does, so the outer levels need not be held
+        for level in 0..5u64 {
+            let (server_sub, client_sub) = open_pair(&server, &client).await;
+
+            server_sub.send_serialized(level).await.unwrap();
+            assert_eq!(client_sub.recv_serialized::<u64>().await.unwrap(), level);
+
+            let (next_server, next_client) = tokio::join!(server_sub.multiplex::<SymmetricConvID>(), client_sub.multiplex::<SymmetricConvID>());
+            server = next_server.unwrap();
+            client = next_client.unwrap();
+        }
+    }
+}